@@ -3,5 +3,14 @@ pub static IRCD_VERSION: &str = "0.1.0";
 pub static IRCD_REPOSITORY: &str = "https://github.com/diath/ayame";
 pub static IRCD_CONFIG: &str = "config.toml";
 pub static IRCD_MOTD: &str = "motd.txt";
-pub static IRCD_USER_MODES: &str = "oOx";
+pub static IRCD_DATA: &str = "data";
+pub static IRCD_USER_MODES: &str = "oOSx";
 pub static IRCD_CHANNEL_MODES: &str = "qaohvmiklnst";
+pub static IRCD_CAPABILITIES: &[&str] = &[
+    "batch",
+    "chghost",
+    "echo-message",
+    "labeled-response",
+    "message-tags",
+    "server-time",
+];
@@ -8,15 +8,18 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use log;
 
+use serde::{Deserialize, Serialize};
+
 use tokio::sync::{Mutex, RwLock};
 
-#[derive(Default)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct ChannelTopic {
     pub text: String,
     pub set_by: String,
     pub set_at: u64,
 }
 
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct ChannelModes {
     pub moderated: bool,
     pub invite_only: bool,
@@ -27,6 +30,7 @@ pub struct ChannelModes {
     pub restrict_topic: bool,
 }
 
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct ChannelUserModes {
     pub owner: bool,
     pub admin: bool,
@@ -35,8 +39,23 @@ pub struct ChannelUserModes {
     pub voiced: bool,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct ChannelState {
+    pub name: String,
+    #[serde(default = "now")]
+    pub created_at: i64,
+    pub topic: ChannelTopic,
+    pub modes: ChannelModes,
+    pub participants: HashMap<String, ChannelUserModes>,
+    pub invites: HashSet<String>,
+    pub invite_exceptions: HashSet<String>,
+    pub bans: HashSet<String>,
+    pub ban_exceptions: HashSet<String>,
+}
+
 pub struct Channel {
     pub name: String,
+    pub created_at: RwLock<i64>,
     pub topic: Mutex<ChannelTopic>,
     pub modes: Mutex<ChannelModes>,
     pub participants: RwLock<HashMap<String, ChannelUserModes>>,
@@ -98,12 +117,50 @@ impl ChannelUserModes {
 
         return "";
     }
+
+    /* NOTE(diath): Every status symbol rather than just the highest one, as sent to linked servers. */
+    pub fn get_prefixes(&self) -> String {
+        let mut prefixes = String::new();
+        for (flag, symbol) in [
+            (self.owner, '~'),
+            (self.admin, '&'),
+            (self.operator, '@'),
+            (self.half_operator, '%'),
+            (self.voiced, '+'),
+        ]
+        .iter()
+        {
+            if *flag {
+                prefixes.push(*symbol);
+            }
+        }
+
+        prefixes
+    }
+
+    pub fn from_prefixes(prefixes: &str) -> ChannelUserModes {
+        ChannelUserModes {
+            owner: prefixes.contains('~'),
+            admin: prefixes.contains('&'),
+            operator: prefixes.contains('@'),
+            half_operator: prefixes.contains('%'),
+            voiced: prefixes.contains('+'),
+        }
+    }
+}
+
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_) => 0,
+    }
 }
 
 impl Channel {
     pub fn new(name: String) -> Channel {
         Channel {
             name: name,
+            created_at: RwLock::new(now()),
             topic: Mutex::new(ChannelTopic {
                 ..Default::default()
             }),
@@ -124,6 +181,34 @@ impl Channel {
         }
     }
 
+    pub async fn get_state(&self) -> ChannelState {
+        ChannelState {
+            name: self.name.to_string(),
+            created_at: *self.created_at.read().await,
+            topic: self.topic.lock().await.clone(),
+            modes: self.modes.lock().await.clone(),
+            participants: self.participants.read().await.clone(),
+            invites: self.invites.lock().await.clone(),
+            invite_exceptions: self.invite_exceptions.lock().await.clone(),
+            bans: self.bans.lock().await.clone(),
+            ban_exceptions: self.ban_exceptions.lock().await.clone(),
+        }
+    }
+
+    pub fn from_state(state: ChannelState) -> Channel {
+        Channel {
+            name: state.name,
+            created_at: RwLock::new(state.created_at),
+            topic: Mutex::new(state.topic),
+            modes: Mutex::new(state.modes),
+            participants: RwLock::new(state.participants),
+            invites: Mutex::new(state.invites),
+            invite_exceptions: Mutex::new(state.invite_exceptions),
+            bans: Mutex::new(state.bans),
+            ban_exceptions: Mutex::new(state.ban_exceptions),
+        }
+    }
+
     pub async fn has_participant(&self, name: &str) -> bool {
         self.participants.read().await.contains_key(name)
     }
@@ -176,6 +261,13 @@ impl Channel {
         self.participants.write().await.remove(&name);
     }
 
+    pub async fn rename(&self, old_name: &str, name: &str) {
+        let mut participants = self.participants.write().await;
+        if let Some(modes) = participants.remove(old_name) {
+            participants.insert(name.to_string(), modes);
+        }
+    }
+
     pub async fn set_topic(&self, sender: String, text: String) {
         let mut topic = self.topic.lock().await;
         topic.text = text;
@@ -188,6 +280,84 @@ impl Channel {
         }
     }
 
+    /* NOTE(diath): Drops every mode, list entry and status, used when a linked server has an older copy of the channel. Returns the change string. */
+    pub async fn clear_modes(&self) -> String {
+        let mut changes = "-".to_string();
+        let mut changes_params = vec![];
+
+        {
+            let mut modes = self.modes.lock().await;
+            for (flag, letter) in [
+                (modes.moderated, 'm'),
+                (modes.invite_only, 'i'),
+                (!modes.password.is_empty(), 'k'),
+                (modes.limit != 0, 'l'),
+                (modes.no_external_messages, 'n'),
+                (modes.secret, 's'),
+                (modes.restrict_topic, 't'),
+            ]
+            .iter()
+            {
+                if *flag {
+                    changes.push(*letter);
+                }
+            }
+
+            if !modes.password.is_empty() {
+                changes_params.push(modes.password.clone());
+            }
+
+            *modes = ChannelModes {
+                ..Default::default()
+            };
+        }
+
+        for (list, letter) in [
+            (&self.bans, 'b'),
+            (&self.ban_exceptions, 'e'),
+            (&self.invite_exceptions, 'I'),
+        ]
+        .iter()
+        {
+            for mask in list.lock().await.drain() {
+                changes.push(*letter);
+                changes_params.push(mask);
+            }
+        }
+
+        for (nick, modes) in self.participants.write().await.iter_mut() {
+            for (flag, letter) in [
+                (modes.owner, 'q'),
+                (modes.admin, 'a'),
+                (modes.operator, 'o'),
+                (modes.half_operator, 'h'),
+                (modes.voiced, 'v'),
+            ]
+            .iter()
+            {
+                if *flag {
+                    changes.push(*letter);
+                    changes_params.push(nick.to_string());
+                }
+            }
+
+            *modes = ChannelUserModes {
+                ..Default::default()
+            };
+        }
+
+        if changes.len() == 1 {
+            return String::new();
+        }
+
+        if !changes_params.is_empty() {
+            changes.push(' ');
+        }
+
+        changes.push_str(&changes_params.join(" "));
+        changes
+    }
+
     pub async fn get_modes_description(&self, with_params: bool) -> String {
         let mut desc = "+".to_string();
         let modes = self.modes.lock().await;
@@ -242,7 +412,8 @@ impl Channel {
         }
 
         let nick = client.nick.lock().await.to_string();
-        let oper = *client.operator.lock().await;
+        /* NOTE(diath): Changes made on a linked server have already been checked there. */
+        let oper = *client.operator.lock().await || client.is_remote();
 
         let mut modes = self.modes.lock().await;
 
@@ -547,7 +718,7 @@ impl Channel {
                 'q' | 'a' | 'o' | 'h' | 'v' => {
                     if let Some(param) = params.get(index) {
                         let nick = client.nick.lock().await.to_string();
-                        let oper = *client.operator.lock().await;
+                        let oper = *client.operator.lock().await || client.is_remote();
                         if oper || self.can_toggle_user_mode(&nick, ch, flag).await {
                             if self.toggle_user_mode(&param, ch, flag).await {
                                 changes.push(ch);
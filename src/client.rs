@@ -1,50 +1,187 @@
 use crate::ayame::*;
-use crate::cloak::get_cloaked_host;
+use crate::dnsbl::{DnsblAction, DnsblListing};
+use crate::ident::Ident;
+use crate::link;
+use crate::module::Dispatch;
 use crate::replies::NumericReply;
-use crate::server::Server;
+use crate::server::{MessageKind, Server, Shutdown};
+use crate::tags::{
+    format_tags, generate_id, parse_tags, prepend_tag, split_tags, MessageTags, Tag,
+    MAX_CLIENT_TAGS_LENGTH, MAX_LABEL_LENGTH,
+};
 
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::fmt::Write;
-use std::io::ErrorKind;
-use std::net::SocketAddr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::Poll;
 use std::time::SystemTime;
 
 use chrono::prelude::DateTime;
 use chrono::Utc;
 
+use futures::future;
+
 use log;
 
-use tokio::io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf};
+use serde::{Deserialize, Serialize};
+
+use tokio::io::{split, AsyncBufRead, AsyncWriteExt, BufReader, WriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
 use tokio::time::{delay_until, Duration, Instant};
 
 use ircmsgprs::parser::{Message, Parser};
 
+#[derive(Clone, Deserialize, Serialize)]
 pub enum UserHost {
     IPv4(String),
     IPv6(String),
+    Hostname(String),
     VHost(String),
 }
 
+/* NOTE(diath): Where a user introduced by a linked server lives, and the link it was introduced through. */
+#[derive(Clone)]
+pub struct RemoteOrigin {
+    pub server: String,
+    pub link: String,
+}
+
+/* NOTE(diath): The lines sent to a client while its task handles a labeled command, they are sent as one labeled response once the command is done. Lines sent to the client by other tasks in the meantime are not part of the response. */
+struct LabeledResponse {
+    client: usize,
+    lines: RefCell<Vec<String>>,
+}
+
+tokio::task_local! {
+    static RESPONSE: LabeledResponse;
+}
+
+/* NOTE(diath): The part of a registered client that is carried over an UPGRADE. */
+#[derive(Deserialize, Serialize)]
+pub struct ClientState {
+    pub fd: RawFd,
+    pub address: SocketAddr,
+    pub nick: String,
+    #[serde(default)]
+    pub nick_ts: i64,
+    pub user: String,
+    pub vident: Option<String>,
+    pub hostname: Option<String>,
+    pub ident: Option<String>,
+    pub host: UserHost,
+    pub real_name: String,
+    pub operator: bool,
+    #[serde(default)]
+    pub oper_name: Option<String>,
+    pub privileges: HashSet<String>,
+    pub channels: HashSet<String>,
+    pub away_message: String,
+    pub last_activity: i64,
+    pub account: Option<String>,
+    pub capabilities: HashSet<String>,
+}
+
 pub struct Client {
     pub nick: Mutex<String>,
+    pub nick_ts: RwLock<i64>,
     pub user: Mutex<String>,
+    pub vident: Mutex<Option<String>>,
+    pub hostname: Mutex<Option<String>>,
+    pub ident: Mutex<Option<String>>,
+    pub dnsbl_listing: Mutex<Option<DnsblListing>>,
     pub host: Mutex<UserHost>,
     pub real_name: Mutex<String>,
     pub password: Mutex<String>,
     pub registered: RwLock<bool>,
     pub operator: Mutex<bool>,
+    /* NOTE(diath): The name of the oper block the client used to become an operator. */
+    pub oper_name: Mutex<Option<String>>,
+    pub privileges: Mutex<HashSet<String>>,
     pub channels: Mutex<HashSet<String>>,
     pub away_message: Mutex<String>,
     pub last_activity: RwLock<i64>,
-    pub identified: Mutex<bool>,
+    pub account: Mutex<Option<String>>,
+    pub capabilities: RwLock<HashSet<String>>,
     pub address: SocketAddr,
-    server: Arc<Server>,
+    pub server: Arc<Server>,
+    pub remote: Option<RemoteOrigin>,
+    pub service: bool,
+    fd: Mutex<Option<RawFd>>,
     writer: Mutex<Option<WriteHalf<TcpStream>>>,
     parser: Mutex<Parser>,
+    /* NOTE(diath): The client-only tags of the message that is being handled. */
+    tags: Mutex<Vec<Tag>>,
+    /* NOTE(diath): The raw text of the message that is being handled, without the tags. */
+    line: Mutex<String>,
+    /* NOTE(diath): The targets the PRIVMSG that is being handled was delivered to. */
+    delivered: Mutex<Vec<String>>,
     received_pong: RwLock<bool>,
+    cap_negotiating: RwLock<bool>,
+    lookups_pending: RwLock<bool>,
+    link_request: Mutex<Option<(String, String)>>,
+    pub quit_reason: Mutex<Option<String>>,
+    quit: Mutex<Option<oneshot::Sender<()>>>,
+    pause: Mutex<Option<mpsc::UnboundedSender<PauseRequest>>>,
+}
+
+/* NOTE(diath): Answered with whether the client had unprocessed input, the reader stays paused until the second half is dropped. */
+type PauseRequest = (oneshot::Sender<bool>, oneshot::Receiver<()>);
+
+/* NOTE(diath): Skips the prefix and the command of a raw message. */
+fn get_raw_params(line: &str) -> String {
+    let mut rest = line
+        .trim_end_matches(&['\r', '\n'][..])
+        .trim_start_matches(' ');
+    if rest.starts_with(':') {
+        rest = rest
+            .split_once(' ')
+            .map(|(_, rest)| rest)
+            .unwrap_or_default();
+    }
+
+    let rest = rest
+        .trim_start_matches(' ')
+        .split_once(' ')
+        .map(|(_, rest)| rest.trim_start_matches(' '))
+        .unwrap_or_default();
+    rest.strip_prefix(':').unwrap_or(rest).to_string()
+}
+
+/* NOTE(diath): Unlike AsyncBufReadExt::read_line this can be cancelled without losing data, a partial line stays in the buffer until the next call completes it. Returns the length of the line, or 0 at EOF. */
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+) -> io::Result<usize> {
+    future::poll_fn(|context| loop {
+        let mut reader = Pin::new(&mut *reader);
+        let available = match reader.as_mut().poll_fill_buf(context) {
+            Poll::Ready(Ok(available)) => available,
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if available.is_empty() {
+            return Poll::Ready(Ok(line.len()));
+        }
+
+        let (used, done) = match available.iter().position(|byte| *byte == b'\n') {
+            Some(index) => (index + 1, true),
+            None => (available.len(), false),
+        };
+        line.extend_from_slice(&available[..used]);
+        reader.consume(used);
+
+        if done {
+            return Poll::Ready(Ok(line.len()));
+        }
+    })
+    .await
 }
 
 impl Client {
@@ -53,88 +190,383 @@ impl Client {
             SocketAddr::V4(addr) => UserHost::IPv4(addr.ip().to_string()),
             SocketAddr::V6(addr) => UserHost::IPv6(addr.ip().to_string()),
         };
+        let host = server.cloak.get_cloaked_host(host);
 
         Client {
             nick: Mutex::new(String::new()),
+            nick_ts: RwLock::new(0),
             user: Mutex::new(String::new()),
-            host: Mutex::new(UserHost::VHost(get_cloaked_host(host))),
+            vident: Mutex::new(None),
+            hostname: Mutex::new(None),
+            ident: Mutex::new(None),
+            dnsbl_listing: Mutex::new(None),
+            host: Mutex::new(UserHost::VHost(host)),
             real_name: Mutex::new(String::new()),
             password: Mutex::new(String::new()),
             registered: RwLock::new(false),
             operator: Mutex::new(false),
+            oper_name: Mutex::new(None),
+            privileges: Mutex::new(HashSet::new()),
             channels: Mutex::new(HashSet::new()),
             away_message: Mutex::new(String::new()),
             last_activity: RwLock::new(0),
-            identified: Mutex::new(false),
+            account: Mutex::new(None),
+            capabilities: RwLock::new(HashSet::new()),
             address: address,
             server: server,
+            remote: None,
+            service: false,
+            fd: Mutex::new(None),
             writer: Mutex::new(None),
             parser: Mutex::new(Parser::new()),
+            tags: Mutex::new(vec![]),
+            line: Mutex::new(String::new()),
+            delivered: Mutex::new(vec![]),
             received_pong: RwLock::new(true),
+            cap_negotiating: RwLock::new(false),
+            lookups_pending: RwLock::new(true),
+            link_request: Mutex::new(None),
+            quit_reason: Mutex::new(None),
+            quit: Mutex::new(None),
+            pause: Mutex::new(None),
         }
     }
 
+    /* NOTE(diath): A user introduced by a linked server, it has no connection of its own so anything sent to it is dropped. */
+    pub fn new_remote(server: Arc<Server>, address: SocketAddr, origin: RemoteOrigin) -> Client {
+        let mut client = Client::new(server, address);
+        client.registered = RwLock::new(true);
+        client.lookups_pending = RwLock::new(false);
+        client.remote = Some(origin);
+        client
+    }
+
+    /* NOTE(diath): The pseudo-client a service talks through, messages to it are handed to the service instead. */
+    pub fn new_service(server: Arc<Server>, nick: &str) -> Client {
+        let host = format!("services.{}", server.name);
+        let mut client = Client::new(
+            server,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        );
+        client.nick = Mutex::new(nick.to_string());
+        client.user = Mutex::new("services".to_string());
+        client.host = Mutex::new(UserHost::VHost(host));
+        client.real_name = Mutex::new(format!("{} Service", nick));
+        client.registered = RwLock::new(true);
+        client.lookups_pending = RwLock::new(false);
+        client.last_activity = RwLock::new(Utc::now().timestamp());
+        client.service = true;
+        client
+    }
+
+    pub fn is_service(&self) -> bool {
+        self.service
+    }
+
+    pub fn is_remote(&self) -> bool {
+        self.remote.is_some()
+    }
+
+    pub fn get_server_name(&self) -> String {
+        match &self.remote {
+            Some(remote) => remote.server.to_string(),
+            None => self.server.name.to_string(),
+        }
+    }
+
+    /* NOTE(diath): Introduces the user to a linked server, see link.rs for the format. */
+    pub async fn get_uid_line(&self) -> String {
+        let real_host = match self.get_real_host().await {
+            UserHost::IPv4(host) => host,
+            UserHost::IPv6(host) => host,
+            UserHost::Hostname(host) => host,
+            UserHost::VHost(host) => host,
+        };
+
+        format!(
+            ":{} UID {} {} {} {} {} {} {} {} :{}",
+            self.get_server_name(),
+            self.nick.lock().await,
+            *self.nick_ts.read().await,
+            self.get_user().await,
+            self.get_host().await,
+            real_host,
+            self.address.ip(),
+            self.get_modes_description().await,
+            self.account.lock().await.as_deref().unwrap_or("*"),
+            self.real_name.lock().await
+        )
+    }
+
+    /* NOTE(diath): Only registered clients with a live connection can be handed over. */
+    pub async fn get_state(&self) -> Option<ClientState> {
+        if !*self.registered.read().await || self.writer.lock().await.is_none() {
+            return None;
+        }
+
+        Some(ClientState {
+            fd: (*self.fd.lock().await)?,
+            address: self.address,
+            nick: self.nick.lock().await.to_string(),
+            nick_ts: *self.nick_ts.read().await,
+            user: self.user.lock().await.to_string(),
+            vident: self.vident.lock().await.clone(),
+            hostname: self.hostname.lock().await.clone(),
+            ident: self.ident.lock().await.clone(),
+            host: self.host.lock().await.clone(),
+            real_name: self.real_name.lock().await.to_string(),
+            operator: *self.operator.lock().await,
+            oper_name: self.oper_name.lock().await.clone(),
+            privileges: self.privileges.lock().await.clone(),
+            channels: self.channels.lock().await.clone(),
+            away_message: self.away_message.lock().await.to_string(),
+            last_activity: *self.last_activity.read().await,
+            account: self.account.lock().await.clone(),
+            capabilities: self.capabilities.read().await.clone(),
+        })
+    }
+
+    pub fn from_state(server: Arc<Server>, state: ClientState) -> Client {
+        let mut client = Client::new(server, state.address);
+        client.nick = Mutex::new(state.nick);
+        client.nick_ts = RwLock::new(state.nick_ts);
+        client.user = Mutex::new(state.user);
+        client.vident = Mutex::new(state.vident);
+        client.hostname = Mutex::new(state.hostname);
+        client.ident = Mutex::new(state.ident);
+        client.host = Mutex::new(state.host);
+        client.real_name = Mutex::new(state.real_name);
+        client.registered = RwLock::new(true);
+        client.operator = Mutex::new(state.operator);
+        client.oper_name = Mutex::new(state.oper_name);
+        client.privileges = Mutex::new(state.privileges);
+        client.channels = Mutex::new(state.channels);
+        client.away_message = Mutex::new(state.away_message);
+        client.last_activity = RwLock::new(state.last_activity);
+        client.account = Mutex::new(state.account);
+        client.capabilities = RwLock::new(state.capabilities);
+        client.lookups_pending = RwLock::new(false);
+        client
+    }
+
     pub async fn get_prefix(&self) -> String {
         return format!(
             "{}!{}@{}",
             self.nick.lock().await.to_string(),
-            self.user.lock().await.to_string(),
+            self.get_user().await,
             self.get_host().await
         );
     }
 
+    /* NOTE(diath): The ident shown to other users, a vhost may override the one sent with USER. */
+    pub async fn get_user(&self) -> String {
+        match &*self.vident.lock().await {
+            Some(ident) => ident.to_string(),
+            None => self.user.lock().await.to_string(),
+        }
+    }
+
     pub async fn get_host(&self) -> String {
         match &*self.host.lock().await {
             UserHost::IPv4(host) => host.to_string(),
             UserHost::IPv6(host) => host.to_string(),
+            UserHost::Hostname(host) => host.to_string(),
             UserHost::VHost(host) => host.to_string(),
         }
     }
 
+    /* NOTE(diath): The resolved hostname if there is one, the IP address otherwise. */
+    pub async fn get_real_host(&self) -> UserHost {
+        if let Some(hostname) = &*self.hostname.lock().await {
+            return UserHost::Hostname(hostname.to_string());
+        }
+
+        match self.address {
+            SocketAddr::V4(addr) => UserHost::IPv4(addr.ip().to_string()),
+            SocketAddr::V6(addr) => UserHost::IPv6(addr.ip().to_string()),
+        }
+    }
+
+    /* NOTE(diath): The hosts that server bans are matched against, the IP address and the resolved hostname if there is one. */
+    pub async fn get_ban_hosts(&self) -> Vec<String> {
+        let mut hosts = vec![self.address.ip().to_string()];
+        if let Some(hostname) = &*self.hostname.lock().await {
+            hosts.push(hostname.to_string());
+        }
+
+        hosts
+    }
+
+    /* NOTE(diath): Besides the displayed prefix, includes the prefixes with the host cloaked by every older cloak key. */
+    pub async fn get_ban_prefixes(&self) -> Vec<String> {
+        let prefix = self.get_prefix().await;
+        let host = self.get_host().await;
+        let cloaks = self
+            .server
+            .cloak
+            .get_cloaked_hosts(&self.get_real_host().await);
+        if !cloaks.contains(&host) {
+            return vec![prefix];
+        }
+
+        let nick = self.nick.lock().await.to_string();
+        let user = self.get_user().await;
+        let mut prefixes = vec![prefix];
+        for cloak in cloaks {
+            if cloak != host {
+                prefixes.push(format!("{}!{}@{}", nick, user, cloak));
+            }
+        }
+
+        prefixes
+    }
+
     pub async fn task(&self, stream: TcpStream) {
+        let local_address = stream.local_addr().ok();
+        (*self.fd.lock().await) = Some(stream.as_raw_fd());
         let (reader, writer) = split(stream);
-        let mut line = String::new();
+        let mut line = vec![];
         let mut buf_reader = BufReader::new(reader);
 
+        let (quit_sender, mut quit_receiver) = oneshot::channel();
+        let (pause_sender, mut pause_receiver) = mpsc::unbounded_channel();
         (*self.writer.lock().await) = Some(writer);
+        (*self.quit.lock().await) = Some(quit_sender);
+        (*self.pause.lock().await) = Some(pause_sender);
 
-        loop {
-            match buf_reader.read_line(&mut line).await {
-                Ok(size) => {
-                    if size == 0 {
-                        self.server.broadcast_quit(&self, "EOF").await;
-                        break;
-                    } else {
-                        (*self.server.recv_packets.write().await) += 1;
-                        (*self.server.recv_bytes.write().await) += line.len() as u64;
+        /* NOTE(diath): The lookups run alongside the read loop instead of being a branch of it, read_line is not cancel-safe and dropping it would lose a partially read line. */
+        let lookups = async {
+            if *self.lookups_pending.read().await {
+                self.perform_lookups(local_address).await;
+            }
+
+            if self.is_login_required().await {
+                delay_until(Instant::now() + Duration::from_millis(60 * 1000)).await;
+                if self.is_login_required().await {
+                    let reason = match &*self.dnsbl_listing.lock().await {
+                        Some(listing) => listing.reason.to_string(),
+                        None => String::new(),
+                    };
+                    self.kill(&format!("{} (login required)", reason)).await;
+                }
+            }
+            future::pending::<()>().await;
+        };
 
-                        let result = self.parser.lock().await.parse(line.clone());
-                        if result.is_none() {
-                            log::debug!("Client parse error.");
-                            break;
+        let read_loop = async {
+            loop {
+                tokio::select! {
+                    result = read_line(&mut buf_reader, &mut line) => {
+                        match result {
+                            Ok(size) => {
+                                if size == 0 {
+                                    self.server.broadcast_quit(&self, "EOF").await;
+                                    break;
+                                } else if let Ok(line) = std::str::from_utf8(&line) {
+                                    (*self.server.recv_packets.write().await) += 1;
+                                    (*self.server.recv_bytes.write().await) += line.len() as u64;
+
+                                    let (tags, rest) = split_tags(line);
+                                    let handled = match self.get_label(tags).await {
+                                        Some(label) => self.handle_labeled(&label, tags, rest).await,
+                                        None => self.handle_line(tags, rest).await,
+                                    };
+
+                                    if !handled {
+                                        log::debug!("Client parse error.");
+                                        break;
+                                    }
+
+                                    if self.link_request.lock().await.is_some() {
+                                        break;
+                                    }
+                                }
+                            }
+                            Err(err) => {
+                                self.server.broadcast_quit(&self, "Read Error").await;
+                                log::debug!("Client read error ({}).", err);
+                                break;
+                            }
                         }
-                        self.on_message(result.unwrap()).await;
                     }
-                }
-                Err(err) => {
-                    if err.kind() != ErrorKind::InvalidData {
-                        self.server.broadcast_quit(&self, "Read Error").await;
-                        log::debug!("Client read error ({}).", err);
+                    request = pause_receiver.recv() => {
+                        if let Some((reply, resume)) = request {
+                            reply.send(!line.is_empty() || !buf_reader.buffer().is_empty()).ok();
+                            resume.await.ok();
+                        }
+                        continue;
+                    }
+                    _ = &mut quit_receiver => {
                         break;
                     }
                 }
+
+                line.clear();
             }
+        };
+
+        tokio::select! {
+            _ = lookups => {}
+            _ = read_loop => {}
+        }
 
-            line.clear();
+        /* NOTE(diath): A server that has authenticated takes the connection over, it is no longer a client. */
+        let link_request = self.link_request.lock().await.take();
+        if let Some((name, description)) = link_request {
+            self.server.unmap_client(self).await;
+
+            let writer = self.writer.lock().await.take();
+            if let Some(writer) = writer {
+                link::accept(
+                    self.server.clone(),
+                    name,
+                    description,
+                    self.address,
+                    buf_reader,
+                    writer,
+                )
+                .await;
+            }
+            return;
         }
 
+        self.cleanup().await;
+
+        log::debug!("Client disconnected ({}).", self.address);
+    }
+
+    async fn cleanup(&self) {
+        /* NOTE(diath): The nick is cleared below, so the services only hear about the first cleanup. */
+        if *self.registered.read().await && !self.nick.lock().await.is_empty() {
+            let reason = self
+                .quit_reason
+                .lock()
+                .await
+                .clone()
+                .unwrap_or_else(|| "Connection closed".to_string());
+            for service in self.server.get_services() {
+                service.on_quit(self, &reason).await;
+            }
+        }
+
+        self.account.lock().await.take();
+
         self.server.remove_from_channels(&self).await;
+        self.channels.lock().await.clear();
 
+        /* NOTE(diath): The nick is cleared so a repeated cleanup (such as a killed client task exiting) does not touch a new owner of the nick. */
         let nick = self.nick.lock().await.to_string();
+        self.nick.lock().await.clear();
         if nick.len() != 0 {
             self.server.remove_operator(&nick).await;
             self.server.unmap_nick(nick.to_string()).await;
 
+            if *self.registered.read().await && self.quit_reason.lock().await.is_none() {
+                self.server
+                    .propagate(self, format!(":{} QUIT :Connection closed", nick))
+                    .await;
+            }
+
             self.server
                 .broadcast_oper_notice(format!(
                     "Client exiting: {} ({})",
@@ -145,8 +577,6 @@ impl Client {
         }
 
         self.server.unmap_client(&self).await;
-
-        log::debug!("Client disconnected ({}).", self.address);
     }
 
     pub async fn task_ping(&self) {
@@ -168,10 +598,329 @@ impl Client {
             }
 
             delay_until(Instant::now() + Duration::from_millis(30 * 1000)).await;
+
+            /* NOTE(diath): The connection has been closed or handed over to a server link. */
+            if self.writer.lock().await.is_none() {
+                break;
+            }
+        }
+    }
+
+    async fn send_auth_notice(&self, message: &str) {
+        self.send_raw(format!(":{} NOTICE * :*** {}", self.server.name, message))
+            .await;
+    }
+
+    /* NOTE(diath): Runs alongside the message loop, registration is held back until it finishes. */
+    async fn perform_lookups(&self, local_address: Option<SocketAddr>) {
+        let hostname = async {
+            if self.server.dns_lookups {
+                self.lookup_hostname().await;
+            }
+        };
+        let ident = async {
+            if let (Some(ident), Some(local_address)) = (&self.server.ident, local_address) {
+                self.lookup_ident(ident, local_address).await;
+            }
+        };
+        let dnsbl = async {
+            if self.server.dnsbl.is_enabled() {
+                self.check_dnsbl().await
+            } else {
+                true
+            }
+        };
+
+        let (_, _, allowed) = tokio::join!(hostname, ident, dnsbl);
+        if !allowed {
+            return;
+        }
+
+        (*self.lookups_pending.write().await) = false;
+        if self.can_complete_registration().await {
+            self.complete_registration().await;
+        }
+    }
+
+    async fn lookup_hostname(&self) {
+        self.send_auth_notice("Looking up your hostname...").await;
+
+        let ip = self.address.ip();
+        let resolver = &self.server.resolver;
+        let hostname = match resolver.lookup_ptr(ip).await {
+            Some(hostname) => hostname.to_ascii_lowercase(),
+            None => {
+                self.send_auth_notice(
+                    "Couldn't look up your hostname, using your IP address instead",
+                )
+                .await;
+                return;
+            }
+        };
+
+        if !Client::is_hostname_valid(&hostname) {
+            self.send_auth_notice("Your hostname is invalid, using your IP address instead")
+                .await;
+            return;
+        }
+
+        /* NOTE(diath): Only trust the reverse record if it resolves back to the address we are connected from. */
+        let confirmed = match ip {
+            IpAddr::V4(ip) => match resolver.lookup_ipv4(&hostname).await {
+                Some(addresses) => addresses.contains(&ip),
+                None => false,
+            },
+            IpAddr::V6(ip) => match resolver.lookup_ipv6(&hostname).await {
+                Some(addresses) => addresses.contains(&ip),
+                None => false,
+            },
+        };
+
+        if !confirmed {
+            self.send_auth_notice(
+                "Your forward and reverse DNS do not match, using your IP address instead",
+            )
+            .await;
+            return;
+        }
+
+        self.send_auth_notice(&format!("Found your hostname: {}", hostname))
+            .await;
+
+        (*self.hostname.lock().await) = Some(hostname.to_string());
+        let host = self
+            .server
+            .cloak
+            .get_cloaked_host(UserHost::Hostname(hostname));
+        (*self.host.lock().await) = UserHost::VHost(host);
+    }
+
+    async fn lookup_ident(&self, ident: &Ident, local_address: SocketAddr) {
+        self.send_auth_notice("Checking Ident").await;
+
+        match ident.lookup(self.address, local_address).await {
+            Some(user) => {
+                self.send_auth_notice("Got Ident response").await;
+                (*self.ident.lock().await) = Some(user);
+            }
+            None => {
+                self.send_auth_notice("No Ident response").await;
+            }
+        }
+    }
+
+    /* NOTE(diath): Returns false if the client has been rejected. */
+    async fn check_dnsbl(&self) -> bool {
+        let ip = self.address.ip();
+        let listing = match self.server.dnsbl.check(&self.server.resolver, ip).await {
+            Some(listing) => listing,
+            None => return true,
+        };
+
+        self.server
+            .broadcast_oper_notice(format!(
+                "DNSBL: {} is listed in {} ({}), action: {}",
+                ip,
+                listing.zone,
+                listing.reply,
+                listing.action.name()
+            ))
+            .await;
+
+        if listing.action == DnsblAction::Reject {
+            self.kill(&listing.reason).await;
+            return false;
+        }
+
+        if listing.action == DnsblAction::RequireSasl {
+            self.send_auth_notice(&format!(
+                "{}, you have to log in to an account within 60 seconds to connect: /msg NickServ IDENTIFY <nick> <password>",
+                listing.reason
+            ))
+            .await;
+        }
+
+        (*self.dnsbl_listing.lock().await) = Some(listing);
+        true
+    }
+
+    /* NOTE(diath): A client listed with the require-sasl DNSBL action is held back from registering until it logs in to an account. */
+    pub async fn is_login_required(&self) -> bool {
+        !*self.registered.read().await
+            && self.account.lock().await.is_none()
+            && self
+                .dnsbl_listing
+                .lock()
+                .await
+                .as_ref()
+                .is_some_and(|listing| listing.action == DnsblAction::RequireSasl)
+    }
+
+    /* NOTE(diath): The only thing a held back client may do is identify to NickServ, registration resumes once that succeeds. */
+    pub async fn identify_before_registration(&self, text: &str) {
+        let command = text.split(' ').find(|word| !word.is_empty()).unwrap_or("");
+        if !command.eq_ignore_ascii_case("IDENTIFY") {
+            self.send_auth_notice(
+                "You have to log in to an account first: /msg NickServ IDENTIFY <nick> <password>",
+            )
+            .await;
+            return;
+        }
+
+        self.server.message_service(self, "NickServ", text).await;
+        if self.can_complete_registration().await {
+            self.complete_registration().await;
+        }
+    }
+
+    async fn can_complete_registration(&self) -> bool {
+        !*self.registered.read().await
+            && !*self.cap_negotiating.read().await
+            && !*self.lookups_pending.read().await
+            && !self.is_login_required().await
+            && self.nick.lock().await.len() != 0
+            && self.user.lock().await.len() != 0
+    }
+
+    /* NOTE(diath): Returns false if the line could not be parsed. */
+    async fn handle_line(&self, tags: Option<&str>, line: &str) -> bool {
+        if !self.set_client_tags(tags).await {
+            self.send_numeric_reply(
+                NumericReply::ErrInputTooLong,
+                ":Input line was too long".to_string(),
+            )
+            .await;
+            return true;
+        }
+
+        if line.trim_end().is_empty() {
+            return true;
+        }
+
+        (*self.line.lock().await) = line.to_string();
+        let result = self.parser.lock().await.parse(line);
+        match result {
+            Some(message) => {
+                self.on_message(message).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn handle_labeled(&self, label: &str, tags: Option<&str>, line: &str) -> bool {
+        let response = LabeledResponse {
+            client: self as *const Client as usize,
+            lines: RefCell::new(vec![]),
+        };
+
+        let (handled, lines) = RESPONSE
+            .scope(response, async {
+                let handled = self.handle_line(tags, line).await;
+                (handled, RESPONSE.with(|response| response.lines.take()))
+            })
+            .await;
+
+        self.send_labeled(label, lines).await;
+        handled
+    }
+
+    /* NOTE(diath): A command without replies is acknowledged, a single reply carries the label and several replies are wrapped in a batch. */
+    async fn send_labeled(&self, label: &str, lines: Vec<String>) {
+        match lines.len() {
+            0 => {
+                self.send_raw(prepend_tag(
+                    &format!(":{} ACK", self.server.name),
+                    "label",
+                    label,
+                ))
+                .await;
+            }
+            1 => {
+                self.send_raw(prepend_tag(&lines[0], "label", label)).await;
+            }
+            _ => {
+                let id = generate_id();
+                self.send_raw(prepend_tag(
+                    &format!(":{} BATCH +{} labeled-response", self.server.name, id),
+                    "label",
+                    label,
+                ))
+                .await;
+
+                for line in lines {
+                    self.send_raw(prepend_tag(&line, "batch", &id)).await;
+                }
+
+                self.send_raw(format!(":{} BATCH -{}", self.server.name, id))
+                    .await;
+            }
+        }
+    }
+
+    /* NOTE(diath): The label is only honoured for clients that negotiated labeled-response. */
+    async fn get_label(&self, section: Option<&str>) -> Option<String> {
+        let section = section?;
+        if !self.has_capability("labeled-response").await {
+            return None;
+        }
+
+        parse_tags(section)
+            .into_iter()
+            .find(|tag| tag.key == "label")
+            .map(|tag| tag.value)
+            .filter(|label| !label.is_empty() && label.len() <= MAX_LABEL_LENGTH)
+    }
+
+    /* NOTE(diath): Tags sent by a client that did not negotiate message-tags are ignored, as are the tags that are not client-only. Returns false if the client-only tags are too long. */
+    async fn set_client_tags(&self, section: Option<&str>) -> bool {
+        let mut tags = vec![];
+        if let Some(section) = section {
+            if self.has_capability("message-tags").await {
+                tags = parse_tags(section)
+                    .into_iter()
+                    .filter(|tag| tag.is_client_only())
+                    .collect::<Vec<Tag>>();
+            }
+        }
+
+        let valid = format_tags(&tags).len() <= MAX_CLIENT_TAGS_LENGTH;
+        if !valid {
+            tags.clear();
         }
+
+        (*self.tags.lock().await) = tags;
+        valid
+    }
+
+    pub async fn get_client_tags(&self) -> Vec<Tag> {
+        self.tags.lock().await.clone()
+    }
+
+    /* NOTE(diath): Only the tags of the capabilities the client negotiated are sent, a client without any gets the bare line. */
+    pub async fn send_tagged(&self, tags: &MessageTags, message: String) {
+        let server_time = self.has_capability("server-time").await;
+        let message_tags = self.has_capability("message-tags").await;
+        self.send_raw(tags.apply_for(message, server_time, message_tags))
+            .await;
     }
 
     pub async fn send_raw(&self, message: String) {
+        let client = self as *const Client as usize;
+        let captured = RESPONSE
+            .try_with(|response| {
+                if response.client == client {
+                    response.lines.borrow_mut().push(message.clone());
+                    true
+                } else {
+                    false
+                }
+            })
+            .unwrap_or(false);
+        if captured {
+            return;
+        }
+
         if let Some(writer) = &mut *self.writer.lock().await {
             match writer
                 .write_all(format!("{}\r\n", message).as_bytes())
@@ -188,17 +937,142 @@ impl Client {
         }
     }
 
+    pub async fn kill(&self, reason: &str) {
+        if self.service {
+            return;
+        }
+
+        let nick = self.nick.lock().await.to_string();
+        if self.is_remote() {
+            self.server
+                .propagate_except(
+                    None,
+                    format!(":{} KILL {} :{}", self.server.name, nick, reason),
+                )
+                .await;
+            self.server.remove_remote_client(self, reason).await;
+            return;
+        }
+
+        self.send_raw(format!(
+            "ERROR :Closing Link: {}[{}] ({})",
+            nick,
+            self.address.ip(),
+            reason
+        ))
+        .await;
+        self.server.broadcast_quit(self, reason).await;
+
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            writer.flush().await.ok();
+            writer.shutdown().await.ok();
+        }
+
+        self.cleanup().await;
+
+        /* NOTE(diath): Wake up the reader so the client task exits as well. */
+        if let Some(quit) = self.quit.lock().await.take() {
+            quit.send(()).ok();
+        }
+    }
+
+    /* NOTE(diath): Stops the reader task at a line boundary until the returned sender is dropped, along with whether there was input left unprocessed. Returns None for clients without a reader task. */
+    pub async fn pause(&self) -> Option<(bool, oneshot::Sender<()>)> {
+        let sender = self.pause.lock().await.clone()?;
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let (resume_sender, resume_receiver) = oneshot::channel();
+        sender.send((reply_sender, resume_receiver)).ok()?;
+
+        let pending = reply_receiver.await.ok()?;
+        Some((pending, resume_sender))
+    }
+
+    /* NOTE(diath): Returns the text after the command of the message that is being handled as the client sent it, a leading colon is dropped. */
+    pub async fn get_raw_params(&self) -> String {
+        get_raw_params(&self.line.lock().await)
+    }
+
+    /* NOTE(diath): Closes the connection with a final line, without announcing a quit to anyone. */
+    pub async fn disconnect(&self, line: &str) {
+        self.send_raw(line.to_string()).await;
+
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            writer.flush().await.ok();
+            writer.shutdown().await.ok();
+        }
+
+        if let Some(quit) = self.quit.lock().await.take() {
+            quit.send(()).ok();
+        }
+    }
+
     pub async fn send_numeric_reply(&self, reply: NumericReply, message: String) {
+        self.send_numeric(reply as u16, message).await;
+    }
+
+    /* NOTE(diath): Used directly for the numerics defined by modules. */
+    pub async fn send_numeric(&self, code: u16, message: String) {
         let nick = self.nick.lock().await.to_string();
         self.send_raw(format!(
             ":{} {:03} {} {}",
-            self.server.name, reply as i32, nick, message
+            self.server.name, code, nick, message
         ))
         .await;
     }
 
     pub async fn complete_registration(&self) {
+        /* NOTE(diath): With ident lookups enabled, a username not confirmed by identd is marked with a tilde. */
+        if self.server.ident.is_some() {
+            let user = match self.ident.lock().await.clone() {
+                Some(ident) => ident,
+                None => format!("~{}", self.user.lock().await),
+            };
+            (*self.user.lock().await) = user;
+        }
+
+        let user = self.user.lock().await.to_string();
+        let ip = self.address.ip().to_string();
+        let hosts = self.get_ban_hosts().await;
+        if let Some(akill) = self.server.operserv.find_akill(&user, &hosts).await {
+            self.send_numeric_reply(
+                NumericReply::ErrYoureBannedCreep,
+                format!(":You are banned from this server ({})", akill.reason),
+            )
+            .await;
+
+            let nick = self.nick.lock().await.to_string();
+            self.server
+                .broadcast_oper_notice(format!(
+                    "AKILL active for {} ({}): {}",
+                    nick, ip, akill.mask
+                ))
+                .await;
+
+            self.kill(&format!("AKILL: {}", akill.reason)).await;
+            return;
+        }
+
+        if let Some(ban) = self.server.find_ban(&user, &hosts).await {
+            self.send_numeric_reply(
+                NumericReply::ErrYoureBannedCreep,
+                format!(":You are banned from this server ({})", ban.reason),
+            )
+            .await;
+
+            let nick = self.nick.lock().await.to_string();
+            self.server
+                .broadcast_oper_notice(format!(
+                    "Server ban active for {} ({}): {}",
+                    nick, ip, ban.mask
+                ))
+                .await;
+
+            self.kill(&format!("Banned: {}", ban.reason)).await;
+            return;
+        }
+
         (*self.registered.write().await) = true;
+        self.server.propagate(self, self.get_uid_line().await).await;
 
         let prefix = self.get_prefix().await;
         self.send_numeric_reply(
@@ -253,6 +1127,10 @@ impl Client {
                 self.address.ip().to_string()
             ))
             .await;
+
+        for service in self.server.get_services() {
+            service.on_register(self).await;
+        }
     }
 
     pub async fn get_modes_description(&self) -> String {
@@ -262,6 +1140,10 @@ impl Client {
             write!(desc, "oO").expect("");
         }
 
+        if self.service {
+            write!(desc, "S").expect("");
+        }
+
         if self.away_message.lock().await.to_string().len() > 0 {
             write!(desc, "a").expect("");
         }
@@ -296,11 +1178,7 @@ impl Client {
                 }
                 'o' | 'O' => {
                     if !flag {
-                        (*self.operator.lock().await) = false;
-
-                        let nick = self.nick.lock().await.to_string();
-                        self.server.remove_operator(&nick).await;
-
+                        self.deoper().await;
                         changes.push(ch);
                     }
                 }
@@ -310,15 +1188,20 @@ impl Client {
                         let host = match &*self.host.lock().await {
                             UserHost::IPv4(host) => UserHost::IPv4(host.to_string()),
                             UserHost::IPv6(host) => UserHost::IPv6(host.to_string()),
+                            UserHost::Hostname(host) => UserHost::Hostname(host.to_string()),
                             UserHost::VHost(host) => UserHost::VHost(host.to_string()),
                         };
-                        (*self.host.lock().await) = UserHost::VHost(get_cloaked_host(host));
+                        let ident = self.vident.lock().await.clone();
+                        let host = self.server.cloak.get_cloaked_host(host);
+                        self.server
+                            .change_host(self, ident, UserHost::VHost(host))
+                            .await;
                     } else {
-                        (*self.host.lock().await) = match self.address {
-                            SocketAddr::V4(addr) => UserHost::IPv4(addr.ip().to_string()),
-                            SocketAddr::V6(addr) => UserHost::IPv6(addr.ip().to_string()),
-                        };
+                        let host = self.get_real_host().await;
+                        self.server.change_host(self, None, host).await;
                     }
+
+                    changes.push(ch);
                 }
                 _ => {
                     self.send_numeric_reply(
@@ -336,163 +1219,143 @@ impl Client {
     async fn on_message(&self, message: Message) {
         log::debug!("Received message: {}", message);
 
-        let registered = *self.registered.read().await;
-        if !registered {
-            match message.command.as_str() {
-                /* Connection Registration */
-                "CAP" => {
-                    self.on_cap(message);
-                }
-                "PASS" => {
-                    self.on_pass(message).await;
-                }
-                "NICK" => {
-                    self.on_nick(message).await;
-                }
-                "USER" => {
-                    self.on_user(message).await;
-                }
-                _ => {
-                    self.send_numeric_reply(
-                        NumericReply::ErrNotRegistered,
-                        ":You have not registered".to_string(),
-                    )
-                    .await;
-                }
+        let command = message.command.to_string();
+        match self.server.modules.dispatch(self, message).await {
+            Dispatch::Handled => {}
+            Dispatch::NotRegistered => {
+                self.send_numeric_reply(
+                    NumericReply::ErrNotRegistered,
+                    ":You have not registered".to_string(),
+                )
+                .await;
             }
-        } else {
-            match message.command.as_str() {
-                /* Connection Registration */
-                "CAP" => {
-                    self.on_cap(message);
-                }
-                "PASS" => {
-                    self.on_pass(message).await;
-                }
-                "NICK" => {
-                    self.on_nick(message).await;
-                }
-                "USER" => {
-                    self.on_user(message).await;
-                }
-                "OPER" => {
-                    self.on_oper(message).await;
-                }
-                "QUIT" => {
-                    self.on_quit(message).await;
-                }
-                /* Channel Operations */
-                "JOIN" => {
-                    self.on_join(message).await;
-                }
-                "PART" => {
-                    self.on_part(message).await;
-                }
-                "TOPIC" => {
-                    self.on_topic(message).await;
-                }
-                "NAMES" => {
-                    self.on_names(message).await;
-                }
-                "LIST" => {
-                    self.on_list(message).await;
-                }
-                "INVITE" => {
-                    self.on_invite(message).await;
-                }
-                "KICK" => {
-                    self.on_kick(message).await;
-                }
-                /* Sending Messages */
-                "PRIVMSG" => {
-                    self.on_privmsg(message, false).await;
-                }
-                "NOTICE" => {
-                    self.on_privmsg(message, true).await;
-                }
-                /* Server Queries and Commands */
-                "MOTD" => {
-                    self.on_motd(message).await;
-                }
-                "VERSION" => {
-                    self.on_version(message).await;
-                }
-                "STATS" => {
-                    self.on_stats(message).await;
-                }
-                "TIME" => {
-                    self.on_time(message).await;
-                }
-                "REHASH" => {
-                    self.on_rehash().await;
-                }
-                "DIE" | "RESTART" => {
-                    self.send_numeric_reply(
-                        NumericReply::ErrNoPrivileges,
-                        ":Permission Denied- You're not an IRC operator".to_string(),
-                    )
-                    .await;
-                }
-                "SUMMON" => {
-                    self.send_numeric_reply(
-                        NumericReply::ErrSummonDisabled,
-                        ":SUMMON has been disabled".to_string(),
-                    )
-                    .await;
-                }
-                "USERS" => {
-                    self.send_numeric_reply(
-                        NumericReply::ErrUsersDisabled,
-                        ":USERS has been disabled".to_string(),
-                    )
-                    .await;
-                }
-                /* User Based Queries */
-                "WHO" => {
-                    self.on_who(message).await;
-                }
-                "WHOIS" => {
-                    self.on_whois(message).await;
-                }
-                "WHOWAS" => {
-                    self.on_whowas(message).await;
-                }
-                /* Other */
-                "MODE" => {
-                    self.on_mode(message).await;
-                }
-                "PING" => {
-                    self.on_ping(message).await;
-                }
-                "PONG" => {
-                    self.on_pong(message).await;
-                }
-                "AWAY" => {
-                    self.on_away(message).await;
-                }
-                "USERHOST" => {
-                    self.on_userhost(message).await;
+            Dispatch::UnknownCommand => {
+                self.send_numeric_reply(
+                    NumericReply::ErrUnknownCommand,
+                    format!("{} :Unknown command", command),
+                )
+                .await;
+                log::debug!("Command {} not implemented.", command);
+            }
+        }
+    }
+
+    pub async fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.read().await.contains(name)
+    }
+
+    pub async fn on_cap(&self, message: Message) {
+        if message.params.len() < 1 {
+            self.send_numeric_reply(
+                NumericReply::ErrNeedMoreParams,
+                "CAP :Not enough parameters".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        let registered = *self.registered.read().await;
+        let nick = match self.nick.lock().await.as_str() {
+            "" => "*".to_string(),
+            nick => nick.to_string(),
+        };
+
+        let subcommand = message.params[0].to_ascii_uppercase();
+        match subcommand.as_str() {
+            "LS" => {
+                /* NOTE(diath): Registration is suspended until the client ends the capability negotiation. */
+                if !registered {
+                    (*self.cap_negotiating.write().await) = true;
                 }
-                "ISON" => {
-                    self.on_ison(message).await;
+
+                self.send_raw(format!(
+                    ":{} CAP {} LS :{}",
+                    self.server.name,
+                    nick,
+                    self.server.modules.get_capabilities().join(" ")
+                ))
+                .await;
+            }
+            "LIST" => {
+                let mut capabilities = self
+                    .capabilities
+                    .read()
+                    .await
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<String>>();
+                capabilities.sort();
+
+                self.send_raw(format!(
+                    ":{} CAP {} LIST :{}",
+                    self.server.name,
+                    nick,
+                    capabilities.join(" ")
+                ))
+                .await;
+            }
+            "REQ" => {
+                if !registered {
+                    (*self.cap_negotiating.write().await) = true;
                 }
-                _ => {
-                    self.send_numeric_reply(
-                        NumericReply::ErrUnknownCommand,
-                        format!("{} :Unknown command", message.command),
-                    )
-                    .await;
-                    log::debug!("Command {} not implemented.", message.command);
+
+                let requested = message.params.get(1).cloned().unwrap_or_default();
+
+                /* NOTE(diath): The request is applied as a whole, if any capability is unknown then none of them are. */
+                let valid = requested.split_whitespace().all(|capability| {
+                    self.server
+                        .modules
+                        .has_capability(capability.trim_start_matches('-'))
+                });
+
+                let valid = valid
+                    && {
+                        let mut capabilities = self.capabilities.write().await;
+                        let mut updated = capabilities.clone();
+                        for capability in requested.split_whitespace() {
+                            if let Some(name) = capability.strip_prefix('-') {
+                                updated.remove(name);
+                            } else {
+                                updated.insert(capability.to_string());
+                            }
+                        }
+
+                        /* NOTE(diath): Labeled responses of several lines are sent as a batch, so labeled-response can not be enabled without batch. */
+                        if updated.contains("labeled-response") && !updated.contains("batch") {
+                            false
+                        } else {
+                            *capabilities = updated;
+                            true
+                        }
+                    };
+
+                self.send_raw(format!(
+                    ":{} CAP {} {} :{}",
+                    self.server.name,
+                    nick,
+                    if valid { "ACK" } else { "NAK" },
+                    requested
+                ))
+                .await;
+            }
+            "END" => {
+                (*self.cap_negotiating.write().await) = false;
+
+                if self.can_complete_registration().await {
+                    self.complete_registration().await;
                 }
             }
+            _ => {
+                self.send_numeric_reply(
+                    NumericReply::ErrInvalidCapCmd,
+                    format!("{} :Invalid CAP command", message.params[0]),
+                )
+                .await;
+            }
         }
     }
 
-    fn on_cap(&self, _message: Message) {
-        log::debug!("Ignoring CAP command (IRCv3)");
-    }
-
-    async fn on_pass(&self, message: Message) {
+    pub async fn on_pass(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -510,7 +1373,7 @@ impl Client {
         }
     }
 
-    async fn on_nick(&self, message: Message) {
+    pub async fn on_nick(&self, message: Message) {
         /* TODO(diath): ERR_NICKCOLLISION, ERR_UNAVAILRESOURCE, ERR_RESTRICTED */
         if let Some(nick) = message.params.get(0) {
             if self.server.is_nick_mapped(nick).await {
@@ -526,28 +1389,46 @@ impl Client {
                         format!("{} :Erroneous nickname", nick),
                     )
                     .await;
-                } else {
-                    let mut send_complete_registration = false;
-                    if self.nick.lock().await.len() == 0 {
-                        self.server.map_nick(nick.to_string(), &self).await;
-
-                        if !*self.registered.read().await && self.user.lock().await.len() != 0 {
-                            send_complete_registration = true;
-                        }
-                    } else {
-                        let old_nick = self.nick.lock().await.to_string();
-                        self.server
-                            .append_nick_history(old_nick.to_string(), self)
-                            .await;
-                        self.server
-                            .remap_nick(old_nick.to_string(), nick.to_string())
-                            .await;
-                    }
+                } else if self.server.is_service_nick(nick) {
+                    self.send_numeric_reply(
+                        NumericReply::ErrErroneousNickname,
+                        format!("{} :Nickname is reserved for services", nick),
+                    )
+                    .await;
+                } else if let Some(reason) = self.server.operserv.is_juped(nick).await {
+                    self.send_numeric_reply(
+                        NumericReply::ErrUnavailResource,
+                        format!(
+                            "{} :Nick/channel is temporarily unavailable ({})",
+                            nick, reason
+                        ),
+                    )
+                    .await;
+                } else if self.server.nickserv.is_held(nick).await {
+                    self.send_numeric_reply(
+                        NumericReply::ErrUnavailResource,
+                        format!(
+                            "{} :Nick/channel is temporarily unavailable (held by services)",
+                            nick
+                        ),
+                    )
+                    .await;
+                } else if self.nick.lock().await.len() == 0 {
+                    self.server.map_nick(nick.to_string(), &self).await;
                     (*self.nick.lock().await) = nick.to_string();
 
-                    if send_complete_registration {
+                    if self.can_complete_registration().await {
                         self.complete_registration().await;
                     }
+                } else {
+                    let old_nick = self.nick.lock().await.to_string();
+                    self.server.change_nick(self, nick).await;
+
+                    if *self.registered.read().await {
+                        for service in self.server.get_services() {
+                            service.on_nick_change(self, &old_nick).await;
+                        }
+                    }
                 }
             }
         } else {
@@ -559,7 +1440,7 @@ impl Client {
         }
     }
 
-    async fn on_user(&self, message: Message) {
+    pub async fn on_user(&self, message: Message) {
         if *self.registered.read().await {
             self.send_numeric_reply(
                 NumericReply::ErrAlreadyRegistered,
@@ -576,13 +1457,28 @@ impl Client {
             (*self.user.lock().await) = message.params[0].clone();
             (*self.real_name.lock().await) = message.params[3].clone();
 
-            if self.nick.lock().await.len() != 0 {
+            if self.can_complete_registration().await {
                 self.complete_registration().await;
             }
         }
     }
 
-    async fn on_oper(&self, message: Message) {
+    pub async fn deoper(&self) {
+        let operator = *self.operator.lock().await;
+        (*self.operator.lock().await) = false;
+        self.oper_name.lock().await.take();
+        self.privileges.lock().await.clear();
+
+        let nick = self.nick.lock().await.to_string();
+        self.server.remove_operator(&nick).await;
+        if operator {
+            self.server
+                .propagate(self, format!(":{} MODE {} -o", nick, nick))
+                .await;
+        }
+    }
+
+    pub async fn on_oper(&self, message: Message) {
         /* TODO(diath): ERR_NOOPERHOST */
         if *self.operator.lock().await {
             return;
@@ -597,11 +1493,16 @@ impl Client {
         } else {
             let name = message.params[0].clone();
             let password = message.params[1].clone();
-            if self.server.verify_operator(&name, &password).await {
+            if let Some(privileges) = self.server.verify_operator(&name, &password).await {
                 (*self.operator.lock().await) = true;
+                (*self.oper_name.lock().await) = Some(name);
+                (*self.privileges.lock().await) = privileges;
 
                 let nick = self.nick.lock().await.to_string();
-                self.server.add_operator(nick).await;
+                self.server.add_operator(nick.to_string()).await;
+                self.server
+                    .propagate(self, format!(":{} MODE {} +o", nick, nick))
+                    .await;
 
                 self.send_numeric_reply(
                     NumericReply::RplYoureOper,
@@ -618,13 +1519,17 @@ impl Client {
         }
     }
 
-    async fn on_join(&self, message: Message) {
+    pub async fn on_join(&self, message: Message) {
         /* TODO(diath): ERR_TOOMANYTARGETS, ERR_BADCHANMASK, ERR_TOOMANYCHANNELS, ERR_UNAVAILRESOURCE */
         if message.params[0] == "0" {
-            for channel in &*self.channels.lock().await {
-                self.server.part_channel(self, &channel, "Leaving").await;
+            let channels = self.channels.lock().await.drain().collect::<Vec<String>>();
+            for channel in channels {
+                if self.server.part_channel(self, &channel, "Leaving").await {
+                    for service in self.server.get_services() {
+                        service.on_part(self, &channel).await;
+                    }
+                }
             }
-            self.channels.lock().await.clear();
         } else {
             let targets = message.params[0].split(",");
             let mut passwords = vec![];
@@ -637,6 +1542,18 @@ impl Client {
                     continue;
                 }
 
+                if let Some(reason) = self.server.operserv.is_juped(target).await {
+                    self.send_numeric_reply(
+                        NumericReply::ErrUnavailResource,
+                        format!(
+                            "{} :Nick/channel is temporarily unavailable ({})",
+                            target, reason
+                        ),
+                    )
+                    .await;
+                    continue;
+                }
+
                 if !self.server.is_channel_mapped(target).await {
                     self.server.create_channel(target).await;
                 }
@@ -651,12 +1568,16 @@ impl Client {
 
                     /* NOTE(diath): This cannot be handled in Server::join_channel method or we will end up with a deadlock. */
                     self.server.send_names(self, target.to_string()).await;
+
+                    for service in self.server.get_services() {
+                        service.on_join(self, target).await;
+                    }
                 }
             }
         }
     }
 
-    async fn on_part(&self, message: Message) {
+    pub async fn on_part(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -697,12 +1618,16 @@ impl Client {
 
                 if self.server.part_channel(self, target, &part_message).await {
                     self.channels.lock().await.remove(target);
+
+                    for service in self.server.get_services() {
+                        service.on_part(self, target).await;
+                    }
                 }
             }
         }
     }
 
-    async fn on_topic(&self, message: Message) {
+    pub async fn on_topic(&self, message: Message) {
         /* TODO(diath): ERR_NOCHANMODES */
         if message.params.len() < 1 {
             self.send_numeric_reply(
@@ -732,7 +1657,7 @@ impl Client {
         }
     }
 
-    async fn on_names(&self, message: Message) {
+    pub async fn on_names(&self, message: Message) {
         /* TODO(diath): ERR_TOOMANYMATCHES */
         if message.params.len() > 1 {
             if message.params[1] != self.server.name {
@@ -769,7 +1694,7 @@ impl Client {
         }
     }
 
-    async fn on_list(&self, message: Message) {
+    pub async fn on_list(&self, message: Message) {
         if message.params.len() > 1 {
             if message.params[1] != self.server.name {
                 self.send_numeric_reply(
@@ -790,7 +1715,7 @@ impl Client {
         }
     }
 
-    async fn on_invite(&self, message: Message) {
+    pub async fn on_invite(&self, message: Message) {
         if message.params.len() < 2 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -843,7 +1768,7 @@ impl Client {
         self.server.invite_channel(self, &target, &user).await;
     }
 
-    async fn on_kick(&self, message: Message) {
+    pub async fn on_kick(&self, message: Message) {
         /* TODO(diath): ERR_BADCHANMASK */
         if message.params.len() < 2 {
             self.send_numeric_reply(
@@ -948,18 +1873,20 @@ impl Client {
         }
     }
 
-    async fn on_privmsg(&self, message: Message, is_notice: bool) {
+    pub async fn on_privmsg(&self, message: Message, kind: MessageKind) {
         /* TODO(diath): ERR_NOTOPLEVEL, ERR_WILDTOPLEVEL, ERR_BADMASK */
+        self.delivered.lock().await.clear();
+
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNoRecipient,
-                ":No recipient given (PRIVMSG)".to_string(),
+                format!(":No recipient given ({})", kind.get_command()),
             )
             .await;
             return;
         }
 
-        if message.params.len() < 2 {
+        if message.params.len() < 2 && kind != MessageKind::Tagmsg {
             self.send_numeric_reply(
                 NumericReply::ErrNoTextToSend,
                 ":No text to send".to_string(),
@@ -969,7 +1896,13 @@ impl Client {
         }
 
         let targets = message.params[0].split(",");
-        let text = message.params[1].clone();
+        let text = message.params.get(1).cloned().unwrap_or_default();
+        let tags = self.get_client_tags().await;
+
+        /* NOTE(diath): A TAGMSG without client-only tags has nothing to deliver. */
+        if kind == MessageKind::Tagmsg && tags.is_empty() {
+            return;
+        }
 
         for target in targets {
             if target.len() == 0 {
@@ -978,9 +1911,14 @@ impl Client {
 
             match &target[0..1] {
                 "#" => {
-                    self.server
-                        .forward_channel_message(is_notice, self, target, text.clone())
-                        .await;
+                    if self
+                        .server
+                        .forward_channel_message(kind, self, target, text.clone(), &tags)
+                        .await
+                        && kind == MessageKind::Privmsg
+                    {
+                        self.delivered.lock().await.push(target.to_string());
+                    }
                 }
                 /* NOTE(diath): Technically a channel can be prefixed with either # (network), ! (safe), + (unmoderated) or & (local) but we only support #. */
                 "!" | "&" | "+" => {
@@ -991,15 +1929,57 @@ impl Client {
                     .await;
                 }
                 _ => {
-                    self.server
-                        .forward_message(is_notice, self, target, text.clone())
-                        .await;
+                    if self
+                        .server
+                        .forward_message(kind, self, target, text.clone(), &tags)
+                        .await
+                        && kind == MessageKind::Privmsg
+                    {
+                        self.delivered.lock().await.push(target.to_string());
+                    }
                 }
             }
         }
     }
 
-    async fn on_motd(&self, _message: Message) {
+    pub async fn take_delivered_targets(&self) -> Vec<String> {
+        self.delivered.lock().await.drain(..).collect()
+    }
+
+    pub async fn on_squery(&self, message: Message) {
+        if message.params.is_empty() {
+            self.send_numeric_reply(
+                NumericReply::ErrNoRecipient,
+                ":No recipient given (SQUERY)".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        if message.params.len() < 2 {
+            self.send_numeric_reply(
+                NumericReply::ErrNoTextToSend,
+                ":No text to send".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        let name = &message.params[0];
+        if !self
+            .server
+            .message_service(self, name, &message.params[1])
+            .await
+        {
+            self.send_numeric_reply(
+                NumericReply::ErrNoSuchService,
+                format!("{} :No such service", name),
+            )
+            .await;
+        }
+    }
+
+    pub async fn on_motd(&self, _message: Message) {
         /* TODO: add support for <target> */
         self.server.send_motd(&self).await;
     }
@@ -1026,7 +2006,7 @@ impl Client {
         .await;
     }
 
-    async fn on_stats(&self, message: Message) {
+    pub async fn on_stats(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -1092,7 +2072,7 @@ impl Client {
         .await;
     }
 
-    async fn on_time(&self, _message: Message) {
+    pub async fn on_time(&self, _message: Message) {
         /* TODO: add support for <target> */
         self.send_numeric_reply(
             NumericReply::RplTime,
@@ -1106,7 +2086,7 @@ impl Client {
         .await;
     }
 
-    async fn on_quit(&self, message: Message) {
+    pub async fn on_quit(&self, message: Message) {
         let reason = if message.params.len() > 0 {
             message.params[0].to_string()
         } else {
@@ -1122,7 +2102,7 @@ impl Client {
         }
     }
 
-    async fn on_rehash(&self) {
+    pub async fn on_rehash(&self) {
         if !*self.operator.lock().await {
             self.send_numeric_reply(
                 NumericReply::ErrNoPrivileges,
@@ -1135,13 +2115,204 @@ impl Client {
 
         self.send_numeric_reply(
             NumericReply::RplRehashing,
-            "motd.txt :Rehashing".to_string(),
+            format!("{} :Rehashing", self.server.config_path),
+        )
+        .await;
+
+        let nick = self.nick.lock().await.to_string();
+        self.server
+            .broadcast_oper_notice(format!("{} is rehashing server config file", nick))
+            .await;
+
+        if let Err(error) = Server::rehash(&self.server).await {
+            log::warn!("Rehash failed: {}", error);
+            self.server
+                .broadcast_oper_notice(format!("REHASH failed: {}", error))
+                .await;
+        }
+    }
+
+    pub async fn on_shutdown(&self, reason: Shutdown) {
+        if !*self.operator.lock().await {
+            self.send_numeric_reply(
+                NumericReply::ErrNoPrivileges,
+                ":Permission Denied- You're not an IRC operator".to_string(),
+            )
+            .await;
+
+            return;
+        }
+
+        let (command, privilege) = match reason {
+            Shutdown::Die => ("DIE", "die"),
+            Shutdown::Restart => ("RESTART", "restart"),
+            Shutdown::Upgrade => ("UPGRADE", "upgrade"),
+        };
+
+        if !self.privileges.lock().await.contains(privilege) {
+            self.send_numeric_reply(
+                NumericReply::ErrNoPrivs,
+                format!("{} :Insufficient oper privileges.", privilege),
+            )
+            .await;
+
+            return;
+        }
+
+        let nick = self.nick.lock().await.to_string();
+        log::info!("{} issued {}.", nick, command);
+        self.server
+            .broadcast_oper_notice(format!("{} issued {}", nick, command))
+            .await;
+        self.server.request_shutdown(reason);
+    }
+
+    pub async fn on_server(&self, message: Message) {
+        if message.params.len() < 3 {
+            self.send_numeric_reply(
+                NumericReply::ErrNeedMoreParams,
+                "SERVER :Not enough parameters".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        let name = message.params[0].clone();
+        let password = self.password.lock().await.to_string();
+        if !self.server.check_link(&name, &password).await {
+            log::info!(
+                "Rejected link from {} ({}): invalid credentials.",
+                name,
+                self.address
+            );
+            self.server
+                .broadcast_oper_notice(format!(
+                    "Link with {} rejected: invalid credentials ({})",
+                    name,
+                    self.address.ip()
+                ))
+                .await;
+
+            self.disconnect("ERROR :Closing Link: Invalid link credentials")
+                .await;
+            return;
+        }
+
+        (*self.link_request.lock().await) = Some((name, message.params[2].clone()));
+    }
+
+    pub async fn on_links(&self) {
+        for (name, uplink, hops, description) in self.server.get_servers().await {
+            self.send_numeric_reply(
+                NumericReply::RplLinks,
+                format!("{} {} :{} {}", name, uplink, hops, description),
+            )
+            .await;
+        }
+
+        self.send_numeric_reply(
+            NumericReply::RplEndOfLinks,
+            "* :End of /LINKS list.".to_string(),
         )
         .await;
-        self.server.reload_motd().await;
     }
 
-    async fn on_who(&self, message: Message) {
+    pub async fn on_connect(&self, message: Message) {
+        if !*self.operator.lock().await {
+            self.send_numeric_reply(
+                NumericReply::ErrNoPrivileges,
+                ":Permission Denied- You're not an IRC operator".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        if message.params.is_empty() {
+            self.send_numeric_reply(
+                NumericReply::ErrNeedMoreParams,
+                "CONNECT :Not enough parameters".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        let name = message.params[0].clone();
+        let config = match self.server.get_link_config(&name).await {
+            Some(config) => config,
+            None => {
+                self.send_numeric_reply(
+                    NumericReply::ErrNoSuchServer,
+                    format!("{} :No such server", name),
+                )
+                .await;
+                return;
+            }
+        };
+
+        let nick = self.nick.lock().await.to_string();
+        if self.server.is_server_known(&name).await {
+            self.send_raw(format!(
+                ":{} NOTICE {} :Server {} is already linked",
+                self.server.name, nick, name
+            ))
+            .await;
+            return;
+        }
+
+        log::info!("{} issued CONNECT for {}.", nick, name);
+        self.server
+            .broadcast_oper_notice(format!("{} issued CONNECT for {}", nick, name))
+            .await;
+
+        let server = self.server.clone();
+        tokio::spawn(async move {
+            link::connect(server, config).await;
+        });
+    }
+
+    pub async fn on_squit(&self, message: Message) {
+        if !*self.operator.lock().await {
+            self.send_numeric_reply(
+                NumericReply::ErrNoPrivileges,
+                ":Permission Denied- You're not an IRC operator".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        if message.params.is_empty() {
+            self.send_numeric_reply(
+                NumericReply::ErrNeedMoreParams,
+                "SQUIT :Not enough parameters".to_string(),
+            )
+            .await;
+            return;
+        }
+
+        let nick = self.nick.lock().await.to_string();
+        let name = message.params[0].clone();
+        let reason = match message.params.get(1) {
+            Some(reason) => reason.to_string(),
+            None => nick.to_string(),
+        };
+
+        /* NOTE(diath): Only servers linked to us directly can be split off, the rest of the network is reached through them. */
+        if !self.server.close_link(&name, &reason).await {
+            self.send_numeric_reply(
+                NumericReply::ErrNoSuchServer,
+                format!("{} :No such server", name),
+            )
+            .await;
+            return;
+        }
+
+        log::info!("{} issued SQUIT for {} ({}).", nick, name, reason);
+        self.server
+            .broadcast_oper_notice(format!("{} issued SQUIT for {} ({})", nick, name, reason))
+            .await;
+    }
+
+    pub async fn on_who(&self, message: Message) {
         if message.params.len() < 1 {
             self.server
                 .send_who_entry(None, "*".to_string(), self, self)
@@ -1164,7 +2335,7 @@ impl Client {
         }
     }
 
-    async fn on_whois(&self, message: Message) {
+    pub async fn on_whois(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNoNicknameGiven,
@@ -1194,7 +2365,7 @@ impl Client {
         }
     }
 
-    async fn on_whowas(&self, message: Message) {
+    pub async fn on_whowas(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNoNicknameGiven,
@@ -1228,7 +2399,7 @@ impl Client {
         }
     }
 
-    async fn on_mode(&self, message: Message) {
+    pub async fn on_mode(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -1261,7 +2432,7 @@ impl Client {
         }
     }
 
-    async fn on_ping(&self, message: Message) {
+    pub async fn on_ping(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNoOrigin,
@@ -1289,7 +2460,7 @@ impl Client {
         .await;
     }
 
-    async fn on_pong(&self, message: Message) {
+    pub async fn on_pong(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNoOrigin,
@@ -1313,15 +2484,21 @@ impl Client {
         (*self.received_pong.write().await) = true;
     }
 
-    async fn on_away(&self, message: Message) {
+    pub async fn on_away(&self, message: Message) {
+        let nick = self.nick.lock().await.to_string();
         if message.params.len() > 0 {
             (*self.away_message.lock().await) = message.params[0].to_string();
+            self.server
+                .propagate(self, format!(":{} AWAY :{}", nick, message.params[0]))
+                .await;
             self.send_numeric_reply(
                 NumericReply::RplNowAway,
                 ":You have been marked as being away".to_string(),
             )
             .await;
         } else {
+            self.away_message.lock().await.clear();
+            self.server.propagate(self, format!(":{} AWAY", nick)).await;
             self.send_numeric_reply(
                 NumericReply::RplUnAway,
                 ":You are no longer marked as being away".to_string(),
@@ -1330,7 +2507,7 @@ impl Client {
         }
     }
 
-    async fn on_userhost(&self, message: Message) {
+    pub async fn on_userhost(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -1343,7 +2520,7 @@ impl Client {
         self.server.handle_userhost(self, message.params).await;
     }
 
-    async fn on_ison(&self, message: Message) {
+    pub async fn on_ison(&self, message: Message) {
         if message.params.len() < 1 {
             self.send_numeric_reply(
                 NumericReply::ErrNeedMoreParams,
@@ -1389,4 +2566,38 @@ impl Client {
 
         true
     }
+
+    fn is_hostname_valid(hostname: &str) -> bool {
+        if hostname.is_empty() || hostname.len() > 255 {
+            return false;
+        }
+
+        hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_params() {
+        assert_eq!(
+            get_raw_params("NS IDENTIFY  two  spaces"),
+            "IDENTIFY  two  spaces"
+        );
+        assert_eq!(
+            get_raw_params(":nick NS :IDENTIFY secret"),
+            "IDENTIFY secret"
+        );
+        assert_eq!(get_raw_params("NS   IDENTIFY x\r\n"), "IDENTIFY x");
+        assert_eq!(get_raw_params("NS"), "");
+        assert_eq!(get_raw_params(":nick"), "");
+    }
 }
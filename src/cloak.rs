@@ -1,54 +1,227 @@
 use crate::client::UserHost;
+use crate::config::CloakConfig;
+
+use std::net::Ipv6Addr;
+use std::process;
+use std::time::SystemTime;
 
 use crypto::digest::Digest;
-use crypto::sha1::Sha1;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
 
-pub fn get_cloaked_host(host: UserHost) -> String {
-    match host {
-        UserHost::IPv4(s) => get_cloaked_host_ipv4(s.to_string()),
-        UserHost::IPv6(s) => get_cloaked_host_ipv6(s.to_string()),
-        UserHost::VHost(s) => s.to_string(),
-    }
+pub struct Cloak {
+    /* NOTE(diath): The first key is used to cloak new hosts, the remaining ones are kept so that bans on cloaks made with older keys still match after a key rotation. */
+    keys: Vec<String>,
+    prefix: String,
+    ipv4_suffix: String,
+    ipv6_suffix: String,
 }
 
-fn get_cloaked_host_ipv4(host: String) -> String {
-    let mut chunks = host.split(".").collect::<Vec<&str>>();
-    if chunks.len() != 4 {
-        return host;
+impl Cloak {
+    pub fn new(config: CloakConfig) -> Cloak {
+        let (cloak, problems) = Cloak::load(config);
+        for problem in problems {
+            log::warn!("{}", problem);
+        }
+
+        cloak
+    }
+
+    /* NOTE(diath): Returns the problems with the config along with the cloak, so that config checks can report them. */
+    pub fn load(config: CloakConfig) -> (Cloak, Vec<String>) {
+        let mut problems = vec![];
+        let mut keys = config.keys.unwrap_or_default();
+        if keys.iter().any(|key| key.is_empty()) {
+            problems.push("Ignoring empty cloak keys.".to_string());
+            keys.retain(|key| !key.is_empty());
+        }
+
+        for key in keys.iter() {
+            if key.len() < 16 {
+                problems.push(
+                    "Cloak key is shorter than 16 characters, cloaks may be reversible."
+                        .to_string(),
+                );
+            }
+        }
+
+        if keys.is_empty() {
+            /* NOTE(diath): Not a mistake in the config, only a warning at startup. */
+            log::warn!("No cloak keys configured, cloaked hosts will change after a restart.");
+            keys.push(Cloak::generate_key());
+        }
+
+        let cloak = Cloak {
+            keys,
+            prefix: config.prefix.unwrap_or_default(),
+            ipv4_suffix: config.ipv4_suffix.unwrap_or_else(|| "IP".to_string()),
+            ipv6_suffix: config.ipv6_suffix.unwrap_or_else(|| "IPv6".to_string()),
+        };
+
+        for (name, value) in [
+            ("prefix", &cloak.prefix),
+            ("ipv4_suffix", &cloak.ipv4_suffix),
+            ("ipv6_suffix", &cloak.ipv6_suffix),
+        ] {
+            if !value
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '.')
+            {
+                problems.push(format!(
+                    "Cloak {} contains invalid characters: {}",
+                    name, value
+                ));
+            }
+        }
+
+        (cloak, problems)
     }
 
-    chunks.remove(chunks.len() - 1);
+    fn generate_key() -> String {
+        let nanos = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(duration) => duration.as_nanos(),
+            Err(_) => 0,
+        };
 
-    let mut result = vec![];
-    for chunk in chunks {
-        let mut hasher = Sha1::new();
-        hasher.input_str(chunk);
-        result.push(hasher.result_str().to_string()[0..8].to_string());
+        let mut hasher = Sha256::new();
+        hasher.input_str(&format!("{}:{}", nanos, process::id()));
+        hasher.result_str()
     }
-    result.push("IP".to_string());
 
-    result.join(".")
-}
+    pub fn get_cloaked_host(&self, host: UserHost) -> String {
+        self.cloak(&self.keys[0], &host)
+    }
 
-fn get_cloaked_host_ipv6(host: String) -> String {
-    let mut chunks = host.split(":").collect::<Vec<&str>>();
-    if chunks.len() == 0 {
-        return host;
+    /* NOTE(diath): Returns the cloak of a host for every configured key, starting with the active one. */
+    pub fn get_cloaked_hosts(&self, host: &UserHost) -> Vec<String> {
+        self.keys.iter().map(|key| self.cloak(key, host)).collect()
     }
 
-    chunks.remove(chunks.len() - 1);
+    fn cloak(&self, key: &str, host: &UserHost) -> String {
+        match host {
+            UserHost::IPv4(s) => self.cloak_ipv4(key, s),
+            UserHost::IPv6(s) => self.cloak_ipv6(key, s),
+            UserHost::Hostname(s) => self.cloak_hostname(key, s),
+            UserHost::VHost(s) => s.to_string(),
+        }
+    }
+
+    fn with_prefix(&self, hash: String) -> String {
+        if self.prefix.is_empty() {
+            hash
+        } else {
+            format!("{}-{}", self.prefix, hash)
+        }
+    }
 
-    let mut result = vec![];
-    for chunk in chunks {
-        if chunk.len() == 0 {
-            result.push("".to_string());
+    /* NOTE(diath): Each segment hashes a progressively shorter part of the address so that bans can still target a whole subnet. */
+    fn cloak_ipv4(&self, key: &str, host: &str) -> String {
+        let chunks = host.split('.').collect::<Vec<&str>>();
+        if chunks.len() != 4 {
+            return host.to_string();
         }
 
-        let mut hasher = Sha1::new();
-        hasher.input_str(chunk);
-        result.push(hasher.result_str().to_string()[0..8].to_string());
+        [
+            self.with_prefix(hash(key, host)),
+            hash(key, &chunks[..3].join(".")),
+            hash(key, &chunks[..2].join(".")),
+            self.ipv4_suffix.to_string(),
+        ]
+        .join(".")
     }
-    result.push("IPv6".to_string());
 
-    result.join(":")
+    fn cloak_ipv6(&self, key: &str, host: &str) -> String {
+        let segments = match host.parse::<Ipv6Addr>() {
+            Ok(address) => address
+                .segments()
+                .iter()
+                .map(|segment| format!("{:x}", segment))
+                .collect::<Vec<String>>(),
+            Err(_) => return host.to_string(),
+        };
+
+        [
+            self.with_prefix(hash(key, &segments.join(":"))),
+            hash(key, &segments[..4].join(":")),
+            hash(key, &segments[..3].join(":")),
+            self.ipv6_suffix.to_string(),
+        ]
+        .join(":")
+    }
+
+    /* NOTE(diath): Only the first label is replaced, the domain suffix is kept so users can still tell where someone connects from. A host without a domain has nothing to keep, so it gets the IPv4 suffix instead. */
+    fn cloak_hostname(&self, key: &str, host: &str) -> String {
+        let labels = host.split('.').collect::<Vec<&str>>();
+        let suffix = match labels.len() {
+            0 | 1 => self.ipv4_suffix.to_string(),
+            2 => labels[1].to_string(),
+            _ => labels[1..].join("."),
+        };
+
+        format!("{}.{}", self.with_prefix(hash(key, host)), suffix)
+    }
+}
+
+fn hash(key: &str, value: &str) -> String {
+    let mut hmac = Hmac::new(Sha256::new(), key.as_bytes());
+    hmac.input(value.as_bytes());
+
+    let mut result = String::new();
+    for byte in hmac.result().code()[0..4].iter() {
+        result.push_str(&format!("{:02x}", byte));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_cloak() -> Cloak {
+        Cloak::new(CloakConfig {
+            keys: Some(vec!["0123456789abcdef".to_string()]),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn hostname() {
+        let cloak = get_cloak();
+        let host = cloak.get_cloaked_host(UserHost::Hostname("a.b.example.com".to_string()));
+        assert!(host.ends_with(".b.example.com"), "{}", host);
+        assert!(!host.starts_with("a."), "{}", host);
+
+        let host = cloak.get_cloaked_host(UserHost::Hostname("example.com".to_string()));
+        assert!(
+            host.ends_with(".com") && !host.contains("example"),
+            "{}",
+            host
+        );
+    }
+
+    #[test]
+    fn single_label_hostname() {
+        let cloak = get_cloak();
+        let host = cloak.get_cloaked_host(UserHost::Hostname("myhost".to_string()));
+        assert!(!host.contains("myhost"), "{}", host);
+        assert!(host.ends_with(".IP"), "{}", host);
+    }
+
+    #[test]
+    fn ipv4() {
+        let cloak = get_cloak();
+        let first = cloak.get_cloaked_host(UserHost::IPv4("192.0.2.1".to_string()));
+        let second = cloak.get_cloaked_host(UserHost::IPv4("192.0.2.2".to_string()));
+        assert!(!first.contains("192"), "{}", first);
+        assert!(first.ends_with(".IP"), "{}", first);
+
+        /* NOTE(diath): Addresses in the same /24 share every segment but the first. */
+        assert_ne!(first, second);
+        assert_eq!(
+            first.split_once('.').unwrap().1,
+            second.split_once('.').unwrap().1
+        );
+    }
 }
@@ -1,9 +1,112 @@
+use crate::ayame::*;
+
+use std::collections::HashMap;
+
+use clap::{App, Arg};
 use serde::Deserialize;
 
+use log::LevelFilter;
+
+/* NOTE(diath): Command line options, the paths given here take precedence over the ones in the config file. */
+#[derive(Debug)]
+pub struct Options {
+    pub config_path: String,
+    pub config_path_given: bool,
+    pub motd_path: Option<String>,
+    pub data_path: Option<String>,
+    pub log_level: Option<LevelFilter>,
+    pub check_config: bool,
+    pub ignore_config_errors: bool,
+    pub upgrade: bool,
+}
+
+impl Options {
+    pub fn parse() -> Options {
+        let matches = App::new(IRCD_NAME)
+            .version(IRCD_VERSION)
+            .about(IRCD_REPOSITORY)
+            .arg(
+                Arg::with_name("config")
+                    .short("c")
+                    .long("config")
+                    .value_name("FILE")
+                    .help("Path to the config file")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("motd")
+                    .short("m")
+                    .long("motd")
+                    .value_name("FILE")
+                    .help("Path to the MOTD file, overrides server.motd_path")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("data")
+                    .short("d")
+                    .long("data")
+                    .value_name("DIR")
+                    .help("Path to the data directory, overrides server.data_path")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("log-level")
+                    .short("l")
+                    .long("log-level")
+                    .value_name("LEVEL")
+                    .help("Log level (off, error, warn, info, debug, trace)")
+                    .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                    .case_insensitive(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("check-config")
+                    .long("check-config")
+                    .help("Validates the config file and exits"),
+            )
+            .arg(
+                Arg::with_name("ignore-config-errors")
+                    .long("ignore-config-errors")
+                    .help("Starts with the default settings if the config file is invalid"),
+            )
+            .arg(
+                Arg::with_name("upgrade")
+                    .long("upgrade")
+                    .help("Resumes the state handed over by a running server")
+                    .hidden(true),
+            )
+            .get_matches();
+
+        Options {
+            config_path: matches
+                .value_of("config")
+                .unwrap_or(IRCD_CONFIG)
+                .to_string(),
+            config_path_given: matches.is_present("config"),
+            motd_path: matches.value_of("motd").map(|path| path.to_string()),
+            data_path: matches.value_of("data").map(|path| path.to_string()),
+            log_level: matches
+                .value_of("log-level")
+                .and_then(|level| level.parse::<LevelFilter>().ok()),
+            check_config: matches.is_present("check-config"),
+            ignore_config_errors: matches.is_present("ignore-config-errors"),
+            upgrade: matches.is_present("upgrade"),
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub oper: Option<Vec<OperConfig>>,
+    pub services: Option<ServicesConfig>,
+    pub cloak: Option<CloakConfig>,
+    pub dns: Option<DnsConfig>,
+    pub ident: Option<IdentConfig>,
+    pub dnsbl: Option<Vec<DnsblConfig>>,
+    pub ban: Option<Vec<BanConfig>>,
+    pub link: Option<Vec<LinkConfig>>,
+    pub scripts: Option<ScriptsConfig>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -11,11 +114,89 @@ pub struct ServerConfig {
     pub name: Option<String>,
     pub host: Option<String>,
     pub port: Option<u16>,
+    pub listen: Option<Vec<String>>,
+    pub description: Option<String>,
     pub motd_path: Option<String>,
+    pub data_path: Option<String>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 pub struct OperConfig {
     pub name: Option<String>,
     pub password: Option<String>,
+    pub privileges: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BanConfig {
+    pub mask: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct LinkConfig {
+    pub name: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub password: Option<String>,
+    pub autoconnect: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CloakConfig {
+    pub keys: Option<Vec<String>>,
+    pub prefix: Option<String>,
+    pub ipv4_suffix: Option<String>,
+    pub ipv6_suffix: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DnsConfig {
+    pub lookups: Option<bool>,
+    pub nameserver: Option<String>,
+    pub timeout: Option<u64>,
+    pub dnsbl_cache_duration: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DnsblConfig {
+    pub zone: Option<String>,
+    pub action: Option<String>,
+    pub reason: Option<String>,
+    pub replies: Option<HashMap<String, DnsblReplyConfig>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DnsblReplyConfig {
+    pub action: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct IdentConfig {
+    pub lookups: Option<bool>,
+    pub port: Option<u16>,
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ScriptsConfig {
+    pub directory: Option<String>,
+    /* NOTE(diath): In milliseconds, per call of a script function. */
+    pub time_limit: Option<u64>,
+    pub operation_limit: Option<u64>,
+    pub string_limit: Option<usize>,
+    pub collection_limit: Option<usize>,
+    /* NOTE(diath): Roughly in bytes, the state of a script is reset when it grows past it. */
+    pub state_limit: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ServicesConfig {
+    pub memo_limit: Option<usize>,
+    pub session_limit: Option<usize>,
+    pub nick_enforce: Option<bool>,
+    pub nick_enforce_delay: Option<u64>,
+    pub nick_hold_duration: Option<u64>,
+    pub channels: Option<Vec<String>>,
 }
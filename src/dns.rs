@@ -0,0 +1,468 @@
+use std::collections::hash_map::RandomState;
+use std::fs::read_to_string;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration};
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+}
+
+/* NOTE(diath): A minimal stub resolver, it sends a single UDP query to one nameserver and only understands A, AAAA and PTR answers. */
+pub struct Resolver {
+    pub nameserver: SocketAddr,
+    pub timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(nameserver: Option<String>, timeout: u64) -> Resolver {
+        let nameserver = match nameserver {
+            Some(address) => match address.parse::<SocketAddr>() {
+                Ok(address) => address,
+                Err(_) => match address.parse::<IpAddr>() {
+                    Ok(address) => SocketAddr::new(address, 53),
+                    Err(_) => {
+                        log::warn!("Invalid nameserver address: {}", address);
+                        Resolver::get_system_nameserver()
+                    }
+                },
+            },
+            None => Resolver::get_system_nameserver(),
+        };
+
+        log::info!("Nameserver: {}", nameserver);
+
+        Resolver {
+            nameserver,
+            timeout: Duration::from_secs(timeout),
+        }
+    }
+
+    fn get_system_nameserver() -> SocketAddr {
+        if let Ok(contents) = read_to_string("/etc/resolv.conf") {
+            for line in contents.lines() {
+                let mut chunks = line.split_whitespace();
+                if chunks.next() != Some("nameserver") {
+                    continue;
+                }
+
+                if let Some(Ok(address)) = chunks.next().map(|chunk| chunk.parse::<IpAddr>()) {
+                    return SocketAddr::new(address, 53);
+                }
+            }
+        }
+
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53)
+    }
+
+    pub async fn lookup_ptr(&self, address: IpAddr) -> Option<String> {
+        let name = match address {
+            IpAddr::V4(_) => format!("{}.in-addr.arpa", reverse_address(address)),
+            IpAddr::V6(_) => format!("{}.ip6.arpa", reverse_address(address)),
+        };
+
+        for record in self.query(&name, TYPE_PTR).await? {
+            if let Record::Ptr(name) = record {
+                return Some(name);
+            }
+        }
+
+        None
+    }
+
+    pub async fn lookup_ipv4(&self, name: &str) -> Option<Vec<Ipv4Addr>> {
+        let records = self.query(name, TYPE_A).await?;
+        Some(
+            records
+                .into_iter()
+                .filter_map(|record| match record {
+                    Record::A(address) => Some(address),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    pub async fn lookup_ipv6(&self, name: &str) -> Option<Vec<Ipv6Addr>> {
+        let records = self.query(name, TYPE_AAAA).await?;
+        Some(
+            records
+                .into_iter()
+                .filter_map(|record| match record {
+                    Record::Aaaa(address) => Some(address),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /* NOTE(diath): Returns None if the query failed or timed out and an empty list if the name does not exist. */
+    pub async fn query(&self, name: &str, query_type: u16) -> Option<Vec<Record>> {
+        match timeout(self.timeout, self.send_query(name, query_type)).await {
+            Ok(result) => result,
+            Err(_) => {
+                log::debug!("DNS query for {} timed out.", name);
+                None
+            }
+        }
+    }
+
+    async fn send_query(&self, name: &str, query_type: u16) -> Option<Vec<Record>> {
+        /* NOTE(diath): The id has to be unpredictable so that a spoofed answer can not be matched to the query. */
+        let id = RandomState::new().build_hasher().finish() as u16;
+        let packet = build_query(id, name, query_type)?;
+        let bind_address = match self.nameserver {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        let mut socket = UdpSocket::bind(bind_address).await.ok()?;
+        socket.connect(self.nameserver).await.ok()?;
+        socket.send(&packet).await.ok()?;
+
+        /* NOTE(diath): Packets that do not answer our question are skipped, the query times out if no answer arrives. */
+        let mut buffer = [0; 4096];
+        loop {
+            let size = socket.recv(&mut buffer).await.ok()?;
+            if size < 2 || u16::from_be_bytes([buffer[0], buffer[1]]) != id {
+                continue;
+            }
+
+            if let Some(records) = parse_response(&buffer[..size], name, query_type) {
+                return Some(records);
+            }
+        }
+    }
+}
+
+/* NOTE(diath): Reverses the octets (IPv4) or nibbles (IPv6) of an address as used by PTR and DNSBL lookups. */
+pub fn reverse_address(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+            format!("{}.{}.{}.{}", octets[3], octets[2], octets[1], octets[0])
+        }
+        IpAddr::V6(address) => {
+            let mut nibbles = vec![];
+            for byte in address.octets().iter().rev() {
+                nibbles.push(format!("{:x}", byte & 0x0f));
+                nibbles.push(format!("{:x}", byte >> 4));
+            }
+            nibbles.join(".")
+        }
+    }
+}
+
+fn build_query(id: u16, name: &str, query_type: u16) -> Option<Vec<u8>> {
+    let mut packet = vec![];
+    packet.extend_from_slice(&id.to_be_bytes());
+    /* NOTE(diath): Standard query with recursion desired and a single question. */
+    packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+
+    packet.extend_from_slice(&query_type.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+
+    Some(packet)
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> Option<u16> {
+    if offset + 2 > packet.len() {
+        return None;
+    }
+
+    Some(u16::from_be_bytes([packet[offset], packet[offset + 1]]))
+}
+
+/* NOTE(diath): Reads a possibly compressed name, returns the name and the offset right after it. */
+fn read_name(packet: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut position = offset;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let length = *packet.get(position)? as usize;
+        if length == 0 {
+            position += 1;
+            break;
+        }
+
+        if length & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+
+            let pointer = (read_u16(packet, position)? & 0x3fff) as usize;
+            if end.is_none() {
+                end = Some(position + 2);
+            }
+            position = pointer;
+            continue;
+        }
+
+        let label = packet.get(position + 1..position + 1 + length)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        position += 1 + length;
+    }
+
+    Some((labels.join("."), end.unwrap_or(position)))
+}
+
+/* NOTE(diath): Only a response that repeats the question that was asked is accepted. */
+fn parse_response(packet: &[u8], name: &str, query_type: u16) -> Option<Vec<Record>> {
+    let flags = read_u16(packet, 2)?;
+    let questions = read_u16(packet, 4)?;
+    let answers = read_u16(packet, 6)?;
+
+    if flags & 0x8000 == 0 || questions != 1 {
+        return None;
+    }
+
+    let (question, next) = read_name(packet, 12)?;
+    if !question.eq_ignore_ascii_case(name.trim_end_matches('.'))
+        || read_u16(packet, next)? != query_type
+        || read_u16(packet, next + 2)? != 1
+    {
+        return None;
+    }
+    let mut offset = next + 4;
+
+    /* NOTE(diath): NXDOMAIN means the name does not exist, any other error code is a failed query. */
+    match flags & 0x000f {
+        0 => {}
+        3 => return Some(vec![]),
+        _ => return None,
+    }
+
+    let mut records = vec![];
+    for _ in 0..answers {
+        let (_, next) = read_name(packet, offset)?;
+        let record_type = read_u16(packet, next)?;
+        let length = read_u16(packet, next + 8)? as usize;
+        let data_offset = next + 10;
+        let data = packet.get(data_offset..data_offset + length)?;
+
+        match record_type {
+            TYPE_A if length == 4 => {
+                records.push(Record::A(Ipv4Addr::new(data[0], data[1], data[2], data[3])));
+            }
+            TYPE_AAAA if length == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                records.push(Record::Aaaa(Ipv6Addr::from(octets)));
+            }
+            TYPE_PTR => {
+                let (name, _) = read_name(packet, data_offset)?;
+                records.push(Record::Ptr(name));
+            }
+            _ => {}
+        }
+
+        offset = data_offset + length;
+    }
+
+    Some(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(
+        id: u16,
+        rcode: u8,
+        name: &str,
+        query_type: u16,
+        answers: &[(u16, Vec<u8>)],
+    ) -> Vec<u8> {
+        let mut packet = build_query(id, name, query_type).unwrap();
+        packet[2] = 0x81;
+        packet[3] = 0x80 | rcode;
+        packet[7] = answers.len() as u8;
+
+        for (record_type, data) in answers {
+            /* NOTE(diath): Every answer points back at the question name. */
+            packet.extend_from_slice(&[0xc0, 0x0c]);
+            packet.extend_from_slice(&record_type.to_be_bytes());
+            packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x0e, 0x10]);
+            packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            packet.extend_from_slice(data);
+        }
+
+        packet
+    }
+
+    #[test]
+    fn reverse_addresses() {
+        assert_eq!(
+            reverse_address("192.0.2.1".parse().unwrap()),
+            "1.2.0.192".to_string()
+        );
+        assert_eq!(
+            reverse_address("2001:db8::1".parse().unwrap()),
+            format!("1.{}8.b.d.0.1.0.0.2", "0.".repeat(23))
+        );
+    }
+
+    #[test]
+    fn query_names() {
+        assert!(build_query(1, "example..com", TYPE_A).is_none());
+        assert!(build_query(1, &format!("{}.com", "a".repeat(64)), TYPE_A).is_none());
+
+        let packet = build_query(1, "example.com.", TYPE_A).unwrap();
+        assert_eq!(
+            read_name(&packet, 12),
+            Some(("example.com".to_string(), 25))
+        );
+    }
+
+    #[test]
+    fn compressed_names() {
+        let mut packet = build_query(1, "example.com", TYPE_A).unwrap();
+        let offset = packet.len();
+        packet.extend_from_slice(&[3, b'w', b'w', b'w', 0xc0, 0x0c]);
+        assert_eq!(
+            read_name(&packet, offset),
+            Some(("www.example.com".to_string(), offset + 6))
+        );
+    }
+
+    #[test]
+    fn malformed_names() {
+        /* NOTE(diath): A pointer to itself must not loop forever. */
+        let packet = [0u8; 12]
+            .iter()
+            .chain(&[0xc0, 0x0c])
+            .copied()
+            .collect::<Vec<u8>>();
+        assert_eq!(read_name(&packet, 12), None);
+
+        let packet = [0u8; 12]
+            .iter()
+            .chain(&[5, b'a', b'b'])
+            .copied()
+            .collect::<Vec<u8>>();
+        assert_eq!(read_name(&packet, 12), None);
+        assert_eq!(read_name(&packet, 100), None);
+    }
+
+    #[test]
+    fn address_answers() {
+        let packet = response(
+            1,
+            0,
+            "example.com",
+            TYPE_A,
+            &[
+                (TYPE_A, vec![192, 0, 2, 1]),
+                (
+                    TYPE_AAAA,
+                    "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+                ),
+                (TYPE_A, vec![1, 2, 3]),
+                (16, b"\x04text".to_vec()),
+            ],
+        );
+        let records = parse_response(&packet, "example.com", TYPE_A).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[0], Record::A(address) if address == Ipv4Addr::new(192, 0, 2, 1)));
+        assert!(
+            matches!(records[1], Record::Aaaa(address) if address == "2001:db8::1".parse::<Ipv6Addr>().unwrap())
+        );
+    }
+
+    #[test]
+    fn ptr_answers() {
+        let packet = response(
+            1,
+            0,
+            "1.2.0.192.in-addr.arpa",
+            TYPE_PTR,
+            &[(TYPE_PTR, vec![4, b'h', b'o', b's', b't', 0xc0, 0x0c])],
+        );
+        let records = parse_response(&packet, "1.2.0.192.in-addr.arpa", TYPE_PTR).unwrap();
+        assert!(matches!(&records[0], Record::Ptr(name) if name == "host.1.2.0.192.in-addr.arpa"));
+    }
+
+    #[test]
+    fn error_responses() {
+        let parse = |packet: &[u8]| parse_response(packet, "example.com", TYPE_A);
+        assert_eq!(
+            parse(&response(1, 3, "example.com", TYPE_A, &[])).map(|records| records.len()),
+            Some(0)
+        );
+        assert!(parse(&response(1, 2, "example.com", TYPE_A, &[])).is_none());
+
+        let packet = response(1, 0, "example.com", TYPE_A, &[(TYPE_A, vec![192, 0, 2, 1])]);
+        assert!(parse(&packet[..packet.len() - 2]).is_none());
+        assert!(parse(&packet[..4]).is_none());
+    }
+
+    #[test]
+    fn mismatched_questions() {
+        let packet = response(1, 0, "example.com", TYPE_A, &[(TYPE_A, vec![192, 0, 2, 1])]);
+        assert!(parse_response(&packet, "EXAMPLE.com.", TYPE_A).is_some());
+        assert!(parse_response(&packet, "example.org", TYPE_A).is_none());
+        assert!(parse_response(&packet, "example.com", TYPE_AAAA).is_none());
+
+        /* NOTE(diath): A query echoed back is not a response. */
+        let query = build_query(1, "example.com", TYPE_A).unwrap();
+        assert!(parse_response(&query, "example.com", TYPE_A).is_none());
+
+        /* NOTE(diath): Nor is a response to a NXDOMAIN of another name. */
+        let packet = response(1, 3, "other.example", TYPE_A, &[]);
+        assert!(parse_response(&packet, "example.com", TYPE_A).is_none());
+    }
+
+    #[tokio::test]
+    async fn local_nameserver() {
+        let mut server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            let (_, peer) = server.recv_from(&mut buffer).await.unwrap();
+            let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+
+            /* NOTE(diath): An answer with the wrong id or question is skipped, the next one is used. */
+            let answer = [(TYPE_PTR, vec![4, b'e', b'v', b'i', b'l', 0])];
+            let packet = response(id ^ 1, 0, "1.2.0.192.in-addr.arpa", TYPE_PTR, &answer);
+            server.send_to(&packet, &peer).await.unwrap();
+            let packet = response(id, 0, "2.2.0.192.in-addr.arpa", TYPE_PTR, &answer);
+            server.send_to(&packet, &peer).await.unwrap();
+
+            let packet = response(
+                id,
+                0,
+                "1.2.0.192.in-addr.arpa",
+                TYPE_PTR,
+                &[(TYPE_PTR, vec![4, b'h', b'o', b's', b't', 0])],
+            );
+            server.send_to(&packet, &peer).await.unwrap();
+        });
+
+        let resolver = Resolver::new(Some(address.to_string()), 5);
+        assert_eq!(
+            resolver.lookup_ptr("192.0.2.1".parse().unwrap()).await,
+            Some("host".to_string())
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::config::DnsblConfig;
+use crate::dns::{reverse_address, Resolver};
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+use chrono::Utc;
+
+use futures::future::join_all;
+
+use tokio::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DnsblAction {
+    Reject,
+    Mark,
+    RequireSasl,
+}
+
+impl DnsblAction {
+    fn parse(value: &str) -> Option<DnsblAction> {
+        match value.to_ascii_lowercase().as_str() {
+            "reject" => Some(DnsblAction::Reject),
+            "mark" => Some(DnsblAction::Mark),
+            "require_sasl" | "require-sasl" | "sasl" => Some(DnsblAction::RequireSasl),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DnsblAction::Reject => "reject",
+            DnsblAction::Mark => "mark",
+            DnsblAction::RequireSasl => "require SASL",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DnsblListing {
+    pub zone: String,
+    pub reply: Ipv4Addr,
+    pub action: DnsblAction,
+    pub reason: String,
+}
+
+struct Blocklist {
+    zone: String,
+    action: Option<DnsblAction>,
+    reason: String,
+    replies: HashMap<Ipv4Addr, (DnsblAction, Option<String>)>,
+}
+
+pub struct Dnsbl {
+    lists: Vec<Blocklist>,
+    cache_duration: i64,
+    cache: Mutex<HashMap<IpAddr, (i64, Option<DnsblListing>)>>,
+}
+
+impl Dnsbl {
+    pub fn new(config: Vec<DnsblConfig>, cache_duration: i64) -> Dnsbl {
+        let (dnsbl, problems) = Dnsbl::load(config, cache_duration);
+        for problem in problems {
+            log::warn!("{}", problem);
+        }
+
+        if dnsbl.is_enabled() {
+            log::info!("Loaded {} DNS blocklists.", dnsbl.lists.len());
+        }
+
+        dnsbl
+    }
+
+    /* NOTE(diath): Entries with a problem are left out and the problems returned, so that config checks can report them. */
+    pub fn load(config: Vec<DnsblConfig>, cache_duration: i64) -> (Dnsbl, Vec<String>) {
+        let mut lists = vec![];
+        let mut problems = vec![];
+        for list in config {
+            let zone = match list.zone {
+                Some(zone) if !zone.trim_matches('.').is_empty() => {
+                    zone.trim_matches('.').to_string()
+                }
+                _ => {
+                    problems.push("Ignoring DNSBL entry without a zone.".to_string());
+                    continue;
+                }
+            };
+
+            let action = match list.action {
+                Some(action) => match DnsblAction::parse(&action) {
+                    Some(action) => Some(action),
+                    None => {
+                        problems.push(format!(
+                            "Ignoring DNSBL {} with an invalid action: {}",
+                            zone, action
+                        ));
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            /* NOTE(diath): Reply codes may be given as a full address (127.0.0.2) or only the last octet (2). */
+            let mut replies = HashMap::new();
+            for (code, reply) in list.replies.unwrap_or_default() {
+                let address = match code.parse::<u8>() {
+                    Ok(octet) => Ipv4Addr::new(127, 0, 0, octet),
+                    Err(_) => match code.parse::<Ipv4Addr>() {
+                        Ok(address) => address,
+                        Err(_) => {
+                            problems.push(format!(
+                                "Ignoring invalid DNSBL {} reply code: {}",
+                                zone, code
+                            ));
+                            continue;
+                        }
+                    },
+                };
+
+                match reply
+                    .action
+                    .as_ref()
+                    .and_then(|action| DnsblAction::parse(action))
+                {
+                    Some(action) => {
+                        replies.insert(address, (action, reply.reason));
+                    }
+                    None => {
+                        problems.push(format!(
+                            "Ignoring DNSBL {} reply {} without a valid action.",
+                            zone, code
+                        ));
+                    }
+                }
+            }
+
+            let action = if action.is_none() && replies.is_empty() {
+                Some(DnsblAction::Reject)
+            } else {
+                action
+            };
+
+            lists.push(Blocklist {
+                reason: list
+                    .reason
+                    .unwrap_or_else(|| format!("Your host is listed in {}", zone)),
+                zone,
+                action,
+                replies,
+            });
+        }
+
+        let dnsbl = Dnsbl {
+            lists,
+            cache_duration,
+            cache: Mutex::new(HashMap::new()),
+        };
+
+        (dnsbl, problems)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.lists.is_empty()
+    }
+
+    /* NOTE(diath): Returns the listing of the first configured list that has an action for the address, results are cached. */
+    pub async fn check(&self, resolver: &Resolver, address: IpAddr) -> Option<DnsblListing> {
+        let now = Utc::now().timestamp();
+        if let Some((expires, listing)) = self.cache.lock().await.get(&address) {
+            if *expires > now {
+                return listing.clone();
+            }
+        }
+
+        let name = reverse_address(address);
+        let queries = self
+            .lists
+            .iter()
+            .map(|list| format!("{}.{}", name, list.zone))
+            .collect::<Vec<String>>();
+        let results = join_all(queries.iter().map(|query| resolver.lookup_ipv4(query))).await;
+
+        let mut listing = None;
+        for (list, replies) in self.lists.iter().zip(results) {
+            for reply in replies.unwrap_or_default() {
+                let (action, reason) = match list.replies.get(&reply) {
+                    Some((action, reason)) => (*action, reason.clone()),
+                    None => match list.action {
+                        Some(action) => (action, None),
+                        None => continue,
+                    },
+                };
+
+                listing = Some(DnsblListing {
+                    zone: list.zone.to_string(),
+                    reply,
+                    action,
+                    reason: reason.unwrap_or_else(|| list.reason.to_string()),
+                });
+                break;
+            }
+
+            if listing.is_some() {
+                break;
+            }
+        }
+
+        let mut cache = self.cache.lock().await;
+        cache.retain(|_, (expires, _)| *expires > now);
+        cache.insert(address, (now + self.cache_duration, listing.clone()));
+
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::DnsblReplyConfig;
+
+    use tokio::net::UdpSocket;
+
+    /* NOTE(diath): Answers every query containing the label with 127.0.0.<octet> and anything else with NXDOMAIN. */
+    async fn spawn_blocklist(label: &'static str, octet: u8) -> String {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (size, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let mut packet = buffer[..size].to_vec();
+                let query = String::from_utf8_lossy(&packet[12..]).to_string();
+                packet[2] = 0x81;
+                if query.contains(label) {
+                    packet[3] = 0x80;
+                    packet[7] = 1;
+                    packet.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+                    packet.extend_from_slice(&[0x00, 0x00, 0x0e, 0x10, 0x00, 0x04]);
+                    packet.extend_from_slice(&[127, 0, 0, octet]);
+                } else {
+                    packet[3] = 0x83;
+                }
+                socket.send_to(&packet, &peer).await.unwrap();
+            }
+        });
+        address
+    }
+
+    fn blocklist(zone: &str, action: Option<&str>) -> DnsblConfig {
+        DnsblConfig {
+            zone: Some(zone.to_string()),
+            action: action.map(|action| action.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn invalid_entries() {
+        let mut replies = HashMap::new();
+        replies.insert(
+            "bad".to_string(),
+            DnsblReplyConfig {
+                action: Some("mark".to_string()),
+                reason: None,
+            },
+        );
+        let config = vec![
+            DnsblConfig::default(),
+            blocklist("a.example", Some("block")),
+            DnsblConfig {
+                replies: Some(replies),
+                ..blocklist("b.example", None)
+            },
+        ];
+
+        let dnsbl = Dnsbl::new(config, 60);
+        assert_eq!(dnsbl.lists.len(), 1);
+        assert_eq!(dnsbl.lists[0].zone, "b.example");
+        assert!(dnsbl.lists[0].action == Some(DnsblAction::Reject));
+    }
+
+    #[tokio::test]
+    async fn listed_address() {
+        let nameserver = spawn_blocklist("listed", 3).await;
+        let resolver = Resolver::new(Some(nameserver), 5);
+
+        let mut replies = HashMap::new();
+        replies.insert(
+            "2".to_string(),
+            DnsblReplyConfig {
+                action: Some("reject".to_string()),
+                reason: None,
+            },
+        );
+        replies.insert(
+            "127.0.0.3".to_string(),
+            DnsblReplyConfig {
+                action: Some("mark".to_string()),
+                reason: Some("Proxy".to_string()),
+            },
+        );
+        let config = vec![
+            blocklist("clean.example", Some("reject")),
+            DnsblConfig {
+                replies: Some(replies),
+                ..blocklist("listed.example.", None)
+            },
+        ];
+        let dnsbl = Dnsbl::new(config, 60);
+
+        let listing = dnsbl
+            .check(&resolver, "192.0.2.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(listing.zone, "listed.example");
+        assert_eq!(listing.reply, Ipv4Addr::new(127, 0, 0, 3));
+        assert!(listing.action == DnsblAction::Mark);
+        assert_eq!(listing.reason, "Proxy");
+    }
+
+    #[tokio::test]
+    async fn unlisted_address() {
+        let nameserver = spawn_blocklist("listed", 2).await;
+        let resolver = Resolver::new(Some(nameserver), 5);
+        let dnsbl = Dnsbl::new(vec![blocklist("clean.example", None)], 60);
+
+        assert!(dnsbl
+            .check(&resolver, "192.0.2.1".parse().unwrap())
+            .await
+            .is_none());
+    }
+}
@@ -0,0 +1,73 @@
+use crate::channel::ChannelState;
+use crate::client::ClientState;
+
+use std::fs::{create_dir_all, read_to_string, remove_file, rename, write};
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+static HANDOFF_FILE: &str = "handoff.json";
+
+/* NOTE(diath): The state passed from a running server to the binary it execs during an UPGRADE, the sockets themselves are inherited as file descriptors. */
+#[derive(Serialize, Deserialize)]
+pub struct ServerState {
+    pub created: i64,
+    pub listeners: Vec<ListenerState>,
+    pub clients: Vec<ClientState>,
+    pub channels: Vec<ChannelState>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListenerState {
+    pub address: SocketAddr,
+    pub fd: RawFd,
+}
+
+pub fn save(path: &str, state: &ServerState) -> Result<(), String> {
+    create_dir_all(path).map_err(|error| format!("Unable to create {}: {}", path, error))?;
+
+    let contents = serde_json::to_string(state)
+        .map_err(|error| format!("Unable to serialize the server state: {}", error))?;
+
+    let filename = Path::new(path).join(HANDOFF_FILE);
+    let temporary = Path::new(path).join(format!("{}.tmp", HANDOFF_FILE));
+    write(&temporary, contents)
+        .and_then(|_| rename(&temporary, &filename))
+        .map_err(|error| format!("Unable to write {}: {}", filename.display(), error))
+}
+
+/* NOTE(diath): The file is removed once read, so that a later plain restart does not try to resume stale sockets. */
+pub fn load(path: &str) -> Result<ServerState, String> {
+    let filename = Path::new(path).join(HANDOFF_FILE);
+    let contents = read_to_string(&filename)
+        .map_err(|error| format!("Unable to read {}: {}", filename.display(), error))?;
+    remove_file(&filename).ok();
+
+    serde_json::from_str(&contents)
+        .map_err(|error| format!("Unable to parse {}: {}", filename.display(), error))
+}
+
+/* NOTE(diath): Sockets are created with FD_CLOEXEC, it has to be cleared for the descriptor to survive the exec. */
+pub fn set_inheritable(fd: RawFd) -> Result<(), String> {
+    set_fd_flags(fd, |flags| flags & !libc::FD_CLOEXEC)
+        .map_err(|error| format!("Unable to pass descriptor {}: {}", fd, error))
+}
+
+/* NOTE(diath): Undoes set_inheritable when a handoff is abandoned, so that the descriptors do not leak into a later RESTART. */
+pub fn set_cloexec(fd: RawFd) -> Result<(), String> {
+    set_fd_flags(fd, |flags| flags | libc::FD_CLOEXEC)
+        .map_err(|error| format!("Unable to restore descriptor {}: {}", fd, error))
+}
+
+fn set_fd_flags<F: Fn(i32) -> i32>(fd: RawFd, update: F) -> std::io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, update(flags)) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
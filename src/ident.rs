@@ -0,0 +1,138 @@
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+/* NOTE(diath): RFC 1413 client, asks the identd on the connecting host which user owns the connection. */
+pub struct Ident {
+    pub port: u16,
+    pub timeout: Duration,
+}
+
+impl Ident {
+    pub fn new(port: u16, timeout: u64) -> Ident {
+        Ident {
+            port,
+            timeout: Duration::from_secs(timeout),
+        }
+    }
+
+    pub async fn lookup(&self, remote: SocketAddr, local: SocketAddr) -> Option<String> {
+        match timeout(self.timeout, self.query(remote, local)).await {
+            Ok(result) => result,
+            Err(_) => {
+                log::debug!("Ident query for {} timed out.", remote);
+                None
+            }
+        }
+    }
+
+    async fn query(&self, remote: SocketAddr, local: SocketAddr) -> Option<String> {
+        let mut stream = TcpStream::connect(SocketAddr::new(remote.ip(), self.port))
+            .await
+            .ok()?;
+        stream
+            .write_all(format!("{}, {}\r\n", remote.port(), local.port()).as_bytes())
+            .await
+            .ok()?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.ok()?;
+
+        parse_response(&line, remote.port(), local.port())
+    }
+}
+
+/* NOTE(diath): A successful reply looks like "<remote port>, <local port> : USERID : <system> : <user>". */
+fn parse_response(line: &str, remote_port: u16, local_port: u16) -> Option<String> {
+    let chunks = line.trim().splitn(4, ':').collect::<Vec<&str>>();
+    if chunks.len() != 4 || chunks[1].trim() != "USERID" {
+        return None;
+    }
+
+    let ports = chunks[0]
+        .split(',')
+        .map(|port| port.trim().parse::<u16>())
+        .collect::<Vec<_>>();
+    if ports.len() != 2 || ports[0] != Ok(remote_port) || ports[1] != Ok(local_port) {
+        return None;
+    }
+
+    let user = chunks[3]
+        .trim()
+        .chars()
+        .filter(|ch| ch.is_ascii_alphanumeric() || *ch == '-' || *ch == '_' || *ch == '.')
+        .take(10)
+        .collect::<String>();
+
+    if user.is_empty() {
+        None
+    } else {
+        Some(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn user_replies() {
+        assert_eq!(
+            parse_response("6193, 23 : USERID : UNIX : stjohns\r\n", 6193, 23),
+            Some("stjohns".to_string())
+        );
+        assert_eq!(
+            parse_response("6193,23:USERID:OTHER,UTF-8:stjohns", 6193, 23),
+            Some("stjohns".to_string())
+        );
+        assert_eq!(
+            parse_response("6193, 23 : USERID : UNIX : a:b c!d", 6193, 23),
+            Some("abcd".to_string())
+        );
+        assert_eq!(
+            parse_response("6193, 23 : USERID : UNIX : averyverylonguser", 6193, 23),
+            Some("averyveryl".to_string())
+        );
+    }
+
+    #[test]
+    fn rejected_replies() {
+        assert_eq!(parse_response("6193, 23 : ERROR : NO-USER", 6193, 23), None);
+        assert_eq!(
+            parse_response("6193, 24 : USERID : UNIX : stjohns", 6193, 23),
+            None
+        );
+        assert_eq!(
+            parse_response("6193 : USERID : UNIX : stjohns", 6193, 23),
+            None
+        );
+        assert_eq!(
+            parse_response("6193, 23 : USERID : UNIX : !!", 6193, 23),
+            None
+        );
+        assert_eq!(parse_response("", 6193, 23), None);
+    }
+
+    #[tokio::test]
+    async fn local_identd() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = tokio::io::split(stream);
+            let mut line = String::new();
+            BufReader::new(reader).read_line(&mut line).await.unwrap();
+            let reply = format!("{} : USERID : UNIX : diath\r\n", line.trim());
+            writer.write_all(reply.as_bytes()).await.unwrap();
+        });
+
+        let ident = Ident::new(port, 5);
+        let remote = "127.0.0.1:6193".parse().unwrap();
+        let local = "127.0.0.1:6667".parse().unwrap();
+        assert_eq!(ident.lookup(remote, local).await, Some("diath".to_string()));
+    }
+}
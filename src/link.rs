@@ -0,0 +1,892 @@
+use crate::channel::ChannelUserModes;
+use crate::client::{Client, RemoteOrigin, UserHost};
+use crate::config::LinkConfig;
+use crate::server::{MessageKind, Server};
+use crate::tags::{parse_tags, split_tags, Tag};
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use tokio::io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{oneshot, Mutex, RwLock};
+use tokio::time::{interval, timeout, Duration, Instant};
+
+use ircmsgprs::parser::{Message, Parser};
+
+/* NOTE(diath): Server to server protocol, a simplified take on TS6 where users and servers are referred to by name.
+
+    Handshake, the connecting side goes first and the other side answers once the credentials check out:
+        PASS <password>
+        SERVER <name> 1 :<description>
+
+    Burst, sent by both sides right after the handshake:
+        :<uplink> SERVER <name> <hops> :<description>
+        :<server> UID <nick> <nick ts> <user> <host> <real host> <ip> <user modes> <account or *> :<real name>
+        :<server> SJOIN <channel ts> <channel> <modes> [mode params...] :<status prefixes><nick> ...
+        :<server> TB <channel> <topic ts> <set by> :<topic>
+        :<nick> AWAY :<message>
+        :<server> EOB
+
+    Afterwards every change is passed on to all the other links as it happens:
+        :<server> SJOIN <channel ts> <channel> <modes> :<status prefixes><nick>
+        :<nick> NICK <new nick> <nick ts>
+        :<nick> QUIT :<reason>
+        :<server> KILL <nick> :<reason>
+        :<server> KILL <nick> <nick ts> :<reason>
+        :<nick> PART <channel> :<reason>
+        :<nick> KICK <channel> <nick> :<reason>
+        :<nick> PRIVMSG|NOTICE <target> :<text>
+        :<nick> TOPIC <channel> :<topic>
+        :<nick or server> MODE <channel> <changes> [params...]
+        :<nick> MODE <nick> +o|-o
+        :<nick> INVITE <nick> <channel>
+        :<nick> CHGHOST <user> <host>
+        :<nick> AWAY [:<message>]
+        :<server> SQUIT <name> :<reason>
+        PING, PONG and ERROR
+
+    Collisions are resolved by every server on its own:
+        - Nicks: the user with the older nick ts keeps the nick and the other one is removed, on a tie both are removed. A removed remote user is
+          killed with the KILL form that carries its nick ts, which only applies if the nick still has that ts, so that a server that has already
+          given the nick to the winner leaves it alone.
+        - Channels: the copy with the older channel ts wins and the modes and statuses of the newer one are dropped, on a tie they are merged.
+*/
+
+static LINK_TIMEOUT: u64 = 30;
+static PING_INTERVAL: u64 = 60;
+static PING_TIMEOUT: u64 = 180;
+
+pub struct Link {
+    pub name: String,
+    pub description: String,
+    pub address: SocketAddr,
+    server: Arc<Server>,
+    writer: Mutex<Option<WriteHalf<TcpStream>>>,
+    quit: Mutex<Option<oneshot::Sender<String>>>,
+}
+
+/* NOTE(diath): Initiates a link to a server from the config, the handshake is done here and the rest is shared with incoming links. */
+pub async fn connect(server: Arc<Server>, config: LinkConfig) {
+    let name = config.name.clone().unwrap_or_default();
+    if !server.begin_connect(&name).await {
+        return;
+    }
+
+    log::info!("Connecting to {}...", name);
+    let result = handshake(&server, &config).await;
+    server.end_connect(&name).await;
+
+    match result {
+        Ok((description, address, reader, writer)) => {
+            establish(server, name, description, address, reader, writer).await;
+        }
+        Err(error) => {
+            log::warn!("Link with {} failed: {}", name, error);
+            server
+                .broadcast_oper_notice(format!("Link with {} failed: {}", name, error))
+                .await;
+        }
+    }
+}
+
+async fn handshake(
+    server: &Server,
+    config: &LinkConfig,
+) -> Result<
+    (
+        String,
+        SocketAddr,
+        BufReader<ReadHalf<TcpStream>>,
+        WriteHalf<TcpStream>,
+    ),
+    String,
+> {
+    let name = config.name.as_deref().unwrap_or_default();
+    let host = config.host.as_deref().unwrap_or("127.0.0.1");
+    let port = config.port.unwrap_or(6667);
+    let password = config.password.as_deref().unwrap_or_default();
+
+    let stream = match timeout(
+        Duration::from_secs(LINK_TIMEOUT),
+        TcpStream::connect((host, port)),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(error)) => return Err(format!("Unable to connect to {}:{}: {}", host, port, error)),
+        Err(_) => return Err(format!("Connection to {}:{} timed out", host, port)),
+    };
+    let address = stream
+        .peer_addr()
+        .map_err(|error| format!("Unable to connect to {}:{}: {}", host, port, error))?;
+
+    let (reader, mut writer) = split(stream);
+    let mut reader = BufReader::new(reader);
+    writer
+        .write_all(
+            format!(
+                "PASS {}\r\nSERVER {} 1 :{}\r\n",
+                password, server.name, server.description
+            )
+            .as_bytes(),
+        )
+        .await
+        .map_err(|error| format!("Write error: {}", error))?;
+
+    /* NOTE(diath): The other side treats us as a client until it sees SERVER, so anything else it sends before (such as auth notices) is skipped. */
+    let mut parser = Parser::new();
+    let mut received_password = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match timeout(
+            Duration::from_secs(LINK_TIMEOUT),
+            reader.read_line(&mut line),
+        )
+        .await
+        {
+            Ok(Ok(0)) => return Err("Connection closed".to_string()),
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => return Err(format!("Read error: {}", error)),
+            Err(_) => return Err("Handshake timed out".to_string()),
+        }
+
+        let message = match parser.parse(line.trim_end_matches(&['\r', '\n'][..])) {
+            Some(message) => message,
+            None => continue,
+        };
+
+        match message.command.as_str() {
+            "PASS" => {
+                received_password = message.params.first().cloned();
+            }
+            "SERVER" => {
+                if message.params.len() < 3 {
+                    return Err("Invalid SERVER message".to_string());
+                }
+
+                if !message.params[0].eq_ignore_ascii_case(name) {
+                    return Err(format!("Unexpected server name {}", message.params[0]));
+                }
+
+                if received_password.as_deref() != Some(password) {
+                    return Err("Invalid link credentials".to_string());
+                }
+
+                return Ok((message.params[2].clone(), address, reader, writer));
+            }
+            "ERROR" => {
+                return Err(message.params.first().cloned().unwrap_or_default());
+            }
+            _ => {}
+        }
+    }
+}
+
+/* NOTE(diath): Takes over a client connection that has sent valid link credentials and answers with our own. */
+pub async fn accept(
+    server: Arc<Server>,
+    name: String,
+    description: String,
+    address: SocketAddr,
+    reader: BufReader<ReadHalf<TcpStream>>,
+    mut writer: WriteHalf<TcpStream>,
+) {
+    let password = match server.get_link_config(&name).await {
+        Some(config) => config.password.unwrap_or_default(),
+        None => return,
+    };
+
+    let result = writer
+        .write_all(
+            format!(
+                "PASS {}\r\nSERVER {} 1 :{}\r\n",
+                password, server.name, server.description
+            )
+            .as_bytes(),
+        )
+        .await;
+    if let Err(error) = result {
+        log::warn!("Link with {} failed: Write error: {}", name, error);
+        return;
+    }
+
+    establish(server, name, description, address, reader, writer).await;
+}
+
+async fn establish(
+    server: Arc<Server>,
+    name: String,
+    description: String,
+    address: SocketAddr,
+    reader: BufReader<ReadHalf<TcpStream>>,
+    writer: WriteHalf<TcpStream>,
+) {
+    let (quit_sender, quit_receiver) = oneshot::channel();
+    let link = Arc::new(Link {
+        name,
+        description,
+        address,
+        server: server.clone(),
+        writer: Mutex::new(Some(writer)),
+        quit: Mutex::new(Some(quit_sender)),
+    });
+
+    if !server.add_link(link.clone()).await {
+        log::warn!("Link with {} refused: server already exists.", link.name);
+        link.close("Server already exists").await;
+        return;
+    }
+
+    log::info!("Link with {} ({}) established.", link.name, link.address);
+    server
+        .broadcast_oper_notice(format!(
+            "Link with {} established ({})",
+            link.name,
+            link.address.ip()
+        ))
+        .await;
+
+    server.send_burst(&link).await;
+
+    let reason = link.run(reader, quit_receiver).await;
+    link.close(&reason).await;
+    server.remove_link(&link, &reason).await;
+}
+
+impl Link {
+    pub fn get_key(&self) -> String {
+        self.name.to_lowercase()
+    }
+
+    pub async fn send(&self, line: &str) {
+        if let Some(writer) = &mut *self.writer.lock().await {
+            if let Err(error) = writer.write_all(format!("{}\r\n", line).as_bytes()).await {
+                log::debug!("Failed to write to link {} ({}).", self.name, error);
+            }
+        }
+    }
+
+    /* NOTE(diath): Says goodbye to the other side and wakes up the reader, the link is torn down once it exits. */
+    pub async fn close(&self, reason: &str) {
+        self.send(&format!("ERROR :Closing Link: {}", reason)).await;
+
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            writer.flush().await.ok();
+            writer.shutdown().await.ok();
+        }
+
+        if let Some(quit) = self.quit.lock().await.take() {
+            quit.send(reason.to_string()).ok();
+        }
+    }
+
+    async fn run(
+        &self,
+        reader: BufReader<ReadHalf<TcpStream>>,
+        mut quit_receiver: oneshot::Receiver<String>,
+    ) -> String {
+        let mut parser = Parser::new();
+        let mut lines = reader.lines();
+        let mut ping = interval(Duration::from_secs(PING_INTERVAL));
+        let mut last_activity = Instant::now();
+
+        loop {
+            tokio::select! {
+                result = lines.next_line() => {
+                    match result {
+                        Ok(Some(line)) => {
+                            last_activity = Instant::now();
+
+                            let (tags, line) = split_tags(line.trim_end_matches('\r'));
+                            let tags = tags.map(parse_tags).unwrap_or_default();
+                            if let Some(message) = parser.parse(line) {
+                                log::debug!("Received link message from {}: {}", self.name, message);
+                                if let Err(reason) = self.on_message(line, message, tags).await {
+                                    return reason;
+                                }
+                            }
+                        }
+                        Ok(None) => return "Connection closed".to_string(),
+                        Err(error) => return format!("Read error: {}", error),
+                    }
+                }
+                _ = ping.tick() => {
+                    if last_activity.elapsed() > Duration::from_secs(PING_TIMEOUT) {
+                        return "Ping timeout".to_string();
+                    }
+
+                    self.send(&format!("PING :{}", self.server.name)).await;
+                }
+                reason = &mut quit_receiver => {
+                    return reason.unwrap_or_else(|_| "Link closed".to_string());
+                }
+            }
+        }
+    }
+
+    /* NOTE(diath): Returning an error closes the link with the given reason. */
+    async fn on_message(&self, line: &str, message: Message, tags: Vec<Tag>) -> Result<(), String> {
+        let source = message
+            .server
+            .clone()
+            .or_else(|| message.nick.clone())
+            .unwrap_or_default();
+        let params = message.params;
+
+        match message.command.as_str() {
+            "PING" => {
+                self.send(&format!(
+                    ":{} PONG {} :{}",
+                    self.server.name,
+                    self.server.name,
+                    params.first().map(|param| param.as_str()).unwrap_or("")
+                ))
+                .await;
+            }
+            "PONG" => {}
+            "ERROR" => {
+                return Err(format!(
+                    "Remote error: {}",
+                    params.first().map(|param| param.as_str()).unwrap_or("")
+                ));
+            }
+            "SERVER" => {
+                self.on_server(&source, params).await?;
+            }
+            "SQUIT" => {
+                self.on_squit(line, params).await?;
+            }
+            "EOB" => {
+                self.on_eob(&source).await;
+            }
+            "UID" => {
+                self.on_uid(line, &source, params).await;
+            }
+            "NICK" => {
+                self.on_nick(&source, params).await;
+            }
+            "QUIT" => {
+                self.on_quit(&source, params).await;
+            }
+            "KILL" => {
+                self.on_kill(line, &source, params).await;
+            }
+            "SJOIN" => {
+                self.on_sjoin(line, &source, params).await;
+            }
+            "PART" => {
+                self.on_part(&source, params).await;
+            }
+            "KICK" => {
+                self.on_kick(&source, params).await;
+            }
+            "PRIVMSG" => {
+                self.on_privmsg(&source, params, MessageKind::Privmsg, &tags)
+                    .await;
+            }
+            "NOTICE" => {
+                self.on_privmsg(&source, params, MessageKind::Notice, &tags)
+                    .await;
+            }
+            "TAGMSG" => {
+                self.on_privmsg(&source, params, MessageKind::Tagmsg, &tags)
+                    .await;
+            }
+            "TOPIC" => {
+                self.on_topic(&source, params).await;
+            }
+            "TB" => {
+                self.on_tb(line, &source, params).await;
+            }
+            "MODE" => {
+                self.on_mode(line, &source, params).await;
+            }
+            "INVITE" => {
+                self.on_invite(&source, params).await;
+            }
+            "CHGHOST" => {
+                self.on_chghost(&source, params).await;
+            }
+            "AWAY" => {
+                self.on_away(line, &source, params).await;
+            }
+            _ => {
+                log::debug!(
+                    "Link command {} from {} not implemented.",
+                    message.command,
+                    self.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /* NOTE(diath): Users can only be referred to through the link that introduced them, anything else is a desync or a fake. */
+    async fn get_client(&self, nick: &str) -> Option<Arc<Client>> {
+        let client = self.server.get_client(nick).await?;
+        match &client.remote {
+            Some(remote) if remote.link == self.get_key() => Some(client),
+            _ => {
+                log::debug!("Link {} used {} from the wrong direction.", self.name, nick);
+                None
+            }
+        }
+    }
+
+    async fn is_behind(&self, name: &str) -> bool {
+        self.server.get_server_link(name).await == Some(self.get_key())
+    }
+
+    /* NOTE(diath): Stands in for a remote server as the source of channel changes, it is exempt from the permission checks. */
+    fn get_server_client(&self, name: &str) -> Client {
+        let mut client = Client::new_remote(
+            self.server.clone(),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            RemoteOrigin {
+                server: name.to_string(),
+                link: self.get_key(),
+            },
+        );
+        client.nick = Mutex::new(name.to_string());
+        client.operator = Mutex::new(true);
+        client
+    }
+
+    async fn collide(&self, client: &Client) {
+        let nick = client.nick.lock().await.to_string();
+        log::info!("Nick collision on {} with {}.", nick, self.name);
+        self.server
+            .broadcast_oper_notice(format!("Nick collision on {} with {}", nick, self.name))
+            .await;
+
+        if client.is_remote() {
+            let ts = *client.nick_ts.read().await;
+            self.server
+                .propagate_except(None, self.get_collision_kill(&nick, ts))
+                .await;
+            self.server
+                .remove_remote_client(client, "Nick collision")
+                .await;
+        } else {
+            client.kill("Nick collision").await;
+        }
+    }
+
+    /* NOTE(diath): Removes the losing user from the side of the link that introduced it or changed to its nick, it is never added here. */
+    async fn reject_collision(&self, nick: &str, ts: i64) {
+        log::info!("Nick collision on {} with {}.", nick, self.name);
+        self.send(&self.get_collision_kill(nick, ts)).await;
+    }
+
+    fn get_collision_kill(&self, nick: &str, ts: i64) -> String {
+        format!(":{} KILL {} {} :Nick collision", self.server.name, nick, ts)
+    }
+
+    async fn on_server(&self, source: &str, params: Vec<String>) -> Result<(), String> {
+        if params.len() < 3 || !self.is_behind(source).await {
+            return Ok(());
+        }
+
+        let hops = params[1].parse::<u32>().unwrap_or(1);
+        if !self
+            .server
+            .add_remote_server(&params[0], &params[2], hops, source, &self.get_key())
+            .await
+        {
+            return Err(format!("Server {} already exists", params[0]));
+        }
+
+        self.server
+            .propagate_except(
+                Some(&self.get_key()),
+                format!(
+                    ":{} SERVER {} {} :{}",
+                    source,
+                    params[0],
+                    hops + 1,
+                    params[2]
+                ),
+            )
+            .await;
+
+        Ok(())
+    }
+
+    async fn on_squit(&self, line: &str, params: Vec<String>) -> Result<(), String> {
+        let name = match params.first() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let reason = params.get(1).map(|reason| reason.as_str()).unwrap_or("");
+
+        if name.eq_ignore_ascii_case(&self.server.name) || name.eq_ignore_ascii_case(&self.name) {
+            return Err(format!("SQUIT: {}", reason));
+        }
+
+        if !self.is_behind(name).await {
+            return Ok(());
+        }
+
+        self.server.remove_remote_server(name).await;
+        self.server
+            .propagate_except(Some(&self.get_key()), line.to_string())
+            .await;
+
+        Ok(())
+    }
+
+    async fn on_eob(&self, source: &str) {
+        if source.eq_ignore_ascii_case(&self.name) {
+            log::info!("Link with {} finished bursting.", self.name);
+            self.server
+                .broadcast_oper_notice(format!("Link with {} finished bursting", self.name))
+                .await;
+        }
+    }
+
+    async fn on_uid(&self, line: &str, source: &str, params: Vec<String>) {
+        if params.len() < 9 || !self.is_behind(source).await {
+            return;
+        }
+
+        let nick = params[0].to_string();
+        let ts = params[1].parse::<i64>().unwrap_or(0);
+
+        if let Some(existing) = self.server.get_client(&nick).await {
+            /* NOTE(diath): A user who registered while the link was being set up is introduced twice. */
+            if let Some(remote) = &existing.remote {
+                if remote.link == self.get_key() && *existing.nick_ts.read().await == ts {
+                    return;
+                }
+            }
+
+            if !*existing.registered.read().await {
+                existing.kill("Nick collision").await;
+            } else {
+                let existing_ts = *existing.nick_ts.read().await;
+                if existing_ts < ts {
+                    self.reject_collision(&nick, ts).await;
+                    return;
+                }
+
+                self.collide(&existing).await;
+                if existing_ts == ts {
+                    self.reject_collision(&nick, ts).await;
+                    return;
+                }
+            }
+        }
+
+        let ip = params[5]
+            .parse::<IpAddr>()
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let mut client = Client::new_remote(
+            self.server.clone(),
+            SocketAddr::new(ip, 0),
+            RemoteOrigin {
+                server: source.to_string(),
+                link: self.get_key(),
+            },
+        );
+
+        let modes = &params[6];
+        client.nick = Mutex::new(nick);
+        client.nick_ts = RwLock::new(ts);
+        client.user = Mutex::new(params[2].to_string());
+        if params[4] != params[5] {
+            client.hostname = Mutex::new(Some(params[4].to_string()));
+        }
+        client.host = Mutex::new(if modes.contains('x') {
+            UserHost::VHost(params[3].to_string())
+        } else {
+            UserHost::Hostname(params[3].to_string())
+        });
+        client.operator = Mutex::new(modes.contains('o'));
+        if params[7] != "*" {
+            client.account = Mutex::new(Some(params[7].to_string()));
+        }
+        client.real_name = Mutex::new(params[8].to_string());
+        client.last_activity = RwLock::new(Utc::now().timestamp());
+
+        self.server.add_remote_client(Arc::new(client)).await;
+        self.server
+            .propagate_except(Some(&self.get_key()), line.to_string())
+            .await;
+    }
+
+    async fn on_nick(&self, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        let client = match self.get_client(source).await {
+            Some(client) => client,
+            None => return,
+        };
+
+        let nick = &params[0];
+        let ts = params[1].parse::<i64>().unwrap_or(0);
+
+        if let Some(existing) = self.server.get_client(nick).await {
+            if !Arc::ptr_eq(&existing, &client) {
+                if !*existing.registered.read().await {
+                    existing.kill("Nick collision").await;
+                } else {
+                    let existing_ts = *existing.nick_ts.read().await;
+                    if existing_ts == ts {
+                        self.collide(&existing).await;
+                    }
+
+                    /* NOTE(diath): The other servers still know the user by its old nick, the link by its new one. */
+                    if existing_ts <= ts {
+                        self.collide(&client).await;
+                        self.reject_collision(nick, ts).await;
+                        return;
+                    }
+
+                    self.collide(&existing).await;
+                }
+            }
+        }
+
+        (*client.nick_ts.write().await) = ts;
+        self.server.change_nick(&client, nick).await;
+    }
+
+    async fn on_quit(&self, source: &str, params: Vec<String>) {
+        if let Some(client) = self.get_client(source).await {
+            let reason = params.first().map(|reason| reason.as_str()).unwrap_or("");
+            self.server.broadcast_quit(&client, reason).await;
+            self.server.remove_client(&client).await;
+        }
+    }
+
+    async fn on_kill(&self, line: &str, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        let target = match self.server.get_client(&params[0]).await {
+            Some(target) => target,
+            None => return,
+        };
+
+        /* NOTE(diath): A collision kill only applies to the user that still holds the nick with the given ts. */
+        if params.len() > 2 && params[1].parse::<i64>().ok() != Some(*target.nick_ts.read().await) {
+            return;
+        }
+
+        let reason = &params[params.len() - 1];
+        log::info!("{} was killed by {} ({}).", params[0], source, reason);
+        if target.is_remote() {
+            self.server
+                .propagate_except(Some(&self.get_key()), line.to_string())
+                .await;
+            self.server.remove_remote_client(&target, reason).await;
+        } else {
+            target.kill(reason).await;
+        }
+    }
+
+    async fn on_sjoin(&self, line: &str, source: &str, params: Vec<String>) {
+        if params.len() < 4 || !self.is_behind(source).await {
+            return;
+        }
+
+        let ts = params[0].parse::<i64>().unwrap_or(0);
+        let modes = params[2..params.len() - 1].to_vec();
+
+        let mut members = vec![];
+        for token in params[params.len() - 1].split_whitespace() {
+            let nick = token.trim_start_matches(|ch| "~&@%+".contains(ch));
+            let prefixes = &token[..token.len() - nick.len()];
+            if let Some(client) = self.get_client(nick).await {
+                members.push((client, ChannelUserModes::from_prefixes(prefixes)));
+            }
+        }
+
+        let client = self.get_server_client(source);
+        self.server
+            .sjoin(&client, ts, &params[1], modes, members)
+            .await;
+        self.server
+            .propagate_except(Some(&self.get_key()), line.to_string())
+            .await;
+    }
+
+    async fn on_part(&self, source: &str, params: Vec<String>) {
+        if params.is_empty() {
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            let reason = params.get(1).map(|reason| reason.as_str()).unwrap_or("");
+            if self.server.part_channel(&client, &params[0], reason).await {
+                let name = params[0].to_lowercase();
+                client
+                    .channels
+                    .lock()
+                    .await
+                    .retain(|channel| channel.to_lowercase() != name);
+            }
+        }
+    }
+
+    async fn on_kick(&self, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            let reason = params.get(2).cloned().unwrap_or_default();
+            if self
+                .server
+                .kick_channel(&client, &params[0], &params[1], reason)
+                .await
+            {
+                if let Some(kicked) = self.server.get_client(&params[1]).await {
+                    let name = params[0].to_lowercase();
+                    kicked
+                        .channels
+                        .lock()
+                        .await
+                        .retain(|channel| channel.to_lowercase() != name);
+                }
+            }
+        }
+    }
+
+    async fn on_privmsg(&self, source: &str, params: Vec<String>, kind: MessageKind, tags: &[Tag]) {
+        if params.is_empty() || (params.len() < 2 && kind != MessageKind::Tagmsg) {
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            let target = &params[0];
+            let text = params.get(1).cloned().unwrap_or_default();
+            if target.starts_with('#') {
+                self.server
+                    .forward_channel_message(kind, &client, target, text, tags)
+                    .await;
+            } else {
+                self.server
+                    .forward_message(kind, &client, target, text, tags)
+                    .await;
+            }
+        }
+    }
+
+    async fn on_topic(&self, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            self.server
+                .set_channel_topic(&client, &params[0], params[1].clone())
+                .await;
+        }
+    }
+
+    async fn on_tb(&self, line: &str, source: &str, params: Vec<String>) {
+        if params.len() < 4 || !self.is_behind(source).await {
+            return;
+        }
+
+        let set_at = params[1].parse::<u64>().unwrap_or(0);
+        if self
+            .server
+            .burst_topic(source, &params[0], set_at, &params[2], &params[3])
+            .await
+        {
+            self.server
+                .propagate_except(Some(&self.get_key()), line.to_string())
+                .await;
+        }
+    }
+
+    async fn on_mode(&self, line: &str, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        let target = &params[0];
+        if target.starts_with('#') {
+            if self.server.get_server_link(source).await.is_some() {
+                if self.is_behind(source).await {
+                    let client = self.get_server_client(source);
+                    self.server
+                        .server_channel_mode(&client, target, params[1..].to_vec())
+                        .await;
+                }
+            } else if let Some(client) = self.get_client(source).await {
+                self.server
+                    .handle_channel_mode(&client, target, params[1..].to_vec())
+                    .await;
+            }
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            if *client.nick.lock().await != *target {
+                return;
+            }
+
+            match params[1].as_str() {
+                "+o" => {
+                    (*client.operator.lock().await) = true;
+                }
+                "-o" => {
+                    (*client.operator.lock().await) = false;
+                }
+                _ => return,
+            }
+
+            self.server
+                .propagate_except(Some(&self.get_key()), line.to_string())
+                .await;
+        }
+    }
+
+    async fn on_invite(&self, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            self.server
+                .invite_channel(&client, &params[1], &params[0])
+                .await;
+        }
+    }
+
+    async fn on_chghost(&self, source: &str, params: Vec<String>) {
+        if params.len() < 2 {
+            return;
+        }
+
+        if let Some(client) = self.get_client(source).await {
+            self.server
+                .change_host(
+                    &client,
+                    Some(params[0].to_string()),
+                    UserHost::VHost(params[1].to_string()),
+                )
+                .await;
+        }
+    }
+
+    async fn on_away(&self, line: &str, source: &str, params: Vec<String>) {
+        if let Some(client) = self.get_client(source).await {
+            (*client.away_message.lock().await) = params.first().cloned().unwrap_or_default();
+            self.server
+                .propagate_except(Some(&self.get_key()), line.to_string())
+                .await;
+        }
+    }
+}
@@ -3,23 +3,39 @@ mod channel;
 mod client;
 mod cloak;
 mod config;
+mod dns;
+mod dnsbl;
+mod handoff;
+mod ident;
+mod link;
 mod mask;
+mod module;
+mod modules;
 mod replies;
+mod script;
 mod server;
 mod service;
 mod services;
+mod storage;
+mod tags;
 
 use chrono;
+use std::env;
 use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::process;
 
 use env_logger;
 use log;
 
 use ayame::*;
-use server::Server;
+use config::Options;
+use server::{Server, Shutdown};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::parse();
+
     let default_log_filter = if cfg!(debug_assertions) {
         log::LevelFilter::Debug
     } else {
@@ -36,9 +52,57 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 record.args()
             )
         })
-        .filter(None, default_log_filter)
+        .filter(None, options.log_level.unwrap_or(default_log_filter))
         .init();
 
     log::info!("{} {} ({})", IRCD_NAME, IRCD_VERSION, IRCD_REPOSITORY);
-    return Server::new().accept().await;
+
+    if options.check_config {
+        match Server::read_config(&options.config_path) {
+            Ok(config) => {
+                let problems = Server::check_config(config);
+                if problems.is_empty() {
+                    println!("{}: OK", options.config_path);
+                    return Ok(());
+                }
+
+                for problem in problems {
+                    eprintln!("{}", problem);
+                }
+                process::exit(1);
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        }
+    }
+
+    let server = match Server::new(&options) {
+        Ok(server) => server,
+        Err(error) => {
+            log::error!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    let reason = server.accept().await?;
+    if reason == Shutdown::Restart || reason == Shutdown::Upgrade {
+        /* NOTE(diath): Replaces the process with a fresh copy of the binary, exec only returns on failure. */
+        let mut arguments = env::args()
+            .skip(1)
+            .filter(|argument| argument != "--upgrade")
+            .collect::<Vec<String>>();
+        if reason == Shutdown::Upgrade {
+            arguments.push("--upgrade".to_string());
+        }
+
+        let error = process::Command::new(env::current_exe()?)
+            .args(arguments)
+            .exec();
+        log::error!("Failed to restart: {}", error);
+        process::exit(1);
+    }
+
+    Ok(())
 }
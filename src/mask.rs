@@ -5,41 +5,60 @@ pub fn check_mask(mask: &str, value: &str) -> bool {
         A backslash escapes a question mark or an asterisk.
         Any other character is matched literally.
     */
+    let mask = mask.chars().collect::<Vec<char>>();
+    let value = value.chars().collect::<Vec<char>>();
 
-    /* TODO(diath): Add suport for *. */
-    let value = value.to_string().into_bytes();
-    let mut index = 0 as usize;
-    let mut escape = false;
+    let mut mask_index = 0;
+    let mut value_index = 0;
 
-    for chr in mask.chars() {
-        if index >= value.len() {
-            return false;
-        }
+    /* NOTE(diath): Position right after the last asterisk and the value position it is currently matched up to, used to backtrack on a mismatch. */
+    let mut backtrack: Option<(usize, usize)> = None;
 
-        match chr {
-            '?' => {
-                if escape {
-                    if chr != value[index] as char {
-                        return false;
+    while value_index < value.len() {
+        if mask_index < mask.len() {
+            match mask[mask_index] {
+                '*' => {
+                    mask_index += 1;
+                    backtrack = Some((mask_index, value_index));
+                    continue;
+                }
+                '?' => {
+                    mask_index += 1;
+                    value_index += 1;
+                    continue;
+                }
+                '\\' if mask_index + 1 < mask.len() => {
+                    if mask[mask_index + 1] == value[value_index] {
+                        mask_index += 2;
+                        value_index += 1;
+                        continue;
                     }
-
-                    escape = false;
                 }
-
-                index += 1;
-            }
-            '\\' => {
-                escape = true;
-            }
-            _ => {
-                if chr != value[index] as char {
-                    return false;
+                chr => {
+                    if chr == value[value_index] {
+                        mask_index += 1;
+                        value_index += 1;
+                        continue;
+                    }
                 }
+            }
+        }
 
-                index += 1;
+        match backtrack {
+            Some((next_mask_index, matched_index)) => {
+                mask_index = next_mask_index;
+                value_index = matched_index + 1;
+                backtrack = Some((next_mask_index, value_index));
+            }
+            None => {
+                return false;
             }
         }
     }
 
-    true
+    while mask_index < mask.len() && mask[mask_index] == '*' {
+        mask_index += 1;
+    }
+
+    mask_index == mask.len()
 }
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::ayame::IRCD_CAPABILITIES;
+use crate::client::Client;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CommandScope {
+    /* NOTE(diath): Only before the registration is complete, such as SERVER. */
+    Unregistered,
+    Registered,
+    Any,
+}
+
+pub struct ModuleCommand {
+    pub name: &'static str,
+    pub scope: CommandScope,
+}
+
+pub struct ModuleNumeric {
+    pub name: &'static str,
+    pub code: u16,
+}
+
+/* NOTE(diath): The result of a before hook, a denied message is dropped without a reply so the hook has to send its own error. */
+pub enum HookResult {
+    Continue,
+    Deny,
+    Modify(Message),
+}
+
+#[async_trait]
+pub trait Module: Send + Sync {
+    fn get_name(&self) -> &'static str;
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        &[]
+    }
+
+    fn get_numerics(&self) -> &'static [ModuleNumeric] {
+        &[]
+    }
+
+    fn get_capabilities(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    async fn on_command(&self, _client: &Client, _message: Message) {}
+
+    /* NOTE(diath): Called for every message of a client before it is handled, in the order the modules were registered. */
+    async fn before_message(&self, _client: &Client, _message: &Message) -> HookResult {
+        HookResult::Continue
+    }
+
+    /* NOTE(diath): Called after a message was handled by its command, but not for denied or unknown commands. */
+    async fn after_message(&self, _client: &Client, _message: &Message) {}
+}
+
+struct CommandEntry {
+    scope: CommandScope,
+    module: Arc<dyn Module>,
+}
+
+pub struct ModuleRegistry {
+    modules: Vec<Arc<dyn Module>>,
+    commands: HashMap<String, CommandEntry>,
+    numerics: HashMap<String, u16>,
+    capabilities: Vec<&'static str>,
+}
+
+pub enum Dispatch {
+    Handled,
+    NotRegistered,
+    UnknownCommand,
+}
+
+impl ModuleRegistry {
+    pub fn new(modules: Vec<Arc<dyn Module>>) -> ModuleRegistry {
+        let mut registry = ModuleRegistry {
+            modules: vec![],
+            commands: HashMap::new(),
+            numerics: HashMap::new(),
+            capabilities: IRCD_CAPABILITIES.to_vec(),
+        };
+
+        for module in modules {
+            registry.register(module);
+        }
+
+        registry
+    }
+
+    /* NOTE(diath): A command, numeric or capability that is already taken is skipped with a warning, the first module to claim it wins. */
+    fn register(&mut self, module: Arc<dyn Module>) {
+        let name = module.get_name();
+        for command in module.get_commands().iter() {
+            if self.commands.contains_key(command.name) {
+                log::warn!(
+                    "Module {} tried to register command {} which is already taken.",
+                    name,
+                    command.name
+                );
+                continue;
+            }
+
+            self.commands.insert(
+                command.name.to_string(),
+                CommandEntry {
+                    scope: command.scope,
+                    module: module.clone(),
+                },
+            );
+        }
+
+        for numeric in module.get_numerics().iter() {
+            if self.get_numeric(numeric.name).is_some() {
+                log::warn!(
+                    "Module {} tried to register numeric {} which is already taken.",
+                    name,
+                    numeric.name
+                );
+                continue;
+            }
+
+            self.numerics.insert(numeric.name.to_string(), numeric.code);
+        }
+
+        for capability in module.get_capabilities().iter() {
+            if !self.has_capability(capability) {
+                self.capabilities.push(capability);
+            }
+        }
+
+        log::debug!("Module {} registered.", name);
+        self.modules.push(module);
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.commands.contains_key(&name.to_ascii_uppercase())
+    }
+
+    /* NOTE(diath): Only for numerics that out-of-tree modules bring along, the built-in ones belong in NumericReply. */
+    pub fn get_numeric(&self, name: &str) -> Option<u16> {
+        self.numerics.get(name).copied()
+    }
+
+    pub fn get_capabilities(&self) -> &[&'static str] {
+        &self.capabilities
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.contains(&name)
+    }
+
+    pub async fn dispatch(&self, client: &Client, mut message: Message) -> Dispatch {
+        for module in self.modules.iter() {
+            match module.before_message(client, &message).await {
+                HookResult::Continue => {}
+                HookResult::Deny => return Dispatch::Handled,
+                HookResult::Modify(modified) => message = modified,
+            }
+        }
+
+        let registered = *client.registered.read().await;
+        let entry = match self.commands.get(&message.command.to_ascii_uppercase()) {
+            Some(entry) => entry,
+            None if registered => return Dispatch::UnknownCommand,
+            None => return Dispatch::NotRegistered,
+        };
+
+        match (entry.scope, registered) {
+            (CommandScope::Unregistered, true) => return Dispatch::UnknownCommand,
+            (CommandScope::Registered, false) => return Dispatch::NotRegistered,
+            _ => {}
+        }
+
+        let copy = copy_message(&message);
+        entry.module.on_command(client, message).await;
+
+        for module in self.modules.iter() {
+            module.after_message(client, &copy).await;
+        }
+
+        Dispatch::Handled
+    }
+}
+
+fn copy_message(message: &Message) -> Message {
+    Message {
+        server: message.server.clone(),
+        nick: message.nick.clone(),
+        user: message.user.clone(),
+        host: message.host.clone(),
+        command: message.command.clone(),
+        params: message.params.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestModule;
+
+    static TEST_NUMERICS: &[ModuleNumeric] = &[ModuleNumeric {
+        name: "RPL_TEST",
+        code: 900,
+    }];
+
+    #[async_trait]
+    impl Module for TestModule {
+        fn get_name(&self) -> &'static str {
+            "test"
+        }
+
+        fn get_numerics(&self) -> &'static [ModuleNumeric] {
+            TEST_NUMERICS
+        }
+
+        fn get_capabilities(&self) -> &'static [&'static str] {
+            &["example.org/test", "chghost"]
+        }
+    }
+
+    struct OtherModule;
+
+    static OTHER_NUMERICS: &[ModuleNumeric] = &[ModuleNumeric {
+        name: "RPL_TEST",
+        code: 901,
+    }];
+
+    #[async_trait]
+    impl Module for OtherModule {
+        fn get_name(&self) -> &'static str {
+            "other"
+        }
+
+        fn get_numerics(&self) -> &'static [ModuleNumeric] {
+            OTHER_NUMERICS
+        }
+
+        fn get_capabilities(&self) -> &'static [&'static str] {
+            &["example.org/test"]
+        }
+    }
+
+    #[test]
+    fn numerics_and_capabilities() {
+        let registry = ModuleRegistry::new(vec![Arc::new(TestModule), Arc::new(OtherModule)]);
+
+        assert_eq!(registry.get_numeric("RPL_TEST"), Some(900));
+        assert_eq!(registry.get_numeric("RPL_OTHER"), None);
+        assert!(registry.has_capability("example.org/test"));
+        assert!(registry.has_capability("chghost"));
+        assert!(!registry.has_capability("example.org/other"));
+
+        /* NOTE(diath): This is the list CAP LS sends, the built-in capabilities come first and each one is listed once. */
+        assert_eq!(
+            registry.get_capabilities().join(" "),
+            format!("{} example.org/test", IRCD_CAPABILITIES.join(" "))
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{HookResult, Module};
+use crate::replies::NumericReply;
+
+pub struct AliasModule;
+
+static ALIASES: &[(&str, &str)] = &[
+    ("NICKSERV", "NickServ"),
+    ("NS", "NickServ"),
+    ("HOSTSERV", "HostServ"),
+    ("HS", "HostServ"),
+    ("MEMOSERV", "MemoServ"),
+    ("MS", "MemoServ"),
+    ("OPERSERV", "OperServ"),
+    ("OS", "OperServ"),
+];
+
+#[async_trait]
+impl Module for AliasModule {
+    fn get_name(&self) -> &'static str {
+        "alias"
+    }
+
+    /* NOTE(diath): The /NICKSERV style commands are rewritten to SQUERY, they do not use a trailing param so the text is taken from the raw line to keep its spacing. */
+    async fn before_message(&self, client: &Client, message: &Message) -> HookResult {
+        if client.is_login_required().await {
+            return self.before_login(client, message).await;
+        }
+
+        let service = match ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(&message.command))
+        {
+            Some((_, service)) => *service,
+            None => return HookResult::Continue,
+        };
+
+        if !*client.registered.read().await {
+            return HookResult::Continue;
+        }
+
+        if !client.server.is_service_nick(service) {
+            client
+                .send_numeric_reply(
+                    NumericReply::ErrServicesDown,
+                    format!("{} :Services are currently unavailable", service),
+                )
+                .await;
+            return HookResult::Deny;
+        }
+
+        HookResult::Modify(Message {
+            command: "SQUERY".to_string(),
+            params: vec![service.to_string(), client.get_raw_params().await],
+            ..Default::default()
+        })
+    }
+}
+
+impl AliasModule {
+    /* NOTE(diath): Clients held back by a DNSBL listing may only message NickServ, which is not a command before registration. */
+    async fn before_login(&self, client: &Client, message: &Message) -> HookResult {
+        let text = match message.command.to_ascii_uppercase().as_str() {
+            "NICKSERV" | "NS" => client.get_raw_params().await,
+            "PRIVMSG" | "SQUERY"
+                if message.params.len() > 1
+                    && message.params[0].eq_ignore_ascii_case("NickServ") =>
+            {
+                message.params[1].to_string()
+            }
+            _ => return HookResult::Continue,
+        };
+
+        client.identify_before_registration(&text).await;
+        HookResult::Deny
+    }
+}
@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{CommandScope, Module, ModuleCommand};
+
+pub struct ChannelModule;
+
+static COMMANDS: &[ModuleCommand] = &[
+    ModuleCommand {
+        name: "JOIN",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "PART",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "TOPIC",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "NAMES",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "LIST",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "INVITE",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "KICK",
+        scope: CommandScope::Registered,
+    },
+];
+
+#[async_trait]
+impl Module for ChannelModule {
+    fn get_name(&self) -> &'static str {
+        "channel"
+    }
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, message: Message) {
+        match message.command.to_ascii_uppercase().as_str() {
+            "JOIN" => client.on_join(message).await,
+            "PART" => client.on_part(message).await,
+            "TOPIC" => client.on_topic(message).await,
+            "NAMES" => client.on_names(message).await,
+            "LIST" => client.on_list(message).await,
+            "INVITE" => client.on_invite(message).await,
+            "KICK" => client.on_kick(message).await,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{CommandScope, Module, ModuleCommand};
+use crate::server::MessageKind;
+
+pub struct MessagingModule;
+
+static COMMANDS: &[ModuleCommand] = &[
+    ModuleCommand {
+        name: "PRIVMSG",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "NOTICE",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "TAGMSG",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "SQUERY",
+        scope: CommandScope::Registered,
+    },
+];
+
+#[async_trait]
+impl Module for MessagingModule {
+    fn get_name(&self) -> &'static str {
+        "messaging"
+    }
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, message: Message) {
+        match message.command.to_ascii_uppercase().as_str() {
+            "PRIVMSG" => client.on_privmsg(message, MessageKind::Privmsg).await,
+            "NOTICE" => client.on_privmsg(message, MessageKind::Notice).await,
+            "TAGMSG" => client.on_privmsg(message, MessageKind::Tagmsg).await,
+            "SQUERY" => client.on_squery(message).await,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{CommandScope, Module, ModuleCommand};
+
+pub struct MiscModule;
+
+static COMMANDS: &[ModuleCommand] = &[
+    ModuleCommand {
+        name: "MODE",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "PING",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "PONG",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "AWAY",
+        scope: CommandScope::Registered,
+    },
+];
+
+#[async_trait]
+impl Module for MiscModule {
+    fn get_name(&self) -> &'static str {
+        "misc"
+    }
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, message: Message) {
+        match message.command.to_ascii_uppercase().as_str() {
+            "MODE" => client.on_mode(message).await,
+            "PING" => client.on_ping(message).await,
+            "PONG" => client.on_pong(message).await,
+            "AWAY" => client.on_away(message).await,
+            _ => {}
+        }
+    }
+}
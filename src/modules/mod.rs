@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use crate::module::Module;
+
+pub mod alias;
+pub mod channel;
+pub mod messaging;
+pub mod misc;
+pub mod oper;
+pub mod query;
+pub mod registration;
+pub mod script;
+
+/* NOTE(diath): The modules compiled into the server, extension modules are added to this list. The hooks run in this order. */
+pub fn get_modules() -> Vec<Arc<dyn Module>> {
+    vec![
+        Arc::new(alias::AliasModule),
+        Arc::new(registration::RegistrationModule),
+        Arc::new(channel::ChannelModule),
+        Arc::new(messaging::MessagingModule),
+        Arc::new(query::QueryModule),
+        Arc::new(oper::OperModule),
+        Arc::new(misc::MiscModule),
+        Arc::new(script::ScriptModule),
+    ]
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{CommandScope, Module, ModuleCommand};
+use crate::server::Shutdown;
+
+pub struct OperModule;
+
+static COMMANDS: &[ModuleCommand] = &[
+    ModuleCommand {
+        name: "REHASH",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "DIE",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "RESTART",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "UPGRADE",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "CONNECT",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "SQUIT",
+        scope: CommandScope::Registered,
+    },
+];
+
+#[async_trait]
+impl Module for OperModule {
+    fn get_name(&self) -> &'static str {
+        "oper"
+    }
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, message: Message) {
+        match message.command.to_ascii_uppercase().as_str() {
+            "REHASH" => client.on_rehash().await,
+            "DIE" => client.on_shutdown(Shutdown::Die).await,
+            "RESTART" => client.on_shutdown(Shutdown::Restart).await,
+            "UPGRADE" => client.on_shutdown(Shutdown::Upgrade).await,
+            "CONNECT" => client.on_connect(message).await,
+            "SQUIT" => client.on_squit(message).await,
+            _ => {}
+        }
+    }
+}
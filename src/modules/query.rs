@@ -0,0 +1,103 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{CommandScope, Module, ModuleCommand};
+use crate::replies::NumericReply;
+
+pub struct QueryModule;
+
+static COMMANDS: &[ModuleCommand] = &[
+    ModuleCommand {
+        name: "MOTD",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "VERSION",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "STATS",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "TIME",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "LINKS",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "WHO",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "WHOIS",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "WHOWAS",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "USERHOST",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "ISON",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "SUMMON",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "USERS",
+        scope: CommandScope::Registered,
+    },
+];
+
+#[async_trait]
+impl Module for QueryModule {
+    fn get_name(&self) -> &'static str {
+        "query"
+    }
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, message: Message) {
+        match message.command.to_ascii_uppercase().as_str() {
+            "MOTD" => client.on_motd(message).await,
+            "VERSION" => client.on_version(message).await,
+            "STATS" => client.on_stats(message).await,
+            "TIME" => client.on_time(message).await,
+            "LINKS" => client.on_links().await,
+            "WHO" => client.on_who(message).await,
+            "WHOIS" => client.on_whois(message).await,
+            "WHOWAS" => client.on_whowas(message).await,
+            "USERHOST" => client.on_userhost(message).await,
+            "ISON" => client.on_ison(message).await,
+            "SUMMON" => {
+                client
+                    .send_numeric_reply(
+                        NumericReply::ErrSummonDisabled,
+                        ":SUMMON has been disabled".to_string(),
+                    )
+                    .await;
+            }
+            "USERS" => {
+                client
+                    .send_numeric_reply(
+                        NumericReply::ErrUsersDisabled,
+                        ":USERS has been disabled".to_string(),
+                    )
+                    .await;
+            }
+            _ => {}
+        }
+    }
+}
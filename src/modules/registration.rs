@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{CommandScope, Module, ModuleCommand};
+
+pub struct RegistrationModule;
+
+static COMMANDS: &[ModuleCommand] = &[
+    ModuleCommand {
+        name: "CAP",
+        scope: CommandScope::Any,
+    },
+    ModuleCommand {
+        name: "PASS",
+        scope: CommandScope::Any,
+    },
+    ModuleCommand {
+        name: "NICK",
+        scope: CommandScope::Any,
+    },
+    ModuleCommand {
+        name: "USER",
+        scope: CommandScope::Any,
+    },
+    ModuleCommand {
+        name: "SERVER",
+        scope: CommandScope::Unregistered,
+    },
+    ModuleCommand {
+        name: "OPER",
+        scope: CommandScope::Registered,
+    },
+    ModuleCommand {
+        name: "QUIT",
+        scope: CommandScope::Registered,
+    },
+];
+
+#[async_trait]
+impl Module for RegistrationModule {
+    fn get_name(&self) -> &'static str {
+        "registration"
+    }
+
+    fn get_commands(&self) -> &'static [ModuleCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, message: Message) {
+        match message.command.to_ascii_uppercase().as_str() {
+            "CAP" => client.on_cap(message).await,
+            "PASS" => client.on_pass(message).await,
+            "NICK" => client.on_nick(message).await,
+            "USER" => client.on_user(message).await,
+            "SERVER" => client.on_server(message).await,
+            "OPER" => client.on_oper(message).await,
+            "QUIT" => client.on_quit(message).await,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use async_trait::async_trait;
+
+use ircmsgprs::parser::Message;
+
+use crate::client::Client;
+use crate::module::{HookResult, Module};
+
+/* NOTE(diath): Hands the commands and events of local clients over to the scripts, see script.rs. */
+pub struct ScriptModule;
+
+#[async_trait]
+impl Module for ScriptModule {
+    fn get_name(&self) -> &'static str {
+        "script"
+    }
+
+    /* NOTE(diath): Script commands can't shadow the commands of modules. */
+    async fn before_message(&self, client: &Client, message: &Message) -> HookResult {
+        let name = message.command.to_ascii_uppercase();
+        if !*client.registered.read().await
+            || client.server.modules.has_command(&name)
+            || !client.server.scripts.has_command(&name).await
+        {
+            return HookResult::Continue;
+        }
+
+        let nick = client.nick.lock().await.to_string();
+        client
+            .server
+            .scripts
+            .on_command(&client.server, &nick, &name, &message.params)
+            .await;
+        HookResult::Deny
+    }
+
+    async fn after_message(&self, client: &Client, message: &Message) {
+        let nick = client.nick.lock().await.to_string();
+        match message.command.to_ascii_uppercase().as_str() {
+            /* NOTE(diath): Rejected messages and the ones sent to services never reach the scripts. */
+            "PRIVMSG" if message.params.len() > 1 => {
+                for target in client.take_delivered_targets().await {
+                    if client.server.is_service_nick(&target) {
+                        continue;
+                    }
+
+                    client
+                        .server
+                        .scripts
+                        .on_message(&client.server, &nick, &target, &message.params[1])
+                        .await;
+                }
+            }
+            "JOIN" if !message.params.is_empty() => {
+                for channel in message.params[0].split(',') {
+                    if client.server.has_channel_participant(channel, &nick).await {
+                        client
+                            .server
+                            .scripts
+                            .on_join(&client.server, &nick, channel)
+                            .await;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
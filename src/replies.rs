@@ -20,6 +20,8 @@ pub enum NumericReply {
     RplWhoisIdle = 317,
     RplEndOfWhois = 318,
     RplWhoisChannels = 319,
+    RplWhoisSpecial = 320,
+    RplWhoisAccount = 330,
     RplEndOfWho = 315,
     RplListStart = 321,
     RplList = 322,
@@ -36,6 +38,8 @@ pub enum NumericReply {
     RplVersion = 351,
     RplWhoReply = 352,
     RplNamReply = 353,
+    RplLinks = 364,
+    RplEndOfLinks = 365,
     RplEndOfNames = 366,
     RplBanList = 367,
     RplEndOfBanList = 368,
@@ -46,20 +50,26 @@ pub enum NumericReply {
     RplYoureOper = 381,
     RplRehashing = 382,
     RplTime = 391,
+    RplHostHidden = 396,
     ErrNoSuchNick = 401,
     ErrNoSuchServer = 402,
     ErrNoSuchChannel = 403,
     ErrCannotSendToChan = 404,
     ErrWasNoSuchNick = 406,
     ErrTooManyTargets = 407,
+    ErrNoSuchService = 408,
     ErrNoOrigin = 409,
+    ErrInvalidCapCmd = 410,
     ErrNoRecipient = 411,
     ErrNoTextToSend = 412,
+    ErrInputTooLong = 417,
     ErrUnknownCommand = 421,
     ErrNoMotd = 422,
     ErrNoNicknameGiven = 431,
     ErrErroneousNickname = 432,
     ErrNicknameInUse = 433,
+    ErrUnavailResource = 437,
+    ErrServicesDown = 440,
     ErrUserNotInChannel = 441,
     ErrNotOnChannel = 442,
     ErrUserOnChannel = 443,
@@ -69,6 +79,7 @@ pub enum NumericReply {
     ErrNeedMoreParams = 461,
     ErrAlreadyRegistered = 462,
     ErrPasswordMismatch = 464,
+    ErrYoureBannedCreep = 465,
     ErrKeySet = 467,
     ErrChannelIsFull = 471,
     ErrUnknownMode = 472,
@@ -78,4 +89,5 @@ pub enum NumericReply {
     ErrNoPrivileges = 481,
     ErrChanOpPrivsNeeded = 482,
     ErrUsersDontMatch = 502,
+    ErrNoPrivs = 723,
 }
@@ -0,0 +1,721 @@
+use std::collections::HashSet;
+use std::fs::read_dir;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rhai::{Array, Blob, CallFnOptions, Dynamic, Engine, ImmutableString, Map, Scope, AST};
+
+use tokio::sync::{Mutex, RwLock};
+use tokio::task;
+use tokio::time::Duration;
+
+use crate::client::Client;
+use crate::config::ScriptsConfig;
+use crate::server::{MessageKind, Server};
+
+/* NOTE(diath): Scripts are written in Rhai and placed in the configured directory, every *.rhai file is one script with its own engine and state.
+
+The top level of a script runs once when it is loaded and may call:
+    bot(nick)                   - creates a pseudo-client the script speaks as
+    command(name, function)     - handles the IRC command `name`, the function is called with (nick, params)
+    say(target, text)           - sends a PRIVMSG as the bot
+    notice(target, text)        - sends a NOTICE as the bot
+    join(channel), part(channel)
+
+The following functions are called if the script defines them:
+    on_load()                   - right after the script was loaded
+    on_message(nick, target, text) - for the messages sent to the bot or to a channel it is in
+    on_join(nick, channel)
+
+Every function is called with `this` bound to an object map that is kept between calls, that is where the state of a script lives. The state is lost when the scripts are reloaded by REHASH or when it grows past the state limit.
+
+Scripts run on a blocking thread, one call at a time. */
+const MAX_STATE_DEPTH: usize = 64;
+
+#[derive(Clone, Copy)]
+struct ScriptLimits {
+    time: u64,
+    operations: u64,
+    string_size: usize,
+    collection_size: usize,
+    state_size: usize,
+}
+
+impl ScriptLimits {
+    fn new(config: &ScriptsConfig) -> ScriptLimits {
+        ScriptLimits {
+            time: config.time_limit.unwrap_or(100),
+            operations: config.operation_limit.unwrap_or(100_000),
+            string_size: config.string_limit.unwrap_or(4096),
+            collection_size: config.collection_limit.unwrap_or(1024),
+            state_size: config.state_limit.unwrap_or(65536),
+        }
+    }
+}
+
+enum ScriptAction {
+    Message(MessageKind, String, String),
+    Join(String),
+    Part(String),
+}
+
+/* NOTE(diath): Filled in by the functions registered with the engine, which run synchronously, the actions are carried out once the script returns. */
+#[derive(Default)]
+struct ScriptContext {
+    bot: Option<String>,
+    commands: Vec<(String, String)>,
+    actions: Vec<ScriptAction>,
+}
+
+struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    this: Dynamic,
+    context: Arc<std::sync::Mutex<ScriptContext>>,
+    deadline: Arc<std::sync::Mutex<Instant>>,
+    time_limit: u64,
+    state_limit: usize,
+    bot: Option<Arc<Client>>,
+}
+
+impl Script {
+    fn load(path: PathBuf, limits: &ScriptLimits) -> Result<Script, String> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let context = Arc::new(std::sync::Mutex::new(ScriptContext::default()));
+        let deadline = Arc::new(std::sync::Mutex::new(Instant::now()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(limits.operations);
+        engine.set_max_string_size(limits.string_size);
+        engine.set_max_array_size(limits.collection_size);
+        engine.set_max_map_size(limits.collection_size);
+
+        let progress_deadline = deadline.clone();
+        engine.on_progress(move |_| {
+            if Instant::now() > *progress_deadline.lock().unwrap() {
+                Some("Time limit exceeded".into())
+            } else {
+                None
+            }
+        });
+
+        let print_name = name.clone();
+        engine.on_print(move |text| log::info!("[script {}] {}", print_name, text));
+
+        let bot_context = context.clone();
+        engine.register_fn("bot", move |nick: &str| {
+            bot_context.lock().unwrap().bot = Some(nick.to_string());
+        });
+
+        let command_context = context.clone();
+        engine.register_fn("command", move |name: &str, function: &str| {
+            command_context
+                .lock()
+                .unwrap()
+                .commands
+                .push((name.to_ascii_uppercase(), function.to_string()));
+        });
+
+        let say_context = context.clone();
+        engine.register_fn("say", move |target: &str, text: &str| {
+            say_context
+                .lock()
+                .unwrap()
+                .actions
+                .push(ScriptAction::Message(
+                    MessageKind::Privmsg,
+                    target.to_string(),
+                    text.to_string(),
+                ));
+        });
+
+        let notice_context = context.clone();
+        engine.register_fn("notice", move |target: &str, text: &str| {
+            notice_context
+                .lock()
+                .unwrap()
+                .actions
+                .push(ScriptAction::Message(
+                    MessageKind::Notice,
+                    target.to_string(),
+                    text.to_string(),
+                ));
+        });
+
+        let join_context = context.clone();
+        engine.register_fn("join", move |channel: &str| {
+            join_context
+                .lock()
+                .unwrap()
+                .actions
+                .push(ScriptAction::Join(channel.to_string()));
+        });
+
+        let part_context = context.clone();
+        engine.register_fn("part", move |channel: &str| {
+            part_context
+                .lock()
+                .unwrap()
+                .actions
+                .push(ScriptAction::Part(channel.to_string()));
+        });
+
+        let ast = engine
+            .compile_file(path)
+            .map_err(|error| format!("{}: {}", name, error))?;
+
+        let script = Script {
+            name,
+            engine,
+            ast,
+            this: Dynamic::from_map(Map::new()),
+            context,
+            deadline,
+            time_limit: limits.time,
+            state_limit: limits.state_size,
+            bot: None,
+        };
+
+        script.set_deadline();
+        script
+            .engine
+            .run_ast_with_scope(&mut Scope::new(), &script.ast)
+            .map_err(|error| format!("{}: {}", script.name, error))?;
+
+        Ok(script)
+    }
+
+    fn set_deadline(&self) {
+        *self.deadline.lock().unwrap() = Instant::now() + Duration::from_millis(self.time_limit);
+    }
+
+    fn has_function(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == params)
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Dynamic>) {
+        if !self.has_function(name, args.len()) {
+            return;
+        }
+
+        self.set_deadline();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+        if let Err(error) = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            name,
+            args,
+        ) {
+            log::warn!("Script {} failed in {}: {}", self.name, name, error);
+        }
+
+        if get_size(&self.this, 0) > self.state_limit {
+            log::warn!(
+                "Script {} state grew past {} bytes, resetting it.",
+                self.name,
+                self.state_limit
+            );
+            self.this = Dynamic::from_map(Map::new());
+        }
+    }
+
+    fn take_actions(&self) -> Vec<ScriptAction> {
+        self.context.lock().unwrap().actions.drain(..).collect()
+    }
+
+    fn get_commands(&self) -> Vec<String> {
+        self.context
+            .lock()
+            .unwrap()
+            .commands
+            .iter()
+            .map(|(command, _)| command.to_string())
+            .collect()
+    }
+
+    fn get_command(&self, name: &str) -> Option<String> {
+        self.context
+            .lock()
+            .unwrap()
+            .commands
+            .iter()
+            .find(|(command, _)| command == name)
+            .map(|(_, function)| function.to_string())
+    }
+}
+
+/* NOTE(diath): A rough size of a value in bytes, strings count their length and every other value counts as one. */
+fn get_size(value: &Dynamic, depth: usize) -> usize {
+    if depth > MAX_STATE_DEPTH {
+        return usize::MAX;
+    }
+
+    if let Some(text) = value.read_lock::<ImmutableString>() {
+        text.len()
+    } else if let Some(blob) = value.read_lock::<Blob>() {
+        blob.len()
+    } else if let Some(array) = value.read_lock::<Array>() {
+        array.iter().fold(1, |size, value| {
+            size.saturating_add(get_size(value, depth + 1))
+        })
+    } else if let Some(map) = value.read_lock::<Map>() {
+        map.iter().fold(1, |size, (key, value)| {
+            size.saturating_add(key.len())
+                .saturating_add(get_size(value, depth + 1))
+        })
+    } else {
+        1
+    }
+}
+
+type PendingActions = Vec<(Arc<Client>, Vec<ScriptAction>)>;
+
+/* NOTE(diath): A script that panicked, its state can not be trusted anymore so it is unloaded. */
+struct FailedScript {
+    name: String,
+    bot: Option<Arc<Client>>,
+}
+
+/* NOTE(diath): Runs the callback for every script on a blocking thread until one returns true, returns the scripts along with the actions of their bots and the scripts that panicked. */
+async fn call_scripts<F>(
+    scripts: Vec<Script>,
+    mut callback: F,
+) -> (Vec<Script>, PendingActions, Vec<FailedScript>)
+where
+    F: FnMut(&mut Script) -> bool + Send + 'static,
+{
+    let failed = scripts
+        .iter()
+        .map(|script| FailedScript {
+            name: script.name.clone(),
+            bot: script.bot.clone(),
+        })
+        .collect::<Vec<FailedScript>>();
+
+    let result = task::spawn_blocking(move || {
+        let mut kept = vec![];
+        let mut pending = vec![];
+        let mut failed = vec![];
+        let mut done = false;
+        for mut script in scripts {
+            if done {
+                kept.push(script);
+                continue;
+            }
+
+            match panic::catch_unwind(AssertUnwindSafe(|| callback(&mut script))) {
+                Ok(result) => {
+                    done = result;
+                    let actions = script.take_actions();
+                    if let Some(bot) = script.bot.clone() {
+                        pending.push((bot, actions));
+                    }
+                    kept.push(script);
+                }
+                Err(_) => failed.push(FailedScript {
+                    name: script.name.clone(),
+                    bot: script.bot.clone(),
+                }),
+            }
+        }
+        (kept, pending, failed)
+    })
+    .await;
+
+    match result {
+        Ok(result) => result,
+        Err(error) => {
+            log::error!("Scripts were dropped after a failure: {}", error);
+            (vec![], vec![], failed)
+        }
+    }
+}
+
+pub struct Scripts {
+    scripts: Mutex<Vec<Script>>,
+    /* NOTE(diath): Kept apart from the scripts so that looking up a command does not wait for a script that is running. */
+    commands: RwLock<HashSet<String>>,
+    config: RwLock<ScriptsConfig>,
+}
+
+impl Scripts {
+    pub fn new(config: ScriptsConfig) -> Scripts {
+        Scripts {
+            scripts: Mutex::new(vec![]),
+            commands: RwLock::new(HashSet::new()),
+            config: RwLock::new(config),
+        }
+    }
+
+    /* NOTE(diath): Drops every loaded script along with its bot and loads the directory again, returns the number of scripts loaded if scripting is enabled. */
+    pub async fn reload(
+        &self,
+        server: &Arc<Server>,
+        config: Option<ScriptsConfig>,
+    ) -> Option<usize> {
+        if let Some(config) = config {
+            (*self.config.write().await) = config;
+        }
+
+        let old = self.scripts.lock().await.drain(..).collect::<Vec<Script>>();
+        self.commands.write().await.clear();
+        for script in old {
+            if let Some(bot) = script.bot {
+                server.broadcast_quit(&bot, "Script unloaded").await;
+                server.remove_client(&bot).await;
+            }
+        }
+
+        let (directory, limits) = {
+            let config = self.config.read().await;
+            (config.directory.clone(), ScriptLimits::new(&config))
+        };
+
+        let directory = match directory {
+            Some(directory) => directory,
+            None => return None,
+        };
+
+        let mut paths = match read_dir(&directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "rhai")
+                })
+                .collect::<Vec<PathBuf>>(),
+            Err(error) => {
+                log::warn!(
+                    "Unable to read the scripts directory {}: {}",
+                    directory,
+                    error
+                );
+                return Some(0);
+            }
+        };
+        paths.sort();
+
+        /* NOTE(diath): The top level of a script runs while it is loaded, so loading happens on a blocking thread as well. */
+        let results = task::spawn_blocking(move || {
+            paths
+                .into_iter()
+                .map(|path| Script::load(path, &limits))
+                .collect::<Vec<Result<Script, String>>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut scripts = vec![];
+        for result in results {
+            let mut script = match result {
+                Ok(script) => script,
+                Err(error) => {
+                    log::warn!("Unable to load script {}", error);
+                    server
+                        .broadcast_oper_notice(format!("Unable to load script {}", error))
+                        .await;
+                    continue;
+                }
+            };
+
+            let nick = script.context.lock().unwrap().bot.clone();
+            if let Some(nick) = nick {
+                script.bot = if server.is_service_nick(&nick) {
+                    None
+                } else {
+                    Server::add_pseudo_client(server, &nick).await
+                };
+
+                match &script.bot {
+                    Some(bot) => (*bot.real_name.lock().await) = format!("{} Bot", nick),
+                    None => log::warn!(
+                        "Script {} bot nick {} is already in use.",
+                        script.name,
+                        nick
+                    ),
+                }
+            }
+
+            log::info!("Script {} loaded.", script.name);
+            scripts.push(script);
+        }
+
+        let count = scripts.len();
+        let (pending, failed) = {
+            let mut loaded = self.scripts.lock().await;
+            let (scripts, pending, failed) = call_scripts(scripts, |script| {
+                script.call("on_load", vec![]);
+                false
+            })
+            .await;
+            loaded.extend(scripts);
+            self.update_commands(&loaded).await;
+            (pending, failed)
+        };
+
+        let count = count - failed.len();
+        Scripts::unload_failed(server, failed).await;
+        for (bot, actions) in pending {
+            Scripts::run_actions(server, &bot, actions).await;
+        }
+
+        Some(count)
+    }
+
+    pub async fn has_command(&self, name: &str) -> bool {
+        self.commands.read().await.contains(name)
+    }
+
+    async fn update_commands(&self, scripts: &[Script]) {
+        (*self.commands.write().await) = scripts
+            .iter()
+            .flat_map(|script| script.get_commands())
+            .collect();
+    }
+
+    pub async fn on_command(
+        &self,
+        server: &Arc<Server>,
+        nick: &str,
+        name: &str,
+        params: &[String],
+    ) {
+        let nick = nick.to_string();
+        let name = name.to_string();
+        let params = params
+            .iter()
+            .map(|param| Dynamic::from(param.to_string()))
+            .collect::<Array>();
+        self.dispatch(server, move |script| match script.get_command(&name) {
+            Some(function) => {
+                script.call(
+                    &function,
+                    vec![
+                        Dynamic::from(nick.clone()),
+                        Dynamic::from_array(params.clone()),
+                    ],
+                );
+                true
+            }
+            None => false,
+        })
+        .await;
+    }
+
+    /* NOTE(diath): A script only hears the messages its bot would have received. */
+    pub async fn on_message(&self, server: &Arc<Server>, nick: &str, target: &str, text: &str) {
+        let mut names = HashSet::new();
+        for script in self.scripts.lock().await.iter() {
+            if let Some(bot) = &script.bot {
+                let bot_nick = bot.nick.lock().await.to_string();
+                if bot_nick.eq_ignore_ascii_case(target)
+                    || server.has_channel_participant(target, &bot_nick).await
+                {
+                    names.insert(script.name.clone());
+                }
+            }
+        }
+
+        if names.is_empty() {
+            return;
+        }
+
+        let args = vec![
+            Dynamic::from(nick.to_string()),
+            Dynamic::from(target.to_string()),
+            Dynamic::from(text.to_string()),
+        ];
+        self.dispatch(server, move |script| {
+            if names.contains(&script.name) {
+                script.call("on_message", args.clone());
+            }
+            false
+        })
+        .await;
+    }
+
+    pub async fn on_join(&self, server: &Arc<Server>, nick: &str, channel: &str) {
+        let args = vec![
+            Dynamic::from(nick.to_string()),
+            Dynamic::from(channel.to_string()),
+        ];
+        self.dispatch(server, move |script| {
+            script.call("on_join", args.clone());
+            false
+        })
+        .await;
+    }
+
+    /* NOTE(diath): The actions are carried out after the scripts are unlocked since they can trigger other events. */
+    async fn dispatch<F>(&self, server: &Arc<Server>, callback: F)
+    where
+        F: FnMut(&mut Script) -> bool + Send + 'static,
+    {
+        let (pending, failed) = {
+            let mut scripts = self.scripts.lock().await;
+            if scripts.is_empty() {
+                return;
+            }
+
+            let (taken, pending, failed) =
+                call_scripts(scripts.drain(..).collect(), callback).await;
+            (*scripts) = taken;
+            self.update_commands(&scripts).await;
+            (pending, failed)
+        };
+
+        Scripts::unload_failed(server, failed).await;
+        for (bot, actions) in pending {
+            Scripts::run_actions(server, &bot, actions).await;
+        }
+    }
+
+    async fn unload_failed(server: &Arc<Server>, failed: Vec<FailedScript>) {
+        for script in failed {
+            log::error!("Script {} failed and was unloaded.", script.name);
+            server
+                .broadcast_oper_notice(format!("Script {} failed and was unloaded", script.name))
+                .await;
+
+            if let Some(bot) = script.bot {
+                server.broadcast_quit(&bot, "Script failed").await;
+                server.remove_client(&bot).await;
+            }
+        }
+    }
+
+    async fn run_actions(server: &Arc<Server>, bot: &Client, actions: Vec<ScriptAction>) {
+        for action in actions {
+            match action {
+                ScriptAction::Message(kind, target, text) => {
+                    if target.starts_with('#') {
+                        server
+                            .forward_channel_message(kind, bot, &target, text, &[])
+                            .await;
+                    } else {
+                        server.forward_message(kind, bot, &target, text, &[]).await;
+                    }
+                }
+                ScriptAction::Join(channel) => {
+                    if !channel.starts_with('#') {
+                        continue;
+                    }
+
+                    if !server.is_channel_mapped(&channel).await {
+                        server.create_channel(&channel).await;
+                    }
+
+                    if server.join_channel(bot, &channel, String::new()).await {
+                        bot.channels.lock().await.insert(channel.to_lowercase());
+                    }
+                }
+                ScriptAction::Part(channel) => {
+                    if server.part_channel(bot, &channel, "Leaving").await {
+                        bot.channels.lock().await.remove(&channel.to_lowercase());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn load(source: &str, state_size: usize) -> Script {
+        let path = env::temp_dir().join(format!("ayame-script-{}.rhai", std::process::id()));
+        fs::write(&path, source).unwrap();
+        let limits = ScriptLimits {
+            time: 1000,
+            operations: 100_000,
+            string_size: 4096,
+            collection_size: 1024,
+            state_size,
+        };
+        let script = Script::load(path.clone(), &limits);
+        fs::remove_file(&path).unwrap();
+        script.unwrap()
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(get_size(&Dynamic::from("abc".to_string()), 0), 3);
+        assert_eq!(get_size(&Dynamic::from(42_i64), 0), 1);
+
+        let mut map = Map::new();
+        map.insert("key".into(), Dynamic::from("value".to_string()));
+        map.insert(
+            "list".into(),
+            Dynamic::from_array(vec![Dynamic::from(1_i64), Dynamic::from("ab".to_string())]),
+        );
+        assert_eq!(
+            get_size(&Dynamic::from_map(map), 0),
+            1 + 3 + 5 + 4 + 1 + 1 + 2
+        );
+    }
+
+    #[test]
+    fn state_limit() {
+        let mut script = load(
+            r#"
+            fn add(text) {
+                if this.text == () {
+                    this.text = "";
+                }
+                this.text += text;
+            }
+            "#,
+            32,
+        );
+
+        script.call("add", vec![Dynamic::from("0123456789".to_string())]);
+        script.call("add", vec![Dynamic::from("0123456789".to_string())]);
+        assert_eq!(get_size(&script.this, 0), 1 + 4 + 20);
+
+        script.call("add", vec![Dynamic::from("0123456789".to_string())]);
+        assert!(script.this.read_lock::<Map>().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn panicking_script() {
+        let scripts = vec![
+            load("fn first() {}", 1024),
+            load("fn broken() {}", 1024),
+            load("fn last() {}", 1024),
+        ];
+
+        /* NOTE(diath): Only the script that panicked is dropped, the callback still runs for the ones after it. */
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let (scripts, _, failed) = call_scripts(scripts, move |script| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if script.has_function("broken", 0) {
+                panic!("broken script");
+            }
+            false
+        })
+        .await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(scripts.len(), 2);
+        assert!(scripts[0].has_function("first", 0));
+        assert!(scripts[1].has_function("last", 0));
+    }
+}
@@ -1,29 +1,51 @@
 use crate::ayame::*;
 use crate::channel::{Channel, ChannelUserModes};
-use crate::client::Client;
-use crate::config::Config;
+use crate::client::{Client, UserHost};
+use crate::cloak::Cloak;
+use crate::config::{BanConfig, Config, LinkConfig, OperConfig, Options, ServerConfig};
+use crate::dns::Resolver;
+use crate::dnsbl::Dnsbl;
+use crate::handoff::{self, ListenerState, ServerState};
+use crate::ident::Ident;
+use crate::link::{self, Link};
+use crate::mask::check_mask;
+use crate::module::ModuleRegistry;
+use crate::modules;
 use crate::replies::NumericReply;
+use crate::script::Scripts;
 use crate::service::Service;
 use crate::services::hostserv::HostServ;
+use crate::services::memoserv::MemoServ;
 use crate::services::nickserv::NickServ;
+use crate::services::operserv::OperServ;
+use crate::tags::{MessageTags, Tag};
 
 use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::fs::{read_to_string, File};
 use std::io::{BufRead, BufReader};
+use std::iter;
 use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
 use std::sync::Arc;
 use std::time::SystemTime;
 use std::vec::Vec;
 
 use chrono::prelude::DateTime;
-use chrono::Utc;
+use chrono::{TimeZone, Utc};
 
-use tokio::net::TcpListener;
-use tokio::sync::{Mutex, RwLock};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::time::{delay_for, Duration};
 
 use log;
 
+static LINK_AUTOCONNECT_INTERVAL: u64 = 60;
+static SJOIN_MEMBERS: usize = 30;
+
 pub struct NickHistory {
     pub nick: String,
     pub user: String,
@@ -32,86 +54,423 @@ pub struct NickHistory {
     pub timestamp: i64,
 }
 
+#[derive(Clone, PartialEq)]
+pub struct OperatorCredentials {
+    pub password: String,
+    pub privileges: HashSet<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Shutdown {
+    Die,
+    Restart,
+    Upgrade,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    Privmsg,
+    Notice,
+    /* NOTE(diath): A message with tags only, it is never sent to clients that did not negotiate message-tags. */
+    Tagmsg,
+}
+
+impl MessageKind {
+    pub fn get_command(&self) -> &'static str {
+        match self {
+            MessageKind::Privmsg => "PRIVMSG",
+            MessageKind::Notice => "NOTICE",
+            MessageKind::Tagmsg => "TAGMSG",
+        }
+    }
+
+    pub fn format(&self, source: &str, target: &str, text: &str) -> String {
+        match self {
+            MessageKind::Tagmsg => format!(":{} TAGMSG {}", source, target),
+            _ => format!(":{} {} {} :{}", source, self.get_command(), target, text),
+        }
+    }
+}
+
+struct Listener {
+    fd: RawFd,
+    stop: oneshot::Sender<()>,
+}
+
+#[derive(Clone)]
+pub struct ServerBan {
+    pub mask: String,
+    pub reason: String,
+}
+
+/* NOTE(diath): A server somewhere behind one of our links, the link is the key of the directly connected server it is reached through. */
+#[derive(Clone)]
+pub struct RemoteServer {
+    pub name: String,
+    pub description: String,
+    pub hops: u32,
+    pub uplink: String,
+    pub link: String,
+}
+
+pub struct ServerStats {
+    pub clients: usize,
+    pub pending: usize,
+    pub operators: usize,
+    pub channels: usize,
+}
+
 pub struct Server {
     pub name: String,
+    pub description: String,
+    pub config_path: String,
+    motd_path_override: Option<String>,
+    data_path: String,
+    handoff: Mutex<Option<ServerState>>,
     pub created: DateTime<Utc>,
     pub sent_packets: RwLock<u64>,
     pub recv_packets: RwLock<u64>,
     pub sent_bytes: RwLock<u64>,
     pub recv_bytes: RwLock<u64>,
-    address: SocketAddr,
+    addresses: Vec<SocketAddr>,
+    listeners: Mutex<HashMap<SocketAddr, Listener>>,
     clients: Mutex<HashMap<String, Arc<Client>>>,
     clients_pending: Mutex<Vec<Arc<Client>>>,
-    operator_credentials: Mutex<HashMap<String, String>>,
+    operator_credentials: Mutex<HashMap<String, OperatorCredentials>>,
     operators: Mutex<HashSet<String>>,
     channels: Mutex<HashMap<String, Channel>>,
+    motd_path: RwLock<String>,
     motd: Mutex<Option<Vec<String>>>,
+    bans: Mutex<Vec<ServerBan>>,
     nick_history: Mutex<HashMap<String, Vec<NickHistory>>>,
-    services: Mutex<HashMap<String, Box<dyn Service + Send + Sync>>>,
+    link_configs: RwLock<Vec<LinkConfig>>,
+    links: Mutex<HashMap<String, Arc<Link>>>,
+    links_connecting: Mutex<HashSet<String>>,
+    remote_servers: Mutex<HashMap<String, RemoteServer>>,
+    pub nickserv: Arc<NickServ>,
+    pub hostserv: Arc<HostServ>,
+    pub memoserv: Arc<MemoServ>,
+    pub operserv: Arc<OperServ>,
+    pub cloak: Cloak,
+    pub resolver: Resolver,
+    pub dns_lookups: bool,
+    pub ident: Option<Ident>,
+    pub dnsbl: Dnsbl,
+    services: HashMap<String, Arc<dyn Service + Send + Sync>>,
+    service_channels: Vec<String>,
+    pub modules: ModuleRegistry,
+    pub scripts: Scripts,
+    shutdown_sender: mpsc::UnboundedSender<Shutdown>,
+    shutdown_receiver: Mutex<Option<mpsc::UnboundedReceiver<Shutdown>>>,
 }
 
 impl Server {
-    pub fn new() -> Server {
-        let config = Server::load_config();
+    pub fn new(options: &Options) -> Result<Server, String> {
+        let config = Server::load_config(options)?;
+        let addresses = Server::get_listen_addresses(&config.server)?;
 
         let name = config.server.name.unwrap_or(IRCD_NAME.to_string());
-        let motd_path = config.server.motd_path.unwrap_or(IRCD_MOTD.to_string());
-        let host = config.server.host.unwrap_or("127.0.0.1".to_string());
-        let port = config.server.port.unwrap_or(6667);
+        let description = config.server.description.unwrap_or(IRCD_NAME.to_string());
+        let motd_path = options
+            .motd_path
+            .clone()
+            .or(config.server.motd_path)
+            .unwrap_or(IRCD_MOTD.to_string());
+        let data_path = options
+            .data_path
+            .clone()
+            .or(config.server.data_path)
+            .unwrap_or(IRCD_DATA.to_string());
 
         log::info!("Server: {}", name);
-        log::info!("Address: {}:{}", host, port);
+        for address in addresses.iter() {
+            log::info!("Address: {}", address);
+        }
 
-        let mut operators = HashMap::new();
-        if let Some(opers) = config.oper {
-            for oper in opers {
-                if oper.name.is_none() || oper.password.is_none() {
-                    continue;
-                }
+        let operators = Server::get_operators(config.oper.unwrap_or_default());
+        log::info!("Loaded {} operators.", operators.len());
 
-                operators.insert(oper.name.unwrap(), oper.password.unwrap());
-            }
+        let bans = Server::get_bans(config.ban.unwrap_or_default());
+        if !bans.is_empty() {
+            log::info!("Loaded {} server bans.", bans.len());
+        }
+
+        let link_configs = Server::get_link_configs(config.link.unwrap_or_default(), &name);
+        if !link_configs.is_empty() {
+            log::info!("Loaded {} link blocks.", link_configs.len());
         }
-        log::info!("Loaded {} operators.", operators.len());
 
-        let mut services: HashMap<String, Box<dyn Service + Send + Sync>> = HashMap::new();
-        services.insert("nickserv".to_string(), Box::new(NickServ::new()));
-        services.insert("hostserv".to_string(), Box::new(HostServ::new()));
+        let services_config = config.services.unwrap_or_default();
+        let memo_limit = services_config.memo_limit.unwrap_or(20);
+        let session_limit = services_config.session_limit.unwrap_or(0);
+
+        let nickserv = Arc::new(NickServ::new(
+            &data_path,
+            services_config.nick_enforce.unwrap_or(true),
+            services_config.nick_enforce_delay.unwrap_or(60),
+            services_config.nick_hold_duration.unwrap_or(60),
+        ));
+        let hostserv = Arc::new(HostServ::new(&data_path));
+        let memoserv = Arc::new(MemoServ::new(&data_path, memo_limit));
+        let operserv = Arc::new(OperServ::new(&data_path, session_limit));
+
+        let dns_config = config.dns.unwrap_or_default();
+        let ident_config = config.ident.unwrap_or_default();
+        let ident = if ident_config.lookups.unwrap_or(false) {
+            Some(Ident::new(
+                ident_config.port.unwrap_or(113),
+                ident_config.timeout.unwrap_or(3),
+            ))
+        } else {
+            None
+        };
+
+        let mut services: HashMap<String, Arc<dyn Service + Send + Sync>> = HashMap::new();
+        services.insert("NickServ".to_string(), nickserv.clone());
+        services.insert("HostServ".to_string(), hostserv.clone());
+        services.insert("MemoServ".to_string(), memoserv.clone());
+        services.insert("OperServ".to_string(), operserv.clone());
+
+        let handoff = if options.upgrade {
+            Some(handoff::load(&data_path)?)
+        } else {
+            None
+        };
+        let created = match &handoff {
+            Some(state) => Utc.timestamp(state.created, 0),
+            None => DateTime::<Utc>::from(SystemTime::now()),
+        };
+
+        let (shutdown_sender, shutdown_receiver) = mpsc::unbounded_channel();
 
-        Server {
+        Ok(Server {
             name: name,
-            created: DateTime::<Utc>::from(SystemTime::now()),
+            description,
+            config_path: options.config_path.to_string(),
+            motd_path_override: options.motd_path.clone(),
+            data_path,
+            handoff: Mutex::new(handoff),
+            created,
             sent_packets: RwLock::new(0),
             recv_packets: RwLock::new(0),
             sent_bytes: RwLock::new(0),
             recv_bytes: RwLock::new(0),
-            address: format!("{}:{}", host, port).parse().unwrap(),
+            addresses,
+            listeners: Mutex::new(HashMap::new()),
             clients: Mutex::new(HashMap::new()),
             clients_pending: Mutex::new(vec![]),
             operator_credentials: Mutex::new(operators),
             operators: Mutex::new(HashSet::new()),
             channels: Mutex::new(HashMap::new()),
             motd: Mutex::new(Server::load_motd(&motd_path)),
+            motd_path: RwLock::new(motd_path),
+            bans: Mutex::new(bans),
             nick_history: Mutex::new(HashMap::new()),
-            services: Mutex::new(services),
+            link_configs: RwLock::new(link_configs),
+            links: Mutex::new(HashMap::new()),
+            links_connecting: Mutex::new(HashSet::new()),
+            remote_servers: Mutex::new(HashMap::new()),
+            nickserv,
+            hostserv,
+            memoserv,
+            operserv,
+            cloak: Cloak::new(config.cloak.unwrap_or_default()),
+            resolver: Resolver::new(dns_config.nameserver, dns_config.timeout.unwrap_or(5)),
+            dns_lookups: dns_config.lookups.unwrap_or(true),
+            ident,
+            dnsbl: Dnsbl::new(
+                config.dnsbl.unwrap_or_default(),
+                dns_config.dnsbl_cache_duration.unwrap_or(3600),
+            ),
+            services,
+            service_channels: services_config.channels.unwrap_or_default(),
+            modules: ModuleRegistry::new(modules::get_modules()),
+            scripts: Scripts::new(config.scripts.unwrap_or_default()),
+            shutdown_sender,
+            shutdown_receiver: Mutex::new(Some(shutdown_receiver)),
+        })
+    }
+
+    /* NOTE(diath): An invalid config is fatal at startup unless explicitly overridden, only a missing default config falls back to the defaults. */
+    fn load_config(options: &Options) -> Result<Config, String> {
+        if !options.config_path_given && !Path::new(&options.config_path).exists() {
+            log::warn!(
+                "Config file {} not found, using the default settings.",
+                options.config_path
+            );
+            return Ok(Config {
+                ..Default::default()
+            });
+        }
+
+        match Server::read_config(&options.config_path) {
+            Ok(config) => Ok(config),
+            Err(error) if options.ignore_config_errors => {
+                log::warn!("{}, using the default settings.", error);
+                Ok(Config {
+                    ..Default::default()
+                })
+            }
+            Err(error) => Err(error),
         }
     }
 
-    fn load_config() -> Config {
-        match read_to_string(IRCD_CONFIG) {
-            Ok(s) => match toml::from_str(&s) {
-                Ok(config) => config,
-                Err(error) => {
-                    log::warn!("Config parse error: {}", error);
-                    Config {
-                        ..Default::default()
+    pub fn read_config(filename: &str) -> Result<Config, String> {
+        let contents = read_to_string(filename)
+            .map_err(|error| format!("Unable to read {}: {}", filename, error))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|error| format!("Unable to parse {}: {}", filename, error))?;
+        Server::validate_config(&config)?;
+
+        Ok(config)
+    }
+
+    /* NOTE(diath): Catches mistakes that the lenient loaders below would silently skip, so that a rehash can refuse the whole file instead. */
+    pub fn validate_config(config: &Config) -> Result<(), String> {
+        Server::get_listen_addresses(&config.server)?;
+
+        for oper in config.oper.iter().flatten() {
+            if oper.name.is_none() || oper.password.is_none() {
+                return Err("Operator blocks require a name and a password".to_string());
+            }
+        }
+
+        for ban in config.ban.iter().flatten() {
+            if ban.mask.as_ref().is_none_or(|mask| mask.is_empty()) {
+                return Err("Ban blocks require a mask".to_string());
+            }
+        }
+
+        for link in config.link.iter().flatten() {
+            if link.name.as_ref().is_none_or(|name| name.is_empty()) || link.password.is_none() {
+                return Err("Link blocks require a name and a password".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_listen_addresses(config: &ServerConfig) -> Result<Vec<SocketAddr>, String> {
+        let primary = format!(
+            "{}:{}",
+            config.host.as_deref().unwrap_or("127.0.0.1"),
+            config.port.unwrap_or(6667)
+        );
+
+        let mut addresses = vec![];
+        for address in iter::once(&primary).chain(config.listen.iter().flatten()) {
+            match address.parse::<SocketAddr>() {
+                Ok(address) => {
+                    if !addresses.contains(&address) {
+                        addresses.push(address);
                     }
                 }
-            },
-            Err(_) => Config {
-                ..Default::default()
-            },
+                Err(_) => return Err(format!("Invalid listen address: {}", address)),
+            }
+        }
+
+        Ok(addresses)
+    }
+
+    fn get_operators(config: Vec<OperConfig>) -> HashMap<String, OperatorCredentials> {
+        let mut operators = HashMap::new();
+        for oper in config {
+            if let (Some(name), Some(password)) = (oper.name, oper.password) {
+                let privileges = oper
+                    .privileges
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|privilege| privilege.to_lowercase())
+                    .collect();
+
+                operators.insert(
+                    name,
+                    OperatorCredentials {
+                        password,
+                        privileges,
+                    },
+                );
+            }
+        }
+
+        operators
+    }
+
+    fn get_bans(config: Vec<BanConfig>) -> Vec<ServerBan> {
+        config
+            .into_iter()
+            .filter_map(|ban| {
+                Some(ServerBan {
+                    mask: ban.mask.filter(|mask| !mask.is_empty())?,
+                    reason: ban.reason.unwrap_or_else(|| "Banned".to_string()),
+                })
+            })
+            .collect()
+    }
+
+    fn get_link_configs(config: Vec<LinkConfig>, server_name: &str) -> Vec<LinkConfig> {
+        let (links, problems) = Server::load_link_configs(config, server_name);
+        for problem in problems {
+            log::warn!("{}", problem);
+        }
+
+        links
+    }
+
+    fn load_link_configs(
+        config: Vec<LinkConfig>,
+        server_name: &str,
+    ) -> (Vec<LinkConfig>, Vec<String>) {
+        let mut links: Vec<LinkConfig> = vec![];
+        let mut problems = vec![];
+        for link in config {
+            let name = match (&link.name, &link.password) {
+                (Some(name), Some(_)) if !name.is_empty() => name.to_string(),
+                _ => {
+                    problems.push("Ignoring link block without a name and a password.".to_string());
+                    continue;
+                }
+            };
+
+            if name.eq_ignore_ascii_case(server_name) {
+                problems.push(format!(
+                    "Ignoring link block {} with the name of this server.",
+                    name
+                ));
+            } else if name.contains(|ch: char| ch.is_whitespace() || ch == ',' || ch == '*') {
+                problems.push(format!(
+                    "Ignoring link block with an invalid name: {}",
+                    name
+                ));
+            } else if link.port == Some(0) {
+                problems.push(format!(
+                    "Ignoring link block {} with an invalid port.",
+                    name
+                ));
+            } else if links.iter().any(|other| {
+                other
+                    .name
+                    .as_ref()
+                    .is_some_and(|other| other.eq_ignore_ascii_case(&name))
+            }) {
+                problems.push(format!("Ignoring duplicate link block {}.", name));
+            } else {
+                links.push(link);
+            }
         }
+
+        (links, problems)
+    }
+
+    /* NOTE(diath): Runs the construction of the parts of the config that are only warned about at startup and returns everything that would be ignored. */
+    pub fn check_config(config: Config) -> Vec<String> {
+        let name = config.server.name.unwrap_or(IRCD_NAME.to_string());
+        let (_, mut problems) = Cloak::load(config.cloak.unwrap_or_default());
+        let (_, dnsbl_problems) = Dnsbl::load(config.dnsbl.unwrap_or_default(), 0);
+        let (_, link_problems) = Server::load_link_configs(config.link.unwrap_or_default(), &name);
+        problems.extend(dnsbl_problems);
+        problems.extend(link_problems);
+        problems
     }
 
     fn load_motd(filename: &str) -> Option<Vec<String>> {
@@ -130,188 +489,1009 @@ impl Server {
     }
 
     pub async fn reload_motd(&self) {
-        (*self.motd.lock().await) = Server::load_motd("motd.txt");
+        let motd_path = self.motd_path.read().await.to_string();
+        (*self.motd.lock().await) = Server::load_motd(&motd_path);
     }
 
-    pub async fn accept(self) -> Result<(), Box<dyn std::error::Error>> {
-        let server = Arc::new(self);
-        let mut acceptor = TcpListener::bind(server.address).await?;
-        log::info!("Listening...");
+    /* NOTE(diath): Server bans from the config match user@host against the IP address and the resolved hostname. */
+    pub async fn find_ban(&self, user: &str, hosts: &[String]) -> Option<ServerBan> {
+        self.bans
+            .lock()
+            .await
+            .iter()
+            .find(|ban| {
+                hosts
+                    .iter()
+                    .any(|host| check_mask(&ban.mask, &format!("{}@{}", user, host)))
+            })
+            .cloned()
+    }
 
-        loop {
-            let (stream, addr) = acceptor.accept().await?;
-            let client = Arc::new(Client::new(server.clone(), addr));
+    /* NOTE(diath): The whole file is read and validated before anything is applied, a broken config leaves the running state untouched. */
+    pub async fn rehash(server: &Arc<Server>) -> Result<(), String> {
+        let config = Server::read_config(&server.config_path)?;
+        let addresses = Server::get_listen_addresses(&config.server)?;
+
+        /* NOTE(diath): New listeners are bound up front as well, so that a port that is already in use also rejects the rehash. */
+        let mut listeners = server.listeners.lock().await;
+        let mut bound = vec![];
+        for address in addresses.iter() {
+            if listeners.contains_key(address) {
+                continue;
+            }
 
-            log::debug!("Client connected ({}).", addr);
-            let c = Mutex::new(client.clone());
-            tokio::spawn(async move {
-                c.lock().await.task(stream).await;
-            });
+            match TcpListener::bind(address).await {
+                Ok(listener) => bound.push((*address, listener)),
+                Err(error) => return Err(format!("Unable to listen on {}: {}", address, error)),
+            }
+        }
 
-            let c2 = Mutex::new(client.clone());
-            tokio::spawn(async move {
-                c2.lock().await.task_ping().await;
-            });
+        let mut changes = vec![];
 
-            server.clients_pending.lock().await.push(client.clone());
+        let operators = Server::get_operators(config.oper.unwrap_or_default());
+        let previous_operators = server.operator_credentials.lock().await.clone();
+        {
+            let mut credentials = server.operator_credentials.lock().await;
+            let added = operators
+                .keys()
+                .filter(|name| !credentials.contains_key(*name))
+                .count();
+            let removed = credentials
+                .keys()
+                .filter(|name| !operators.contains_key(*name))
+                .count();
+            let updated = operators
+                .iter()
+                .filter(|(name, operator)| {
+                    credentials
+                        .get(*name)
+                        .is_some_and(|current| current != *operator)
+                })
+                .count();
+
+            if added != 0 || removed != 0 || updated != 0 {
+                changes.push(format!(
+                    "Operators: {} added, {} removed, {} updated",
+                    added, removed, updated
+                ));
+            }
+            *credentials = operators.clone();
         }
-    }
 
-    pub async fn is_nick_mapped(&self, name: &str) -> bool {
-        self.clients.lock().await.contains_key(name)
-    }
+        /* NOTE(diath): Operators whose block was removed or got a new password lose their status, a change of privileges applies right away. */
+        for client in server.get_clients().await {
+            if client.is_remote() || client.is_service() || !*client.operator.lock().await {
+                continue;
+            }
 
-    pub async fn map_nick(&self, nick: String, client: &Client) {
-        let index = self
-            .clients_pending
-            .lock()
-            .await
-            .iter()
-            .position(|c| Arc::into_raw(c.clone()) == &*client);
-        if index.is_none() {
-            panic!("map_nick()");
+            let name = client.oper_name.lock().await.clone();
+            let current = name.as_ref().and_then(|name| operators.get(name));
+            let previous = name.as_ref().and_then(|name| previous_operators.get(name));
+            match (current, previous) {
+                (Some(current), Some(previous)) if current.password == previous.password => {
+                    (*client.privileges.lock().await) = current.privileges.clone();
+                }
+                _ => {
+                    client.deoper().await;
+                    let nick = client.nick.lock().await.to_string();
+                    client
+                        .send_raw(format!(":{} MODE {} :-o", server.name, nick))
+                        .await;
+                    client
+                        .send_raw(format!(
+                            ":{} NOTICE {} :Your operator block has been changed or removed",
+                            server.name, nick
+                        ))
+                        .await;
+                }
+            }
         }
-        let c = self.clients_pending.lock().await.remove(index.unwrap());
-        self.clients.lock().await.insert(nick, c);
-    }
 
-    pub async fn remap_nick(&self, old_nick: String, nick: String) {
-        if !self.clients.lock().await.contains_key(&old_nick) {
-            panic!("remap_nick()");
+        let motd_path = server
+            .motd_path_override
+            .clone()
+            .or(config.server.motd_path)
+            .unwrap_or(IRCD_MOTD.to_string());
+        {
+            let mut current = server.motd_path.write().await;
+            if *current != motd_path {
+                changes.push(format!("MOTD path changed to {}", motd_path));
+                *current = motd_path;
+            }
         }
+        server.reload_motd().await;
 
-        // NOTE(diath): We cannot use the let Some idiom here or we will end up with a deadlock.
-        let client = self.clients.lock().await.remove(&old_nick);
-        if client.is_some() {
-            self.clients.lock().await.insert(nick, client.unwrap());
+        let services_config = config.services.unwrap_or_default();
+        let memo_limit = services_config.memo_limit.unwrap_or(20);
+        {
+            let mut current = server.memoserv.limit.write().await;
+            if *current != memo_limit {
+                changes.push(format!("Memo limit changed to {}", memo_limit));
+                *current = memo_limit;
+            }
         }
-    }
-
-    pub async fn unmap_nick(&self, nick: String) {
-        self.clients.lock().await.remove(&nick);
-    }
 
-    pub async fn unmap_client(&self, client: &Client) {
-        let index = self
-            .clients_pending
-            .lock()
+        let session_limit = services_config.session_limit.unwrap_or(0);
+        if server
+            .operserv
+            .set_default_session_limit(session_limit)
             .await
-            .iter()
-            .position(|c| Arc::into_raw(c.clone()) == &*client);
-        if index.is_some() {
-            self.clients_pending.lock().await.remove(index.unwrap());
+        {
+            changes.push(format!("Session limit changed to {}", session_limit));
         }
-    }
 
-    pub async fn add_operator(&self, nick: String) {
-        self.operators.lock().await.insert(nick);
-    }
+        let bans = Server::get_bans(config.ban.unwrap_or_default());
+        {
+            let mut current = server.bans.lock().await;
+            let added = bans
+                .iter()
+                .filter(|ban| !current.iter().any(|other| other.mask == ban.mask))
+                .count();
+            let removed = current
+                .iter()
+                .filter(|ban| !bans.iter().any(|other| other.mask == ban.mask))
+                .count();
+
+            if added != 0 || removed != 0 {
+                changes.push(format!("Server bans: {} added, {} removed", added, removed));
+            }
+            *current = bans;
+        }
 
-    pub async fn remove_operator(&self, nick: &str) {
-        self.operators.lock().await.remove(nick);
-    }
+        let link_configs = Server::get_link_configs(config.link.unwrap_or_default(), &server.name);
+        {
+            let mut current = server.link_configs.write().await;
+            let added = link_configs
+                .iter()
+                .filter(|link| !current.iter().any(|other| other.name == link.name))
+                .count();
+            let removed = current
+                .iter()
+                .filter(|link| !link_configs.iter().any(|other| other.name == link.name))
+                .count();
+            let updated = link_configs
+                .iter()
+                .filter(|link| {
+                    current
+                        .iter()
+                        .any(|other| other.name == link.name && other != *link)
+                })
+                .count();
+
+            if added != 0 || removed != 0 || updated != 0 {
+                changes.push(format!(
+                    "Links: {} added, {} removed, {} updated",
+                    added, removed, updated
+                ));
+            }
+            *current = link_configs;
+        }
 
-    pub async fn verify_operator(&self, name: &str, password: &str) -> bool {
-        if let Some(entry) = self.operator_credentials.lock().await.get(name) {
-            return entry == password;
+        let removed = listeners
+            .keys()
+            .filter(|address| !addresses.contains(address))
+            .cloned()
+            .collect::<Vec<SocketAddr>>();
+        for address in removed {
+            if let Some(listener) = listeners.remove(&address) {
+                listener.stop.send(()).ok();
+            }
+            changes.push(format!("Stopped listening on {}", address));
         }
 
-        false
-    }
+        for (address, listener) in bound {
+            listeners.insert(address, Server::start_listener(server, address, listener));
+            changes.push(format!("Listening on {}", address));
+        }
+        drop(listeners);
 
-    pub async fn forward_message(
-        &self,
-        is_notice: bool,
-        sender: &Client,
-        name: &str,
-        message: String,
-    ) {
-        if let Some(service) = self.services.lock().await.get(name) {
-            service
-                .on_message(sender, message.split(" ").collect::<Vec<&str>>())
-                .await;
-        } else if let Some(client) = self.clients.lock().await.get(name) {
-            if is_notice {
-                log::debug!(
-                    "[NOTICE {} -> {}] {}",
-                    sender.nick.lock().await.to_string(),
-                    name,
-                    message
-                );
-            } else {
-                log::debug!(
-                    "[PRIVMSG {} -> {}] {}",
-                    sender.nick.lock().await.to_string(),
-                    name,
-                    message
-                );
-            }
+        if let Some(count) = server.scripts.reload(server, config.scripts).await {
+            changes.push(format!("Scripts: {} loaded", count));
+        }
 
-            let message = if is_notice {
-                format!(
-                    ":{} NOTICE {} :{}",
-                    sender.get_prefix().await,
-                    name,
-                    message
-                )
-            } else {
-                format!(
-                    ":{} PRIVMSG {} :{}",
-                    sender.get_prefix().await,
-                    name,
-                    message
-                )
-            };
+        if config.server.name.unwrap_or(IRCD_NAME.to_string()) != server.name {
+            changes.push("Server name changes require a restart".to_string());
+        }
 
-            client.update_idle_time().await;
-            client.send_raw(message).await;
+        if changes.is_empty() {
+            changes.push("No changes".to_string());
+        }
 
-            if !is_notice {
-                let away = client.away_message.lock().await.to_string();
-                if away.len() > 0 {
-                    sender
-                        .send_numeric_reply(NumericReply::RplAway, format!("{}: {}", name, away))
-                        .await;
-                }
-            }
-        } else {
-            sender
-                .send_numeric_reply(
-                    NumericReply::ErrNoSuchNick,
-                    format!("{} :No such nick/channel", name).to_string(),
-                )
+        for change in changes {
+            log::info!("Rehash: {}", change);
+            server
+                .broadcast_oper_notice(format!("REHASH: {}", change))
                 .await;
         }
-    }
 
-    pub async fn is_channel_mapped(&self, name: &str) -> bool {
-        self.channels
-            .lock()
-            .await
-            .contains_key(name.to_string().to_lowercase().as_str())
+        server.enforce_bans().await;
+
+        Ok(())
     }
 
-    pub async fn create_channel(&self, name: &str) {
-        log::debug!("[{}] Channel created.", name);
+    /* NOTE(diath): Operators are left connected, so that an oper can't lock themselves out by rehashing a broad ban. */
+    async fn enforce_bans(&self) {
+        for client in self.get_clients().await {
+            if *client.operator.lock().await || client.is_remote() || client.is_service() {
+                continue;
+            }
 
-        self.channels.lock().await.insert(
-            name.to_string().to_lowercase(),
-            Channel::new(name.to_string().to_lowercase()),
-        );
+            let user = client.user.lock().await.to_string();
+            let hosts = client.get_ban_hosts().await;
+            if let Some(ban) = self.find_ban(&user, &hosts).await {
+                let nick = client.nick.lock().await.to_string();
+                self.broadcast_oper_notice(format!(
+                    "Server ban active for {} ({}): {}",
+                    nick,
+                    client.address.ip(),
+                    ban.mask
+                ))
+                .await;
+
+                client.kill(&format!("Banned: {}", ban.reason)).await;
+            }
+        }
     }
 
-    pub async fn has_channel_participant(&self, name: &str, nick: &str) -> bool {
-        if let Some(channel) = self
-            .channels
-            .lock()
-            .await
-            .get(name.to_string().to_lowercase().as_str())
+    pub async fn accept(self) -> Result<Shutdown, Box<dyn std::error::Error>> {
+        let server = Arc::new(self);
+        let handoff = server.handoff.lock().await.take();
+        if let Some(state) = handoff {
+            Server::restore(&server, state).await?;
+        }
+
+        Server::spawn_services(&server).await;
+        server.scripts.reload(&server, None).await;
+
         {
-            return channel.has_participant(nick).await;
+            let mut listeners = server.listeners.lock().await;
+            for address in server.addresses.iter() {
+                if listeners.contains_key(address) {
+                    continue;
+                }
+
+                let listener = TcpListener::bind(address).await?;
+                listeners.insert(
+                    *address,
+                    Server::start_listener(&server, *address, listener),
+                );
+            }
         }
 
-        false
-    }
+        let autoconnect = server.clone();
+        tokio::spawn(async move {
+            loop {
+                Server::autoconnect(&autoconnect).await;
+                delay_for(Duration::from_secs(LINK_AUTOCONNECT_INTERVAL)).await;
+            }
+        });
+
+        let mut shutdown_receiver = match server.shutdown_receiver.lock().await.take() {
+            Some(receiver) => receiver,
+            None => return Err("The server is already running".into()),
+        };
+
+        /* NOTE(diath): SIGHUP reloads the config the same way REHASH does, SIGTERM shuts down the same way DIE does. */
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let reason = loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    log::info!("Received SIGHUP, rehashing...");
+                    server
+                        .broadcast_oper_notice("Received SIGHUP, rehashing server config file".to_string())
+                        .await;
+
+                    if let Err(error) = Server::rehash(&server).await {
+                        log::warn!("Rehash failed: {}", error);
+                        server
+                            .broadcast_oper_notice(format!("REHASH failed: {}", error))
+                            .await;
+                    }
+                }
+                _ = terminate.recv() => {
+                    log::info!("Received SIGTERM, shutting down...");
+                    break Shutdown::Die;
+                }
+                reason = shutdown_receiver.recv() => {
+                    let reason = reason.unwrap_or(Shutdown::Die);
+                    if reason != Shutdown::Upgrade {
+                        break reason;
+                    }
+
+                    match server.prepare_upgrade().await {
+                        Ok(()) => break reason,
+                        Err(error) => {
+                            log::warn!("Upgrade failed: {}", error);
+                            server
+                                .broadcast_oper_notice(format!("UPGRADE failed: {}", error))
+                                .await;
+                        }
+                    }
+                }
+            }
+        };
+
+        if reason != Shutdown::Upgrade {
+            server.shutdown().await;
+        }
+
+        Ok(reason)
+    }
+
+    /* NOTE(diath): Writes the handoff file and marks the listening and client sockets to be inherited by the exec'd binary, the connections are left open. */
+    async fn prepare_upgrade(&self) -> Result<(), String> {
+        /* NOTE(diath): The readers are paused before the snapshot so that no line is read but left out of it, they resume if the handoff is refused and are simply gone once the new binary is exec'd. */
+        let mut paused = vec![];
+        for client in self.get_clients().await {
+            if client.is_remote() || client.is_service() || !*client.registered.read().await {
+                continue;
+            }
+
+            if let Some((pending, resume)) = client.pause().await {
+                paused.push(resume);
+                if pending {
+                    let nick = client.nick.lock().await.to_string();
+                    return Err(format!("{} has unprocessed input, try again", nick));
+                }
+            }
+        }
+
+        /* NOTE(diath): Links are not handed over, the new process reconnects them and the users behind them are introduced again. */
+        self.close_links("Server restarting").await;
+
+        let mut inherited = vec![];
+        if let Err(error) = self.save_handoff(&mut inherited).await {
+            for fd in inherited {
+                handoff::set_cloexec(fd).ok();
+            }
+            return Err(error);
+        }
+
+        /* NOTE(diath): Clients that have not registered yet are not handed over, they can simply reconnect. */
+        let pending = self.clients_pending.lock().await.clone();
+        for client in pending.iter() {
+            client
+                .disconnect("ERROR :Server restarting, please reconnect")
+                .await;
+        }
+
+        self.nickserv.save().await;
+        self.hostserv.save().await;
+        self.memoserv.save().await;
+        self.operserv.save().await;
+
+        log::info!("Handing over to a new server process...");
+        /* NOTE(diath): The readers must stay paused until the exec. */
+        std::mem::forget(paused);
+        Ok(())
+    }
+
+    /* NOTE(diath): Every descriptor marked to be inherited is added to the list, so that the caller can undo it on failure. */
+    async fn save_handoff(&self, inherited: &mut Vec<RawFd>) -> Result<(), String> {
+        let mut listeners = vec![];
+        for (address, listener) in self.listeners.lock().await.iter() {
+            handoff::set_inheritable(listener.fd)?;
+            inherited.push(listener.fd);
+            listeners.push(ListenerState {
+                address: *address,
+                fd: listener.fd,
+            });
+        }
+
+        let mut clients = vec![];
+        for client in self.get_clients().await {
+            if let Some(state) = client.get_state().await {
+                handoff::set_inheritable(state.fd)?;
+                inherited.push(state.fd);
+                clients.push(state);
+            }
+        }
+
+        let mut channels = vec![];
+        for channel in self.channels.lock().await.values() {
+            channels.push(channel.get_state().await);
+        }
+
+        handoff::save(
+            &self.data_path,
+            &ServerState {
+                created: self.created.timestamp(),
+                listeners,
+                clients,
+                channels,
+            },
+        )
+    }
+
+    async fn restore(
+        server: &Arc<Server>,
+        state: ServerState,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        {
+            let mut listeners = server.listeners.lock().await;
+            for listener in state.listeners {
+                let socket = unsafe { std::net::TcpListener::from_raw_fd(listener.fd) };
+                socket.set_nonblocking(true)?;
+                listeners.insert(
+                    listener.address,
+                    Server::start_listener(
+                        server,
+                        listener.address,
+                        TcpListener::from_std(socket)?,
+                    ),
+                );
+            }
+        }
+
+        let channel_count = state.channels.len();
+        for channel in state.channels {
+            server
+                .channels
+                .lock()
+                .await
+                .insert(channel.name.to_string(), Channel::from_state(channel));
+        }
+
+        let client_count = state.clients.len();
+        for client in state.clients {
+            let socket = unsafe { std::net::TcpStream::from_raw_fd(client.fd) };
+            socket.set_nonblocking(true)?;
+            let stream = TcpStream::from_std(socket)?;
+
+            let nick = client.nick.to_string();
+            let operator = client.operator;
+            let client = Arc::new(Client::from_state(server.clone(), client));
+            server
+                .clients
+                .lock()
+                .await
+                .insert(nick.to_string(), client.clone());
+            if operator {
+                server.add_operator(nick).await;
+            }
+
+            Server::spawn_client(client, stream);
+        }
+
+        log::info!(
+            "Resumed {} clients and {} channels.",
+            client_count,
+            channel_count
+        );
+
+        Ok(())
+    }
+
+    /* NOTE(diath): Every service gets a pseudo-client, so that it can be seen in WHOIS, NAMES and the like and its nick can't be taken. */
+    async fn spawn_services(server: &Arc<Server>) {
+        for nick in server.services.keys() {
+            let client = match Server::add_pseudo_client(server, nick).await {
+                Some(client) => client,
+                None => continue,
+            };
+
+            for channel_name in server.service_channels.iter() {
+                if !channel_name.starts_with('#') {
+                    continue;
+                }
+
+                if !server.is_channel_mapped(channel_name).await {
+                    server.create_channel(channel_name).await;
+                }
+
+                server
+                    .join_channel(&client, channel_name, String::new())
+                    .await;
+                client
+                    .channels
+                    .lock()
+                    .await
+                    .insert(channel_name.to_lowercase());
+            }
+        }
+    }
+
+    /* NOTE(diath): Creates a local client without a connection, used by the services and the script bots. */
+    pub async fn add_pseudo_client(server: &Arc<Server>, nick: &str) -> Option<Arc<Client>> {
+        let mut clients = server.clients.lock().await;
+        if clients.contains_key(nick) {
+            return None;
+        }
+
+        let client = Arc::new(Client::new_service(server.clone(), nick));
+        clients.insert(nick.to_string(), client.clone());
+        Some(client)
+    }
+
+    fn get_service(&self, name: &str) -> Option<(&String, &Arc<dyn Service + Send + Sync>)> {
+        self.services
+            .iter()
+            .find(|(nick, _)| nick.eq_ignore_ascii_case(name))
+    }
+
+    /* NOTE(diath): Returns false if there is no such service, the text is handed over verbatim for the service to tokenize. */
+    pub async fn message_service(&self, sender: &Client, name: &str, text: &str) -> bool {
+        match self.get_service(name) {
+            Some((_, service)) => {
+                service.on_message(sender, text).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_service_nick(&self, name: &str) -> bool {
+        self.get_service(name).is_some()
+    }
+
+    /* NOTE(diath): Services only hear about the events of local clients, remote ones are handled by the services of their own server. */
+    pub fn get_services(&self) -> Vec<Arc<dyn Service + Send + Sync>> {
+        self.services.values().cloned().collect()
+    }
+
+    pub fn get_service_prefix(&self, nick: &str) -> String {
+        format!("{}!services@services.{}", nick, self.name)
+    }
+
+    pub fn request_shutdown(&self, reason: Shutdown) {
+        self.shutdown_sender.send(reason).ok();
+    }
+
+    /* NOTE(diath): Stops accepting connections, says goodbye to every client and writes the services data to disk. */
+    async fn shutdown(&self) {
+        self.close_links("Server shutting down").await;
+
+        for (address, listener) in self.listeners.lock().await.drain() {
+            log::debug!("Closing listener on {}.", address);
+            listener.stop.send(()).ok();
+        }
+
+        let mut clients = self.get_clients().await;
+        clients.extend(self.clients_pending.lock().await.iter().cloned());
+        for client in clients.iter() {
+            if client.is_remote() || client.is_service() {
+                continue;
+            }
+
+            self.nickserv.update_last_seen(client).await;
+            client.disconnect("ERROR :Server shutting down").await;
+        }
+
+        self.nickserv.save().await;
+        self.hostserv.save().await;
+        self.memoserv.save().await;
+        self.operserv.save().await;
+
+        log::info!("Server shut down.");
+    }
+
+    fn start_listener(
+        server: &Arc<Server>,
+        address: SocketAddr,
+        mut listener: TcpListener,
+    ) -> Listener {
+        let fd = listener.as_raw_fd();
+        let (sender, mut receiver) = oneshot::channel();
+        let server = server.clone();
+        tokio::spawn(async move {
+            log::info!("Listening on {}...", address);
+            loop {
+                tokio::select! {
+                    result = listener.accept() => match result {
+                        Ok((stream, addr)) => Server::on_connection(&server, stream, addr).await,
+                        Err(error) => log::warn!("Failed to accept a connection on {}: {}", address, error),
+                    },
+                    _ = &mut receiver => break,
+                }
+            }
+            log::info!("Stopped listening on {}.", address);
+        });
+
+        Listener { fd, stop: sender }
+    }
+
+    async fn on_connection(server: &Arc<Server>, mut stream: TcpStream, addr: SocketAddr) {
+        let ip = addr.ip().to_string();
+        let limit = server.operserv.get_session_limit(&ip).await;
+        if limit != 0 && server.count_sessions(&ip).await >= limit {
+            log::debug!("Session limit exceeded ({}).", addr);
+            stream
+                .write_all(b"ERROR :Closing Link: Session limit exceeded\r\n")
+                .await
+                .ok();
+            server
+                .broadcast_oper_notice(format!("Session limit exceeded for {}", ip))
+                .await;
+            return;
+        }
+
+        let client = Arc::new(Client::new(server.clone(), addr));
+
+        log::debug!("Client connected ({}).", addr);
+        Server::spawn_client(client.clone(), stream);
+        server.clients_pending.lock().await.push(client.clone());
+
+        for service in server.get_services() {
+            service.on_connect(&client).await;
+        }
+    }
+
+    fn spawn_client(client: Arc<Client>, stream: TcpStream) {
+        let c = Mutex::new(client.clone());
+        tokio::spawn(async move {
+            c.lock().await.task(stream).await;
+        });
+
+        let c2 = Mutex::new(client);
+        tokio::spawn(async move {
+            c2.lock().await.task_ping().await;
+        });
+    }
+
+    pub async fn is_nick_mapped(&self, name: &str) -> bool {
+        self.clients.lock().await.contains_key(name)
+    }
+
+    pub async fn get_client(&self, name: &str) -> Option<Arc<Client>> {
+        self.clients.lock().await.get(name).cloned()
+    }
+
+    pub async fn get_clients(&self) -> Vec<Arc<Client>> {
+        self.clients.lock().await.values().cloned().collect()
+    }
+
+    pub async fn count_sessions(&self, ip: &str) -> usize {
+        let mut count = 0;
+        for client in self.clients.lock().await.values() {
+            if !client.is_remote() && !client.is_service() && client.address.ip().to_string() == ip
+            {
+                count += 1;
+            }
+        }
+
+        for client in &*self.clients_pending.lock().await {
+            if client.address.ip().to_string() == ip {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    pub async fn get_stats(&self) -> ServerStats {
+        ServerStats {
+            clients: self.clients.lock().await.len(),
+            pending: self.clients_pending.lock().await.len(),
+            operators: self.operators.lock().await.len(),
+            channels: self.channels.lock().await.len(),
+        }
+    }
+
+    pub async fn map_nick(&self, nick: String, client: &Client) {
+        let index = self
+            .clients_pending
+            .lock()
+            .await
+            .iter()
+            .position(|c| Arc::into_raw(c.clone()) == &*client);
+        if index.is_none() {
+            panic!("map_nick()");
+        }
+        let c = self.clients_pending.lock().await.remove(index.unwrap());
+        (*c.nick_ts.write().await) = Utc::now().timestamp();
+        self.clients.lock().await.insert(nick, c);
+    }
+
+    pub async fn remap_nick(&self, old_nick: String, nick: String) {
+        if !self.clients.lock().await.contains_key(&old_nick) {
+            panic!("remap_nick()");
+        }
+
+        // NOTE(diath): We cannot use the let Some idiom here or we will end up with a deadlock.
+        let client = self.clients.lock().await.remove(&old_nick);
+        if client.is_some() {
+            self.clients.lock().await.insert(nick, client.unwrap());
+        }
+    }
+
+    pub async fn change_nick(&self, client: &Client, nick: &str) {
+        let old_nick = client.nick.lock().await.to_string();
+        let prefix = client.get_prefix().await;
+
+        self.append_nick_history(old_nick.to_string(), client).await;
+        self.remap_nick(old_nick.to_string(), nick.to_string())
+            .await;
+
+        if self.operators.lock().await.remove(&old_nick) {
+            self.operators.lock().await.insert(nick.to_string());
+        }
+
+        /* NOTE(diath): Remote users arrive with the timestamp of their server. */
+        if !client.is_remote() {
+            (*client.nick_ts.write().await) = Utc::now().timestamp();
+        }
+
+        let mut targets = HashSet::new();
+        targets.insert(nick.to_string());
+        for channel_name in &*client.channels.lock().await {
+            if let Some(channel) = self
+                .channels
+                .lock()
+                .await
+                .get(channel_name.to_string().to_lowercase().as_str())
+            {
+                channel.rename(&old_nick, nick).await;
+                for target in channel.participants.read().await.keys() {
+                    targets.insert(target.clone());
+                }
+            }
+        }
+
+        (*client.nick.lock().await) = nick.to_string();
+
+        if *client.registered.read().await {
+            let tags = MessageTags::new(&[]);
+            let message = format!(":{} NICK :{}", prefix, nick);
+            for target in targets {
+                if let Some(client) = self.clients.lock().await.get(&target) {
+                    client.send_tagged(&tags, message.clone()).await;
+                }
+            }
+
+            let ts = *client.nick_ts.read().await;
+            self.propagate(client, format!(":{} NICK {} {}", old_nick, nick, ts))
+                .await;
+        }
+    }
+
+    pub async fn change_host(&self, client: &Client, ident: Option<String>, host: UserHost) {
+        let prefix = client.get_prefix().await;
+        let old_user = client.get_user().await;
+        let old_host = client.get_host().await;
+
+        (*client.vident.lock().await) = ident;
+        (*client.host.lock().await) = host;
+
+        let user = client.get_user().await;
+        let host = client.get_host().await;
+        if !*client.registered.read().await || (user == old_user && host == old_host) {
+            return;
+        }
+
+        let nick = client.nick.lock().await.to_string();
+        let new_prefix = client.get_prefix().await;
+        let chghost = format!(":{} CHGHOST {} {}", prefix, user, host);
+        self.propagate(client, format!(":{} CHGHOST {} {}", nick, user, host))
+            .await;
+
+        /* NOTE(diath): Clients without the chghost capability see the user quit and rejoin every shared channel with the new host. */
+        let mut targets: HashMap<String, Vec<String>> = HashMap::new();
+        for channel_name in &*client.channels.lock().await {
+            if let Some(channel) = self
+                .channels
+                .lock()
+                .await
+                .get(channel_name.to_string().to_lowercase().as_str())
+            {
+                let participants = channel.participants.read().await;
+                let modes = match participants.get(&nick) {
+                    Some(modes) => {
+                        let mut letters = String::new();
+                        let mut nicks = vec![];
+                        for (flag, letter) in [
+                            (modes.owner, 'q'),
+                            (modes.admin, 'a'),
+                            (modes.operator, 'o'),
+                            (modes.half_operator, 'h'),
+                            (modes.voiced, 'v'),
+                        ]
+                        .iter()
+                        {
+                            if *flag {
+                                letters.push(*letter);
+                                nicks.push(nick.to_string());
+                            }
+                        }
+
+                        if letters.is_empty() {
+                            None
+                        } else {
+                            Some(format!("+{} {}", letters, nicks.join(" ")))
+                        }
+                    }
+                    None => None,
+                };
+
+                let mut messages = vec![format!(":{} JOIN {}", new_prefix, channel.name)];
+                if let Some(modes) = modes {
+                    messages.push(format!(":{} MODE {} {}", self.name, channel.name, modes));
+                }
+
+                for target in participants.keys() {
+                    if *target != nick {
+                        targets
+                            .entry(target.to_string())
+                            .or_default()
+                            .extend(messages.iter().cloned());
+                    }
+                }
+            }
+        }
+
+        for (target, messages) in targets {
+            if let Some(target) = self.clients.lock().await.get(&target) {
+                if target.has_capability("chghost").await {
+                    target.send_raw(chghost.clone()).await;
+                } else {
+                    target
+                        .send_raw(format!(":{} QUIT :Changing host", prefix))
+                        .await;
+                    for message in messages {
+                        target.send_raw(message).await;
+                    }
+                }
+            }
+        }
+
+        if client.has_capability("chghost").await {
+            client.send_raw(chghost).await;
+        } else {
+            client
+                .send_numeric_reply(
+                    NumericReply::RplHostHidden,
+                    format!("{} :is now your displayed host", host),
+                )
+                .await;
+        }
+    }
+
+    pub async fn unmap_nick(&self, nick: String) {
+        self.clients.lock().await.remove(&nick);
+    }
+
+    pub async fn unmap_client(&self, client: &Client) {
+        let index = self
+            .clients_pending
+            .lock()
+            .await
+            .iter()
+            .position(|c| Arc::into_raw(c.clone()) == &*client);
+        if index.is_some() {
+            self.clients_pending.lock().await.remove(index.unwrap());
+        }
+    }
+
+    pub async fn add_operator(&self, nick: String) {
+        self.operators.lock().await.insert(nick);
+    }
+
+    pub async fn remove_operator(&self, nick: &str) {
+        self.operators.lock().await.remove(nick);
+    }
+
+    /* NOTE(diath): Returns the privileges of the operator block if the credentials match. */
+    pub async fn verify_operator(&self, name: &str, password: &str) -> Option<HashSet<String>> {
+        if let Some(entry) = self.operator_credentials.lock().await.get(name) {
+            if entry.password == password {
+                return Some(entry.privileges.clone());
+            }
+        }
+
+        None
+    }
+
+    /* NOTE(diath): Returns false if the message was rejected. */
+    pub async fn forward_message(
+        &self,
+        kind: MessageKind,
+        sender: &Client,
+        name: &str,
+        message: String,
+        tags: &[Tag],
+    ) -> bool {
+        let tags = MessageTags::new(tags);
+        let line = kind.format(&sender.get_prefix().await, name, &message);
+
+        if self.is_service_nick(name) {
+            if kind != MessageKind::Tagmsg {
+                self.echo_message(sender, &tags, line).await;
+                self.message_service(sender, name, &message).await;
+            }
+            return true;
+        }
+
+        if let Some(client) = self.clients.lock().await.get(name) {
+            log::debug!(
+                "[{} {} -> {}] {}",
+                kind.get_command(),
+                sender.nick.lock().await.to_string(),
+                name,
+                message
+            );
+
+            client.update_idle_time().await;
+            if client.is_remote() {
+                self.route(sender, client, tags.apply(line.clone())).await;
+            } else if kind != MessageKind::Tagmsg || client.has_capability("message-tags").await {
+                client.send_tagged(&tags, line.clone()).await;
+            }
+
+            self.echo_message(sender, &tags, line).await;
+
+            if kind == MessageKind::Privmsg {
+                let away = client.away_message.lock().await.to_string();
+                if away.len() > 0 {
+                    sender
+                        .send_numeric_reply(NumericReply::RplAway, format!("{}: {}", name, away))
+                        .await;
+                }
+            }
+
+            true
+        } else {
+            sender
+                .send_numeric_reply(
+                    NumericReply::ErrNoSuchNick,
+                    format!("{} :No such nick/channel", name).to_string(),
+                )
+                .await;
+
+            // NOTE(diath): Let the sender know they can still reach a registered nick through MemoServ.
+            if kind == MessageKind::Privmsg && self.nickserv.is_registered(name).await {
+                sender
+                    .send_tagged(
+                        &MessageTags::new(&[]),
+                        format!(
+                            ":{} NOTICE {} :{} is offline, you can leave them a memo with /msg MemoServ SEND {} <text>",
+                            self.get_service_prefix("MemoServ"),
+                            sender.nick.lock().await,
+                            name,
+                            name
+                        ),
+                    )
+                    .await;
+            }
+
+            false
+        }
+    }
+
+    /* NOTE(diath): An accepted message goes back to a sender that negotiated echo-message, with the same tags the recipients got. */
+    async fn echo_message(&self, sender: &Client, tags: &MessageTags, line: String) {
+        if sender.has_capability("echo-message").await {
+            sender.send_tagged(tags, line).await;
+        }
+    }
+
+    pub async fn is_channel_mapped(&self, name: &str) -> bool {
+        self.channels
+            .lock()
+            .await
+            .contains_key(name.to_string().to_lowercase().as_str())
+    }
+
+    pub async fn create_channel(&self, name: &str) {
+        log::debug!("[{}] Channel created.", name);
+
+        self.channels.lock().await.insert(
+            name.to_string().to_lowercase(),
+            Channel::new(name.to_string().to_lowercase()),
+        );
+    }
+
+    pub async fn has_channel_participant(&self, name: &str, nick: &str) -> bool {
+        if let Some(channel) = self
+            .channels
+            .lock()
+            .await
+            .get(name.to_string().to_lowercase().as_str())
+        {
+            return channel.has_participant(nick).await;
+        }
+
+        false
+    }
 
     pub async fn join_channel(
         &self,
@@ -370,7 +1550,15 @@ impl Server {
                     return false;
                 }
 
-                if channel.is_banned(&prefix).await && !channel.is_ban_exempt(&prefix).await {
+                /* NOTE(diath): Bans on cloaks made with a rotated out key must keep matching. */
+                let mut banned = false;
+                let mut exempt = false;
+                for prefix in client.get_ban_prefixes().await {
+                    banned = banned || channel.is_banned(&prefix).await;
+                    exempt = exempt || channel.is_ban_exempt(&prefix).await;
+                }
+
+                if banned && !exempt {
                     client
                         .send_numeric_reply(
                             NumericReply::ErrBannedFromChan,
@@ -397,10 +1585,11 @@ impl Server {
                 },
             );
 
+            let tags = MessageTags::new(&[]);
             let message = format!(":{} JOIN {}", client.get_prefix().await, channel_name);
             for target in participants.keys() {
                 if let Some(client) = self.clients.lock().await.get(target) {
-                    client.send_raw(message.clone()).await;
+                    client.send_tagged(&tags, message.clone()).await;
                 }
             }
 
@@ -432,6 +1621,23 @@ impl Server {
                 }
 
                 log::debug!("[{}] {} joined.", channel_name, nick);
+
+                let prefixes = match participants.get(&nick) {
+                    Some(modes) => modes.get_prefixes(),
+                    None => String::new(),
+                };
+                self.propagate(
+                    client,
+                    format!(
+                        ":{} SJOIN {} {} + :{}{}",
+                        self.name,
+                        *channel.created_at.read().await,
+                        channel.name,
+                        prefixes,
+                        nick
+                    ),
+                )
+                .await;
                 return true;
             }
         }
@@ -455,7 +1661,8 @@ impl Server {
             .get(channel_name.to_string().to_lowercase().as_str())
         {
             let nick = client.nick.lock().await.to_string();
-            if channel.part(nick).await {
+            if channel.part(nick.to_string()).await {
+                let tags = MessageTags::new(&[]);
                 let message = format!(
                     ":{} PART {} :{}",
                     client.get_prefix().await,
@@ -465,12 +1672,17 @@ impl Server {
 
                 for target in channel.participants.read().await.keys() {
                     if let Some(client) = self.clients.lock().await.get(target) {
-                        client.send_raw(message.clone()).await;
+                        client.send_tagged(&tags, message.clone()).await;
                     }
                 }
 
                 /* NOTE(diath): We need to send the confirmation to the sending client separately as they are no longer in the channel participant list. */
-                client.send_raw(message.clone()).await;
+                client.send_tagged(&tags, message.clone()).await;
+                self.propagate(
+                    client,
+                    format!(":{} PART {} :{}", nick, channel.name, part_message),
+                )
+                .await;
 
                 if channel.participants.read().await.len() == 0 {
                     remove = true;
@@ -498,7 +1710,7 @@ impl Server {
             .get(channel_name.to_string().to_lowercase().as_str())
         {
             let nick = client.nick.lock().await.to_string();
-            let oper = *client.operator.lock().await;
+            let oper = *client.operator.lock().await || client.is_remote();
             if !oper && !channel.is_operator(&nick).await {
                 client
                     .send_numeric_reply(
@@ -516,6 +1728,11 @@ impl Server {
                 .insert(invited_nick.to_string());
 
             self.broadcast_invite(client, channel, &invited_nick).await;
+            self.propagate(
+                client,
+                format!(":{} INVITE {} {}", nick, invited_nick, channel.name),
+            )
+            .await;
 
             client
                 .send_numeric_reply(
@@ -555,7 +1772,7 @@ impl Server {
             .get(channel_name.to_string().to_lowercase().as_str())
         {
             let nick = client.nick.lock().await.to_string();
-            let oper = *client.operator.lock().await;
+            let oper = *client.operator.lock().await || client.is_remote();
             if !oper && !channel.is_half_operator(&nick).await {
                 client
                     .send_numeric_reply(
@@ -567,6 +1784,7 @@ impl Server {
             }
 
             if oper || nick == kicked.to_string() || channel.has_access(&nick, kicked).await {
+                let tags = MessageTags::new(&[]);
                 let message = format!(
                     ":{} KICK {} {} :{}",
                     client.get_prefix().await,
@@ -576,10 +1794,19 @@ impl Server {
                 );
                 for target in channel.participants.read().await.keys() {
                     if let Some(client) = self.clients.lock().await.get(target) {
-                        client.send_raw(message.clone()).await;
+                        client.send_tagged(&tags, message.clone()).await;
                     }
                 }
 
+                self.propagate(
+                    client,
+                    format!(
+                        ":{} KICK {} {} :{}",
+                        nick, channel.name, kicked, kick_message
+                    ),
+                )
+                .await;
+
                 channel.remove(kicked.to_string()).await;
                 if channel.participants.read().await.len() == 0 {
                     remove = true;
@@ -599,13 +1826,15 @@ impl Server {
         result
     }
 
+    /* NOTE(diath): Returns false if the message was rejected. */
     pub async fn forward_channel_message(
         &self,
-        is_notice: bool,
+        kind: MessageKind,
         client: &Client,
         name: &str,
         message: String,
-    ) {
+        tags: &[Tag],
+    ) -> bool {
         if let Some(channel) = self
             .channels
             .lock()
@@ -615,8 +1844,8 @@ impl Server {
             let prefix = client.get_prefix().await;
             let nick = client.nick.lock().await.to_string();
 
-            // NOTE(diath): Operators can always send messages to any channel.
-            if !*client.operator.lock().await {
+            // NOTE(diath): Operators can always send messages to any channel, remote users have been checked by their server.
+            if !*client.operator.lock().await && !client.is_remote() {
                 let modes = channel.modes.lock().await;
                 if modes.no_external_messages && !channel.has_participant(&nick).await {
                     client
@@ -626,7 +1855,7 @@ impl Server {
                                 .to_string(),
                         )
                         .await;
-                    return;
+                    return false;
                 }
 
                 if modes.moderated && !channel.is_voiced(&nick).await {
@@ -636,27 +1865,37 @@ impl Server {
                             format!("{} :You need voice (+v) ({})", &name, &name).to_string(),
                         )
                         .await;
-                    return;
+                    return false;
                 }
             }
 
             log::debug!("[{}] {}: {}", name, prefix, message);
 
-            let message = if is_notice {
-                format!(":{} NOTICE {} :{}", prefix, name, message)
-            } else {
-                format!(":{} PRIVMSG {} :{}", prefix, name, message)
-            };
+            let tags = MessageTags::new(tags);
+            self.propagate(
+                client,
+                tags.apply(kind.format(&nick, &channel.name, &message)),
+            )
+            .await;
+
+            let message = kind.format(&prefix, name, &message);
 
             for target in channel.participants.read().await.keys() {
                 if let Some(client) = self.clients.lock().await.get(target) {
-                    if client.get_prefix().await != prefix {
-                        client.send_raw(message.clone()).await;
+                    if client.get_prefix().await == prefix
+                        || (kind == MessageKind::Tagmsg
+                            && !client.has_capability("message-tags").await)
+                    {
+                        continue;
                     }
+
+                    client.send_tagged(&tags, message.clone()).await;
                 }
             }
 
+            self.echo_message(client, &tags, message).await;
             client.update_idle_time().await;
+            true
         } else {
             client
                 .send_numeric_reply(
@@ -664,6 +1903,7 @@ impl Server {
                     format!("{} :No such nick/channel", &name).to_string(),
                 )
                 .await;
+            false
         }
     }
 
@@ -711,7 +1951,7 @@ impl Server {
             .get(channel_name.to_string().to_lowercase().as_str())
         {
             let nick = client.nick.lock().await.to_string();
-            let oper = *client.operator.lock().await;
+            let oper = *client.operator.lock().await || client.is_remote();
             if !oper
                 && channel.modes.lock().await.restrict_topic
                 && !channel.is_operator(&nick).await
@@ -729,6 +1969,7 @@ impl Server {
             // NOTE(diath): The topic sender should be just the name, not the prefix.
             channel.set_topic(nick.to_string(), topic.clone()).await;
 
+            let tags = MessageTags::new(&[]);
             let message = format!(
                 ":{} TOPIC {} :{}",
                 client.get_prefix().await,
@@ -738,18 +1979,32 @@ impl Server {
             for target in channel.participants.read().await.keys() {
                 if let Some(client) = self.clients.lock().await.get(target) {
                     if client.get_prefix().await != nick.to_string() {
-                        client.send_raw(message.clone()).await;
+                        client.send_tagged(&tags, message.clone()).await;
                     }
                 }
             }
+
+            self.propagate(
+                client,
+                format!(":{} TOPIC {} :{}", nick, channel.name, topic),
+            )
+            .await;
         }
     }
 
     pub async fn remove_from_channels(&self, client: &Client) {
         let nick = client.nick.lock().await.to_string();
+        let mut channels = self.channels.lock().await;
         for channel_name in &*client.channels.lock().await {
-            if let Some(channel) = self.channels.lock().await.get(channel_name) {
+            let name = channel_name.to_lowercase();
+            let mut empty = false;
+            if let Some(channel) = channels.get(&name) {
                 channel.remove(nick.to_string()).await;
+                empty = channel.participants.read().await.is_empty();
+            }
+
+            if empty {
+                channels.remove(&name);
             }
         }
     }
@@ -891,7 +2146,7 @@ impl Server {
         client: &Client,
         participant: &Client,
     ) {
-        let user = participant.user.lock().await.to_string();
+        let user = participant.get_user().await;
         let host = participant.get_host().await;
         let nick = participant.nick.lock().await.to_string();
         let real_name = participant.real_name.lock().await.to_string();
@@ -925,12 +2180,18 @@ impl Server {
             flags.push(' ');
         }
 
+        let server = participant.get_server_name();
+        let hops = match self.get_remote_server(&server).await {
+            Some(remote) => remote.hops,
+            None => 0,
+        };
+
         client
             .send_numeric_reply(
                 NumericReply::RplWhoReply,
                 format!(
-                    "{} {} {} {} {} {}:0 {}",
-                    channel_name, user, host, self.name, nick, flags, real_name
+                    "{} {} {} {} {} {}:{} {}",
+                    channel_name, user, host, server, nick, flags, hops, real_name
                 ),
             )
             .await;
@@ -971,7 +2232,7 @@ impl Server {
     pub async fn send_whois(&self, client: &Client, target_nick: &str) {
         if let Some(target) = self.clients.lock().await.get(target_nick) {
             let nick = target.nick.lock().await.to_string();
-            let user = target.user.lock().await.to_string();
+            let user = target.get_user().await;
             let host = target.get_host().await;
             let real_name = target.real_name.lock().await.to_string();
 
@@ -982,7 +2243,7 @@ impl Server {
                 )
                 .await;
 
-            if *target.identified.lock().await {
+            if self.nickserv.is_identified_for(target, &nick).await {
                 client
                     .send_numeric_reply(
                         NumericReply::RplUserIsRegNick,
@@ -991,10 +2252,25 @@ impl Server {
                     .await;
             }
 
+            let account = target.account.lock().await.clone();
+            if let Some(account) = account {
+                client
+                    .send_numeric_reply(
+                        NumericReply::RplWhoisAccount,
+                        format!("{} {} :is logged in as", nick, account),
+                    )
+                    .await;
+            }
+
+            let server = target.get_server_name();
+            let description = match self.get_remote_server(&server).await {
+                Some(remote) => remote.description,
+                None => self.description.to_string(),
+            };
             client
                 .send_numeric_reply(
                     NumericReply::RplWhoisServer,
-                    format!("{} {} :{}", nick, self.name, IRCD_NAME),
+                    format!("{} {} :{}", nick, server, description),
                 )
                 .await;
 
@@ -1007,6 +2283,20 @@ impl Server {
                     .await;
             }
 
+            if *client.operator.lock().await {
+                if let Some(listing) = &*target.dnsbl_listing.lock().await {
+                    client
+                        .send_numeric_reply(
+                            NumericReply::RplWhoisSpecial,
+                            format!(
+                                "{} :is listed in {} ({})",
+                                nick, listing.zone, listing.reason
+                            ),
+                        )
+                        .await;
+                }
+            }
+
             let away_message = target.away_message.lock().await.to_string();
             if away_message.len() > 0 {
                 client
@@ -1106,20 +2396,44 @@ impl Server {
     }
 
     pub async fn broadcast_quit(&self, client: &Client, reason: &str) {
+        self.notify_quit(client, reason).await;
+
+        /* NOTE(diath): A local user can end up here more than once on the way out, the links only hear about it the first time. Those who leave without a reason are announced when their connection is cleaned up. */
+        if !client.is_remote() {
+            let mut quit_reason = client.quit_reason.lock().await;
+            if quit_reason.is_some() {
+                return;
+            }
+            *quit_reason = Some(reason.to_string());
+        }
+
+        let nick = client.nick.lock().await.to_string();
+        self.propagate(client, format!(":{} QUIT :{}", nick, reason))
+            .await;
+    }
+
+    /* NOTE(diath): Lets the local users sharing a channel know, without passing it on to the links. */
+    async fn notify_quit(&self, client: &Client, reason: &str) {
         let mut targets = HashSet::new();
 
         for channel_name in &*client.channels.lock().await {
-            if let Some(channel) = self.channels.lock().await.get(channel_name) {
+            if let Some(channel) = self
+                .channels
+                .lock()
+                .await
+                .get(channel_name.to_lowercase().as_str())
+            {
                 for target in channel.participants.read().await.keys() {
                     targets.insert(target.clone());
                 }
             }
         }
 
+        let tags = MessageTags::new(&[]);
         let message = format!(":{} QUIT :{}", client.get_prefix().await, reason);
         for target in targets {
             if let Some(client) = self.clients.lock().await.get(&target) {
-                client.send_raw(message.clone()).await;
+                client.send_tagged(&tags, message.clone()).await;
             }
         }
     }
@@ -1149,15 +2463,19 @@ impl Server {
     }
 
     pub async fn broadcast_oper_notice(&self, message: String) {
+        let tags = MessageTags::new(&[]);
         for nick in &*self.operators.lock().await {
             if let Some(client) = self.clients.lock().await.get(nick) {
                 client
-                    .send_raw(format!(
-                        ":{} NOTICE {} :{}",
-                        self.name,
-                        self.name,
-                        message.to_string(),
-                    ))
+                    .send_tagged(
+                        &tags,
+                        format!(
+                            ":{} NOTICE {} :{}",
+                            self.name,
+                            self.name,
+                            message.to_string(),
+                        ),
+                    )
                     .await;
             }
         }
@@ -1169,9 +2487,14 @@ impl Server {
         channel_name: &str,
         params: Vec<String>,
     ) {
-        if let Some(channel) = self.channels.lock().await.get(channel_name) {
+        if let Some(channel) = self
+            .channels
+            .lock()
+            .await
+            .get(channel_name.to_lowercase().as_str())
+        {
             let nick = client.nick.lock().await.to_string();
-            let oper = *client.operator.lock().await;
+            let oper = *client.operator.lock().await || client.is_remote();
             let has_participant = channel.has_participant(&nick).await;
 
             if params.len() < 1 {
@@ -1209,6 +2532,7 @@ impl Server {
 
                         log::debug!("[{}] Mode {}.", channel_name, changes);
 
+                        let tags = MessageTags::new(&[]);
                         let message = format!(
                             ":{} MODE {} {}",
                             client.get_prefix().await,
@@ -1217,9 +2541,15 @@ impl Server {
                         );
                         for target in targets {
                             if let Some(client) = self.clients.lock().await.get(&target) {
-                                client.send_raw(message.clone()).await;
+                                client.send_tagged(&tags, message.clone()).await;
                             }
                         }
+
+                        self.propagate(
+                            client,
+                            format!(":{} MODE {} {}", nick, channel.name, changes),
+                        )
+                        .await;
                     }
                 } else {
                     client
@@ -1241,6 +2571,72 @@ impl Server {
         }
     }
 
+    pub async fn override_channel_mode(
+        &self,
+        client: &Client,
+        channel_name: &str,
+        params: Vec<String>,
+    ) -> Option<String> {
+        let prefix = self.get_service_prefix("OperServ");
+        self.force_channel_mode(client, &prefix, channel_name, params)
+            .await
+    }
+
+    /* NOTE(diath): Mode changes from a linked server, the client stands in for the server itself. */
+    pub async fn server_channel_mode(
+        &self,
+        client: &Client,
+        channel_name: &str,
+        params: Vec<String>,
+    ) -> Option<String> {
+        let prefix = client.get_server_name();
+        self.force_channel_mode(client, &prefix, channel_name, params)
+            .await
+    }
+
+    async fn force_channel_mode(
+        &self,
+        client: &Client,
+        prefix: &str,
+        channel_name: &str,
+        params: Vec<String>,
+    ) -> Option<String> {
+        if let Some(channel) = self
+            .channels
+            .lock()
+            .await
+            .get(channel_name.to_string().to_lowercase().as_str())
+        {
+            let changes = channel.toggle_modes(client, params).await;
+            if changes.len() > 0 {
+                log::debug!("[{}] Mode {} (override).", channel_name, changes);
+
+                let tags = MessageTags::new(&[]);
+                let message = format!(":{} MODE {} {}", prefix, channel_name, changes);
+                for target in channel.participants.read().await.keys() {
+                    if let Some(client) = self.clients.lock().await.get(target) {
+                        client.send_tagged(&tags, message.clone()).await;
+                    }
+                }
+
+                self.propagate(
+                    client,
+                    format!(
+                        ":{} MODE {} {}",
+                        client.get_server_name(),
+                        channel.name,
+                        changes
+                    ),
+                )
+                .await;
+            }
+
+            return Some(changes);
+        }
+
+        None
+    }
+
     pub async fn handle_user_mode(&self, client: &Client, target_nick: &str, params: Vec<String>) {
         if self.is_nick_mapped(&target_nick).await {
             let nick = client.nick.lock().await.to_string();
@@ -1249,12 +2645,15 @@ impl Server {
                     let changes = client.toggle_modes(params).await;
                     if changes.len() > 0 {
                         client
-                            .send_raw(format!(
-                                ":{} MODE {} :{}",
-                                self.name,
-                                client.nick.lock().await.to_string(),
-                                changes
-                            ))
+                            .send_tagged(
+                                &MessageTags::new(&[]),
+                                format!(
+                                    ":{} MODE {} :{}",
+                                    self.name,
+                                    client.nick.lock().await.to_string(),
+                                    changes
+                                ),
+                            )
                             .await;
                     }
                 } else {
@@ -1314,6 +2713,585 @@ impl Server {
             .await;
     }
 
+    pub async fn get_link_config(&self, name: &str) -> Option<LinkConfig> {
+        self.link_configs
+            .read()
+            .await
+            .iter()
+            .find(|link| {
+                link.name
+                    .as_ref()
+                    .is_some_and(|other| other.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+    }
+
+    pub async fn check_link(&self, name: &str, password: &str) -> bool {
+        self.get_link_config(name)
+            .await
+            .is_some_and(|link| link.password.as_deref() == Some(password))
+    }
+
+    pub async fn is_server_known(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(&self.name)
+            || self
+                .remote_servers
+                .lock()
+                .await
+                .contains_key(&name.to_lowercase())
+    }
+
+    pub async fn get_remote_server(&self, name: &str) -> Option<RemoteServer> {
+        self.remote_servers
+            .lock()
+            .await
+            .get(&name.to_lowercase())
+            .cloned()
+    }
+
+    pub async fn get_server_link(&self, name: &str) -> Option<String> {
+        self.get_remote_server(name).await.map(|server| server.link)
+    }
+
+    /* NOTE(diath): Returns the name, uplink, hop count and description of every known server, starting with ourselves. */
+    pub async fn get_servers(&self) -> Vec<(String, String, u32, String)> {
+        let mut servers = self
+            .remote_servers
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<RemoteServer>>();
+        servers.sort_by(|a, b| a.hops.cmp(&b.hops).then_with(|| a.name.cmp(&b.name)));
+
+        iter::once((
+            self.name.to_string(),
+            self.name.to_string(),
+            0,
+            self.description.to_string(),
+        ))
+        .chain(
+            servers
+                .into_iter()
+                .map(|server| (server.name, server.uplink, server.hops, server.description)),
+        )
+        .collect()
+    }
+
+    pub async fn begin_connect(&self, name: &str) -> bool {
+        self.links_connecting
+            .lock()
+            .await
+            .insert(name.to_lowercase())
+    }
+
+    pub async fn end_connect(&self, name: &str) {
+        self.links_connecting
+            .lock()
+            .await
+            .remove(&name.to_lowercase());
+    }
+
+    async fn autoconnect(server: &Arc<Server>) {
+        let configs = server.link_configs.read().await.clone();
+        for config in configs {
+            let name = config.name.clone().unwrap_or_default();
+            if config.autoconnect.unwrap_or(false) && !server.is_server_known(&name).await {
+                tokio::spawn(link::connect(server.clone(), config));
+            }
+        }
+    }
+
+    /* NOTE(diath): Registers a link once the handshake is done, refused if the server is already known through another link. */
+    pub async fn add_link(&self, link: Arc<Link>) -> bool {
+        let key = link.get_key();
+        {
+            let mut servers = self.remote_servers.lock().await;
+            if key == self.name.to_lowercase() || servers.contains_key(&key) {
+                return false;
+            }
+
+            servers.insert(
+                key.to_string(),
+                RemoteServer {
+                    name: link.name.to_string(),
+                    description: link.description.to_string(),
+                    hops: 1,
+                    uplink: self.name.to_string(),
+                    link: key.to_string(),
+                },
+            );
+            self.links
+                .lock()
+                .await
+                .insert(key.to_string(), link.clone());
+        }
+
+        self.propagate_except(
+            Some(&key),
+            format!(
+                ":{} SERVER {} 2 :{}",
+                self.name, link.name, link.description
+            ),
+        )
+        .await;
+
+        true
+    }
+
+    pub async fn add_remote_server(
+        &self,
+        name: &str,
+        description: &str,
+        hops: u32,
+        uplink: &str,
+        link: &str,
+    ) -> bool {
+        let key = name.to_lowercase();
+        let mut servers = self.remote_servers.lock().await;
+        if key == self.name.to_lowercase() || servers.contains_key(&key) {
+            return false;
+        }
+
+        servers.insert(
+            key,
+            RemoteServer {
+                name: name.to_string(),
+                description: description.to_string(),
+                hops,
+                uplink: uplink.to_string(),
+                link: link.to_string(),
+            },
+        );
+
+        true
+    }
+
+    pub async fn remove_link(&self, link: &Link, reason: &str) {
+        let key = link.get_key();
+        {
+            let mut links = self.links.lock().await;
+            match links.get(&key) {
+                Some(current) if std::ptr::eq(current.as_ref(), link) => {
+                    links.remove(&key);
+                }
+                _ => return,
+            }
+        }
+
+        log::info!("Link with {} closed ({}).", link.name, reason);
+        self.broadcast_oper_notice(format!("Link with {} closed ({})", link.name, reason))
+            .await;
+
+        self.split(&key).await;
+        self.propagate_except(
+            None,
+            format!(":{} SQUIT {} :{}", self.name, link.name, reason),
+        )
+        .await;
+    }
+
+    pub async fn remove_remote_server(&self, name: &str) {
+        self.split(&name.to_lowercase()).await;
+    }
+
+    /* NOTE(diath): Forgets a server along with everything behind it, the users on those servers quit with the usual netsplit reason. */
+    async fn split(&self, key: &str) {
+        let mut removed = HashMap::new();
+        {
+            let mut servers = self.remote_servers.lock().await;
+            let mut keys = HashSet::new();
+            keys.insert(key.to_string());
+            loop {
+                let behind = servers
+                    .iter()
+                    .filter(|(_, server)| keys.contains(&server.uplink.to_lowercase()))
+                    .map(|(key, _)| key.to_string())
+                    .collect::<Vec<String>>();
+
+                let count = keys.len();
+                keys.extend(behind);
+                if keys.len() == count {
+                    break;
+                }
+            }
+
+            for key in keys {
+                if let Some(server) = servers.remove(&key) {
+                    removed.insert(key, server);
+                }
+            }
+        }
+
+        for client in self.get_clients().await {
+            let server = match &client.remote {
+                Some(remote) => remote.server.to_lowercase(),
+                None => continue,
+            };
+
+            if let Some(server) = removed.get(&server) {
+                let reason = format!("{} {}", server.uplink, server.name);
+                self.remove_remote_client(&client, &reason).await;
+            }
+        }
+    }
+
+    pub async fn close_link(&self, name: &str, reason: &str) -> bool {
+        let link = self.links.lock().await.get(&name.to_lowercase()).cloned();
+        match link {
+            Some(link) => {
+                link.close(reason).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /* NOTE(diath): The links are torn down right away rather than by their tasks, so that no remote users are left behind. */
+    async fn close_links(&self, reason: &str) {
+        let links = self
+            .links
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<Arc<Link>>>();
+        for link in links {
+            link.close(reason).await;
+            self.remove_link(&link, reason).await;
+        }
+    }
+
+    /* NOTE(diath): Passes a change on to every link except the one the client was introduced through. */
+    pub async fn propagate(&self, client: &Client, line: String) {
+        if client.is_service() {
+            return;
+        }
+
+        let except = client.remote.as_ref().map(|remote| remote.link.to_string());
+        self.propagate_except(except.as_deref(), line).await;
+    }
+
+    pub async fn propagate_except(&self, except: Option<&str>, line: String) {
+        let links = self
+            .links
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<Arc<Link>>>();
+        for link in links {
+            if except != Some(link.get_key().as_str()) {
+                link.send(&line).await;
+            }
+        }
+    }
+
+    /* NOTE(diath): Sends a message towards a remote user, unless it came from that direction in the first place. */
+    async fn route(&self, sender: &Client, target: &Client, line: String) {
+        if let Some(remote) = &target.remote {
+            if sender
+                .remote
+                .as_ref()
+                .is_some_and(|origin| origin.link == remote.link)
+            {
+                return;
+            }
+
+            let link = self.links.lock().await.get(&remote.link).cloned();
+            if let Some(link) = link {
+                link.send(&line).await;
+            }
+        }
+    }
+
+    pub async fn add_remote_client(&self, client: Arc<Client>) {
+        let nick = client.nick.lock().await.to_string();
+        self.clients.lock().await.insert(nick, client);
+    }
+
+    pub async fn remove_remote_client(&self, client: &Client, reason: &str) {
+        self.notify_quit(client, reason).await;
+        self.remove_client(client).await;
+    }
+
+    /* NOTE(diath): Drops a remote user without announcing anything, the callers take care of that. */
+    pub async fn remove_client(&self, client: &Client) {
+        self.remove_from_channels(client).await;
+        client.channels.lock().await.clear();
+
+        let nick = client.nick.lock().await.to_string();
+        let mut clients = self.clients.lock().await;
+        if clients
+            .get(&nick)
+            .is_some_and(|other| std::ptr::eq(other.as_ref(), client))
+        {
+            clients.remove(&nick);
+        }
+    }
+
+    /* NOTE(diath): Introduces everything we know about to a freshly established link, see link.rs for the format. */
+    pub async fn send_burst(&self, link: &Link) {
+        let key = link.get_key();
+
+        let mut servers = self
+            .remote_servers
+            .lock()
+            .await
+            .values()
+            .filter(|server| server.link != key)
+            .cloned()
+            .collect::<Vec<RemoteServer>>();
+        servers.sort_by_key(|server| server.hops);
+        for server in servers {
+            link.send(&format!(
+                ":{} SERVER {} {} :{}",
+                server.uplink,
+                server.name,
+                server.hops + 1,
+                server.description
+            ))
+            .await;
+        }
+
+        let mut away = vec![];
+        for client in self.get_clients().await {
+            if !*client.registered.read().await
+                || client.is_service()
+                || client
+                    .remote
+                    .as_ref()
+                    .is_some_and(|remote| remote.link == key)
+            {
+                continue;
+            }
+
+            link.send(&client.get_uid_line().await).await;
+
+            let message = client.away_message.lock().await.to_string();
+            if !message.is_empty() {
+                away.push(format!(":{} AWAY :{}", client.nick.lock().await, message));
+            }
+        }
+
+        for channel in self.channels.lock().await.values() {
+            let mut members = vec![];
+            for (nick, modes) in channel.participants.read().await.iter() {
+                if let Some(client) = self.clients.lock().await.get(nick) {
+                    if !client.is_service()
+                        && client
+                            .remote
+                            .as_ref()
+                            .is_none_or(|remote| remote.link != key)
+                    {
+                        members.push(format!("{}{}", modes.get_prefixes(), nick));
+                    }
+                }
+            }
+
+            if members.is_empty() {
+                continue;
+            }
+
+            /* NOTE(diath): The list modes go along with the rest, so that they are subject to the same timestamp rule. */
+            let description = channel.get_modes_description(true).await;
+            let mut params = description.split_whitespace();
+            let mut letters = params.next().unwrap_or("+").to_string();
+            let mut params = params
+                .map(|param| param.to_string())
+                .collect::<Vec<String>>();
+            for (list, letter) in [
+                (&channel.bans, 'b'),
+                (&channel.ban_exceptions, 'e'),
+                (&channel.invite_exceptions, 'I'),
+            ]
+            .iter()
+            {
+                for mask in list.lock().await.iter() {
+                    letters.push(*letter);
+                    params.push(mask.to_string());
+                }
+            }
+
+            let mut modes = iter::once(letters)
+                .chain(params)
+                .collect::<Vec<String>>()
+                .join(" ");
+            let ts = *channel.created_at.read().await;
+            for chunk in members.chunks(SJOIN_MEMBERS) {
+                link.send(&format!(
+                    ":{} SJOIN {} {} {} :{}",
+                    self.name,
+                    ts,
+                    channel.name,
+                    modes,
+                    chunk.join(" ")
+                ))
+                .await;
+                modes = "+".to_string();
+            }
+
+            let topic = channel.topic.lock().await;
+            if !topic.text.is_empty() {
+                link.send(&format!(
+                    ":{} TB {} {} {} :{}",
+                    self.name, channel.name, topic.set_at, topic.set_by, topic.text
+                ))
+                .await;
+            }
+        }
+
+        for line in away {
+            link.send(&line).await;
+        }
+
+        link.send(&format!(":{} EOB", self.name)).await;
+    }
+
+    /* NOTE(diath): Merges a channel from a linked server, the copy with the older timestamp keeps its modes and statuses. */
+    pub async fn sjoin(
+        &self,
+        client: &Client,
+        ts: i64,
+        channel_name: &str,
+        modes: Vec<String>,
+        members: Vec<(Arc<Client>, ChannelUserModes)>,
+    ) {
+        let name = channel_name.to_lowercase();
+        if !self.is_channel_mapped(&name).await {
+            self.create_channel(&name).await;
+            if let Some(channel) = self.channels.lock().await.get(&name) {
+                (*channel.created_at.write().await) = ts;
+                channel.clear_modes().await;
+            }
+        }
+
+        let channels = self.channels.lock().await;
+        let channel = match channels.get(&name) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let prefix = client.get_server_name();
+        let mut messages = vec![];
+
+        let current_ts = *channel.created_at.read().await;
+        let accept = ts <= current_ts;
+        if ts < current_ts {
+            (*channel.created_at.write().await) = ts;
+
+            let changes = channel.clear_modes().await;
+            if !changes.is_empty() {
+                messages.push(format!(":{} MODE {} {}", prefix, name, changes));
+            }
+        }
+
+        let mut letters = String::new();
+        let mut nicks = vec![];
+        for (member, status) in members {
+            let nick = member.nick.lock().await.to_string();
+            if channel.has_participant(&nick).await {
+                continue;
+            }
+
+            if accept {
+                for (flag, letter) in [
+                    (status.owner, 'q'),
+                    (status.admin, 'a'),
+                    (status.operator, 'o'),
+                    (status.half_operator, 'h'),
+                    (status.voiced, 'v'),
+                ]
+                .iter()
+                {
+                    if *flag {
+                        letters.push(*letter);
+                        nicks.push(nick.to_string());
+                    }
+                }
+            }
+
+            channel.participants.write().await.insert(
+                nick.to_string(),
+                if accept {
+                    status
+                } else {
+                    ChannelUserModes {
+                        ..Default::default()
+                    }
+                },
+            );
+            member.channels.lock().await.insert(name.to_string());
+            messages.push(format!(":{} JOIN {}", member.get_prefix().await, name));
+        }
+
+        if !letters.is_empty() {
+            messages.push(format!(
+                ":{} MODE {} +{} {}",
+                prefix,
+                name,
+                letters,
+                nicks.join(" ")
+            ));
+        }
+
+        if accept && modes.first().is_some_and(|modes| modes.len() > 1) {
+            let changes = channel.toggle_modes(client, modes).await;
+            if !changes.is_empty() {
+                messages.push(format!(":{} MODE {} {}", prefix, name, changes));
+            }
+        }
+
+        let messages = messages
+            .into_iter()
+            .map(|message| (MessageTags::new(&[]), message))
+            .collect::<Vec<(MessageTags, String)>>();
+        for target in channel.participants.read().await.keys() {
+            if let Some(target) = self.clients.lock().await.get(target) {
+                for (tags, message) in messages.iter() {
+                    target.send_tagged(tags, message.clone()).await;
+                }
+            }
+        }
+    }
+
+    /* NOTE(diath): Topics from a burst only replace an empty or a newer local topic. */
+    pub async fn burst_topic(
+        &self,
+        source: &str,
+        channel_name: &str,
+        set_at: u64,
+        set_by: &str,
+        text: &str,
+    ) -> bool {
+        let channels = self.channels.lock().await;
+        let channel = match channels.get(&channel_name.to_lowercase()) {
+            Some(channel) => channel,
+            None => return false,
+        };
+
+        {
+            let mut topic = channel.topic.lock().await;
+            if topic.text == text || (!topic.text.is_empty() && topic.set_at <= set_at) {
+                return false;
+            }
+
+            topic.text = text.to_string();
+            topic.set_by = set_by.to_string();
+            topic.set_at = set_at;
+        }
+
+        let tags = MessageTags::new(&[]);
+        let message = format!(":{} TOPIC {} :{}", source, channel.name, text);
+        for target in channel.participants.read().await.keys() {
+            if let Some(target) = self.clients.lock().await.get(target) {
+                target.send_tagged(&tags, message.clone()).await;
+            }
+        }
+
+        true
+    }
+
     pub async fn uptime(&self) -> i64 {
         return (DateTime::<Utc>::from(SystemTime::now()) - self.created).num_seconds();
     }
@@ -1321,7 +3299,7 @@ impl Server {
     pub async fn append_nick_history(&self, nick: String, client: &Client) {
         let entry = NickHistory {
             nick: nick.to_string(),
-            user: client.user.lock().await.to_string(),
+            user: client.get_user().await,
             host: client.get_host().await,
             real_name: client.real_name.lock().await.to_string(),
             timestamp: Utc::now().timestamp(),
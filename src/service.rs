@@ -2,7 +2,290 @@ use async_trait::async_trait;
 
 use crate::client::Client;
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum ServicePrivilege {
+    Anyone,
+    Identified,
+    Operator,
+}
+
+/* NOTE(diath): An entry of the command table of a service, the arity and privilege are checked before the service sees the command. */
+pub struct ServiceCommand {
+    pub name: &'static str,
+    /* NOTE(diath): One line per form of the command, such as the subcommands of SET. */
+    pub syntax: &'static [&'static str],
+    pub min_params: usize,
+    pub privilege: ServicePrivilege,
+    pub help: &'static str,
+}
+
+/* NOTE(diath): The text of a service message split on runs of whitespace, the offsets let commands take the rest of the line verbatim. */
+pub struct ServiceArgs<'a> {
+    raw: &'a str,
+    params: Vec<&'a str>,
+    offsets: Vec<usize>,
+}
+
+impl<'a> ServiceArgs<'a> {
+    pub fn parse(raw: &'a str) -> ServiceArgs<'a> {
+        let mut params = vec![];
+        let mut offsets = vec![];
+        let mut start = None;
+        for (index, ch) in raw.char_indices() {
+            if ch.is_whitespace() {
+                if let Some(offset) = start.take() {
+                    params.push(&raw[offset..index]);
+                    offsets.push(offset);
+                }
+            } else if start.is_none() {
+                start = Some(index);
+            }
+        }
+
+        if let Some(offset) = start {
+            params.push(&raw[offset..]);
+            offsets.push(offset);
+        }
+
+        ServiceArgs {
+            raw,
+            params,
+            offsets,
+        }
+    }
+
+    pub fn params(&self) -> &[&'a str] {
+        &self.params
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&'a str> {
+        self.params.get(index).copied()
+    }
+
+    /* NOTE(diath): Everything from the given param to the end of the line, including the original spacing, rest(0) is the whole line. */
+    pub fn rest(&self, index: usize) -> &'a str {
+        match self.offsets.get(index) {
+            Some(offset) => self.raw[*offset..].trim_end(),
+            None => "",
+        }
+    }
+
+    /* NOTE(diath): Drops the leading params, used to hand the arguments of a command over to a subcommand. */
+    pub fn shift(&self, count: usize) -> ServiceArgs<'a> {
+        let count = count.min(self.params.len());
+        ServiceArgs {
+            raw: self.rest(count),
+            params: self.params[count..].to_vec(),
+            offsets: self.offsets[count..]
+                .iter()
+                .map(|offset| offset - self.offsets.get(count).copied().unwrap_or(0))
+                .collect(),
+        }
+    }
+}
+
 #[async_trait]
-pub trait Service {
-    async fn on_message(&self, client: &Client, params: Vec<&str>);
+pub trait Service: Send + Sync {
+    fn get_name(&self) -> &'static str;
+    fn get_commands(&self) -> &'static [ServiceCommand];
+
+    /* NOTE(diath): Called with a command from the table that passed the checks, the first param is the command itself. */
+    async fn on_command(&self, client: &Client, command: &str, args: &ServiceArgs<'_>);
+
+    async fn on_connect(&self, _client: &Client) {}
+    async fn on_register(&self, _client: &Client) {}
+    async fn on_nick_change(&self, _client: &Client, _old_nick: &str) {}
+    async fn on_join(&self, _client: &Client, _channel: &str) {}
+    async fn on_part(&self, _client: &Client, _channel: &str) {}
+    async fn on_quit(&self, _client: &Client, _reason: &str) {}
+    async fn on_identify(&self, _client: &Client, _account: &str) {}
+
+    async fn on_message(&self, client: &Client, text: &str) {
+        let args = ServiceArgs::parse(text);
+        if args.is_empty() {
+            self.reply(
+                client,
+                &format!("Type /msg {} HELP for a list of commands", self.get_name()),
+            )
+            .await;
+            return;
+        }
+
+        let name = args.params()[0];
+
+        if name.eq_ignore_ascii_case("help") {
+            self.send_help(client, args.get(1)).await;
+            return;
+        }
+
+        let command = match self.get_command(name) {
+            Some(command) => command,
+            None => {
+                self.reply(
+                    client,
+                    &format!("Unknown command {}, try HELP", name.to_ascii_uppercase()),
+                )
+                .await;
+                return;
+            }
+        };
+
+        match command.privilege {
+            ServicePrivilege::Anyone => {}
+            ServicePrivilege::Identified => {
+                if client.account.lock().await.is_none() {
+                    self.reply(client, "You are not identified").await;
+                    return;
+                }
+            }
+            ServicePrivilege::Operator => {
+                if !*client.operator.lock().await {
+                    self.reply(client, "You are not an IRC operator").await;
+                    return;
+                }
+            }
+        }
+
+        if args.len() - 1 < command.min_params {
+            self.reply_syntax(client, command.name).await;
+            return;
+        }
+
+        self.on_command(client, command.name, &args).await;
+    }
+
+    fn get_command(&self, name: &str) -> Option<&'static ServiceCommand> {
+        self.get_commands()
+            .iter()
+            .find(|command| command.name.eq_ignore_ascii_case(name))
+    }
+
+    async fn send_help(&self, client: &Client, name: Option<&str>) {
+        if let Some(name) = name {
+            match self.get_command(name) {
+                Some(command) => {
+                    for syntax in command.syntax.iter() {
+                        self.reply(
+                            client,
+                            format!("Syntax: {} {}", command.name, syntax).trim_end(),
+                        )
+                        .await;
+                    }
+                    self.reply(client, command.help).await;
+                }
+                None => {
+                    self.reply(
+                        client,
+                        &format!("No help available for {}", name.to_ascii_uppercase()),
+                    )
+                    .await;
+                }
+            }
+            return;
+        }
+
+        /* NOTE(diath): Operator commands are only listed for operators, everyone else could not use them anyway. */
+        let operator = *client.operator.lock().await;
+        self.reply(client, &format!("{} commands:", self.get_name()))
+            .await;
+        for command in self.get_commands().iter() {
+            if command.privilege == ServicePrivilege::Operator && !operator {
+                continue;
+            }
+
+            for syntax in command.syntax.iter() {
+                self.reply(client, format!("{} {}", command.name, syntax).trim_end())
+                    .await;
+            }
+        }
+        self.reply(client, "HELP [command]").await;
+    }
+
+    /* NOTE(diath): The same error for every missing parameter, including the ones of subcommands. */
+    async fn reply_syntax(&self, client: &Client, name: &str) {
+        self.reply(client, "Not enough parameters").await;
+        if let Some(command) = self.get_command(name) {
+            for syntax in command.syntax.iter() {
+                self.reply(
+                    client,
+                    format!("Syntax: {} {}", command.name, syntax).trim_end(),
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn reply(&self, client: &Client, message: &str) {
+        let nick = client.nick.lock().await.to_string();
+        client
+            .send_raw(format!(
+                ":{} NOTICE {} :{}",
+                client.server.get_service_prefix(self.get_name()),
+                nick,
+                message
+            ))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_runs() {
+        let args = ServiceArgs::parse("SET  PASSWORD \t secret");
+        assert_eq!(args.params(), &["SET", "PASSWORD", "secret"]);
+        assert_eq!(args.len(), 3);
+        assert_eq!(args.get(2), Some("secret"));
+        assert_eq!(args.get(3), None);
+    }
+
+    #[test]
+    fn leading_and_trailing_spaces() {
+        let args = ServiceArgs::parse("   INFO nick   ");
+        assert_eq!(args.params(), &["INFO", "nick"]);
+        assert_eq!(args.rest(0), "INFO nick");
+
+        let args = ServiceArgs::parse("    ");
+        assert!(args.is_empty());
+        assert_eq!(args.rest(0), "");
+    }
+
+    #[test]
+    fn rest_keeps_spacing() {
+        let args = ServiceArgs::parse("IDENTIFY nick  two   spaced words ");
+        assert_eq!(args.rest(0), "IDENTIFY nick  two   spaced words");
+        assert_eq!(args.rest(2), "two   spaced words");
+        assert_eq!(args.rest(4), "words");
+        assert_eq!(args.rest(5), "");
+    }
+
+    #[test]
+    fn shift() {
+        let args = ServiceArgs::parse("SET  GREET  hello   there");
+
+        let shifted = args.shift(1);
+        assert_eq!(shifted.params(), &["GREET", "hello", "there"]);
+        assert_eq!(shifted.rest(0), "GREET  hello   there");
+        assert_eq!(shifted.rest(1), "hello   there");
+
+        let shifted = shifted.shift(2);
+        assert_eq!(shifted.params(), &["there"]);
+        assert_eq!(shifted.rest(0), "there");
+
+        /* NOTE(diath): Shifting past the end leaves no params instead of panicking. */
+        let shifted = args.shift(10);
+        assert!(shifted.is_empty());
+        assert_eq!(shifted.rest(0), "");
+        assert_eq!(shifted.shift(1).len(), 0);
+    }
 }
@@ -1,215 +1,443 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
 
 use async_trait::async_trait;
 
+use chrono::Utc;
+
+use serde::{Deserialize, Serialize};
+
 use tokio::sync::Mutex;
 
 use crate::client::{Client, UserHost};
-use crate::cloak::get_cloaked_host;
-use crate::service::Service;
+use crate::mask::check_mask;
+use crate::service::{Service, ServiceArgs, ServiceCommand, ServicePrivilege};
+use crate::services::{format_timestamp, parse_duration};
+use crate::storage;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct VHost {
+    pub host: String,
+    pub set_by: String,
+    pub set_at: i64,
+    pub expires: i64,
+}
+
+impl VHost {
+    fn is_expired(&self) -> bool {
+        self.expires != 0 && self.expires <= Utc::now().timestamp()
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct HostServData {
+    hosts: HashMap<String, VHost>,
+    pending: HashMap<String, String>,
+}
 
 pub struct HostServ {
     pub require_activation: bool,
-    pub hosts: Mutex<HashMap<String, String>>,
+    pub hosts: Mutex<HashMap<String, VHost>>,
     pub pending: Mutex<HashMap<String, String>>,
+    data_path: String,
 }
 
 impl HostServ {
-    pub fn new() -> HostServ {
+    pub fn new(data_path: &str) -> HostServ {
+        let data: HostServData = storage::load(data_path, "hostserv");
         HostServ {
             require_activation: false,
-            hosts: Mutex::new(HashMap::new()),
-            pending: Mutex::new(HashMap::new()),
+            hosts: Mutex::new(data.hosts),
+            pending: Mutex::new(data.pending),
+            data_path: data_path.to_string(),
         }
     }
 
-    async fn reply(&self, client: &Client, message: &str) {
-        let nick = client.nick.lock().await;
-        client
-            .send_raw(format!(":HostServ@services NOTICE {} :{}", nick, message))
-            .await;
+    pub async fn save(&self) {
+        let data = HostServData {
+            hosts: self.hosts.lock().await.clone(),
+            pending: self.pending.lock().await.clone(),
+        };
+        storage::save(&self.data_path, "hostserv", &data);
+    }
+
+    /* NOTE(diath): Expired vhosts are removed lazily when they are looked up. */
+    pub async fn get_vhost(&self, account: &str) -> Option<VHost> {
+        let expired = {
+            let mut hosts = self.hosts.lock().await;
+            match hosts.get(account) {
+                Some(vhost) if vhost.is_expired() => {
+                    hosts.remove(account);
+                    true
+                }
+                Some(vhost) => return Some(vhost.clone()),
+                None => return None,
+            }
+        };
+
+        if expired {
+            self.save().await;
+        }
+
+        None
     }
 }
 
-fn is_vhost_valid(vhost: String) -> bool {
-    for chunk in vhost.split(".") {
-        if chunk.len() == 0 || !chunk.chars().all(|ch| ch.is_ascii_alphabetic()) {
+/* NOTE(diath): A vhost is a hostname optionally prefixed with an ident, e.g. user@dev-team.example2.org. */
+fn is_vhost_valid(vhost: &str) -> bool {
+    let host = match vhost.find('@') {
+        Some(index) => {
+            if !is_ident_valid(&vhost[..index]) {
+                return false;
+            }
+
+            &vhost[index + 1..]
+        }
+        None => vhost,
+    };
+
+    if host.is_empty() || host.len() > 253 {
+        return false;
+    }
+
+    for label in host.split('.') {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+        {
             return false;
         }
     }
 
-    true
+    /* NOTE(diath): Do not allow vhosts that could be mistaken for an IP address. */
+    match host.rsplit('.').next() {
+        Some(label) => !label.chars().all(|ch| ch.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn is_ident_valid(ident: &str) -> bool {
+    !ident.is_empty()
+        && ident.len() <= 10
+        && ident
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
 }
 
+fn split_vhost(vhost: &str) -> (Option<String>, String) {
+    match vhost.find('@') {
+        Some(index) => (
+            Some(vhost[..index].to_string()),
+            vhost[index + 1..].to_string(),
+        ),
+        None => (None, vhost.to_string()),
+    }
+}
+
+static COMMANDS: &[ServiceCommand] = &[
+    ServiceCommand {
+        name: "ON",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Identified,
+        help: "Activates your vhost",
+    },
+    ServiceCommand {
+        name: "OFF",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Identified,
+        help: "Deactivates your vhost",
+    },
+    ServiceCommand {
+        name: "REQUEST",
+        syntax: &["<[ident@]vhost>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Identified,
+        help: "Requests a vhost for your account",
+    },
+    ServiceCommand {
+        name: "ACTIVATE",
+        syntax: &["<account> [+expiry]"],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Activates a requested vhost",
+    },
+    ServiceCommand {
+        name: "REJECT",
+        syntax: &["<account>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Rejects a requested vhost",
+    },
+    ServiceCommand {
+        name: "WAITING",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Operator,
+        help: "Lists pending vhost requests",
+    },
+    ServiceCommand {
+        name: "DEL",
+        syntax: &["<account>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Removes the vhost of an account",
+    },
+    ServiceCommand {
+        name: "LIST",
+        syntax: &["[mask]"],
+        min_params: 0,
+        privilege: ServicePrivilege::Operator,
+        help: "Lists active vhosts",
+    },
+];
+
 #[async_trait]
 impl Service for HostServ {
-    async fn on_message(&self, client: &Client, params: Vec<&str>) {
-        if params.len() < 1 {
-            return;
-        }
+    fn get_name(&self) -> &'static str {
+        "HostServ"
+    }
 
-        match params[0].to_ascii_lowercase().as_str() {
-            "on" => {
-                if *client.identified.lock().await {
-                    let nick = client.nick.lock().await.to_string();
-                    if let Some(vhost) = self.hosts.lock().await.get(&nick) {
-                        (*client.host.lock().await) = UserHost::VHost(vhost.to_string());
-
-                        self.reply(client, &format!("Your vhost of {} is now activated", vhost))
-                            .await;
-                    } else if let Some(_) = self.pending.lock().await.get(&nick) {
-                        self.reply(client, "Your vhost is pending activation").await;
-                    } else {
-                        self.reply(client, "There is no vhost for your nick").await;
-                    }
-                } else {
-                    self.reply(client, "You are not identified for that nick")
+    fn get_commands(&self) -> &'static [ServiceCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, command: &str, args: &ServiceArgs<'_>) {
+        let params = args.params();
+        let account = client.account.lock().await.clone().unwrap_or_default();
+
+        match command {
+            "ON" => {
+                if let Some(vhost) = self.get_vhost(&account).await {
+                    let (ident, host) = split_vhost(&vhost.host);
+                    client
+                        .server
+                        .change_host(client, ident, UserHost::VHost(host))
                         .await;
-                }
-            }
-            "off" => {
-                if *client.identified.lock().await {
-                    let host = match client.address {
-                        SocketAddr::V4(addr) => UserHost::IPv4(addr.ip().to_string()),
-                        SocketAddr::V6(addr) => UserHost::IPv6(addr.ip().to_string()),
-                    };
-                    (*client.host.lock().await) = UserHost::VHost(get_cloaked_host(host));
+
+                    self.reply(
+                        client,
+                        &format!("Your vhost of {} is now activated", vhost.host),
+                    )
+                    .await;
+                } else if self.pending.lock().await.contains_key(&account) {
+                    self.reply(client, "Your vhost is pending activation").await;
                 } else {
-                    self.reply(client, "You are not identified for that nick")
+                    self.reply(client, "There is no vhost for your account")
                         .await;
                 }
             }
-            "request" => {
-                if params.len() < 2 {
-                    self.reply(client, "Not enough params").await;
-                } else if *client.identified.lock().await {
-                    if !is_vhost_valid(params[1].to_string()) {
-                        self.reply(client, "Invalid vhost format specified").await;
-                    } else {
-                        let nick = client.nick.lock().await.to_string();
-                        if self.require_activation {
-                            let result = self
-                                .pending
-                                .lock()
-                                .await
-                                .insert(nick, params[1].to_string());
-
-                            self.reply(
-                                client,
-                                "Your vhost has been requested and awaiting activation",
-                            )
-                            .await;
-
-                            if result.is_some() {
-                                self.reply(client, "Your old vhost has been removed").await;
-                            }
-                        } else {
-                            let result =
-                                self.hosts.lock().await.insert(nick, params[1].to_string());
-
-                            self.reply(client, "Your vhost has been activated and is ready to use")
-                                .await;
+            "OFF" => {
+                let host = client
+                    .server
+                    .cloak
+                    .get_cloaked_host(client.get_real_host().await);
+                client
+                    .server
+                    .change_host(client, None, UserHost::VHost(host))
+                    .await;
+                self.reply(client, "Your vhost has been deactivated").await;
+            }
+            "REQUEST" => {
+                if !is_vhost_valid(params[1]) {
+                    self.reply(client, "Invalid vhost format specified").await;
+                } else if self.require_activation {
+                    let result = self
+                        .pending
+                        .lock()
+                        .await
+                        .insert(account, params[1].to_string());
+                    self.save().await;
 
-                            if result.is_some() {
-                                self.reply(client, "Your old vhost has been removed").await;
-                            }
-                        }
+                    self.reply(
+                        client,
+                        "Your vhost has been requested and awaiting activation",
+                    )
+                    .await;
+
+                    if result.is_some() {
+                        self.reply(client, "Your old vhost has been removed").await;
                     }
                 } else {
-                    self.reply(client, "You are not identified for that nick")
+                    let vhost = VHost {
+                        host: params[1].to_string(),
+                        set_by: client.nick.lock().await.to_string(),
+                        set_at: Utc::now().timestamp(),
+                        expires: 0,
+                    };
+                    let result = self.hosts.lock().await.insert(account, vhost);
+                    self.save().await;
+
+                    self.reply(client, "Your vhost has been activated and is ready to use")
                         .await;
+
+                    if result.is_some() {
+                        self.reply(client, "Your old vhost has been removed").await;
+                    }
                 }
             }
-            "activate" => {
-                if params.len() < 2 {
-                    self.reply(client, "Not enough params").await;
-                } else if *client.operator.lock().await {
-                    // NOTE(diath): This is a little goofy to prevent a deadlock.
-                    let mut vhost = None;
-                    if let Some(value) = self.pending.lock().await.get(params[1]) {
-                        vhost = Some(value.to_string());
-                    }
+            "ACTIVATE" => {
+                let expires = match params.get(2) {
+                    Some(param) => match parse_duration(param) {
+                        Some(0) => 0,
+                        Some(duration) => Utc::now().timestamp() + duration,
+                        None => {
+                            self.reply(client, "Invalid expiry time specified").await;
+                            self.reply(client, "Syntax: ACTIVATE <account> [+expiry]")
+                                .await;
+                            return;
+                        }
+                    },
+                    None => 0,
+                };
+
+                // NOTE(diath): This is a little goofy to prevent a deadlock.
+                let mut vhost = None;
+                if let Some(value) = self.pending.lock().await.get(params[1]) {
+                    vhost = Some(value.to_string());
+                }
 
-                    if vhost.is_some() {
-                        self.pending.lock().await.remove(params[1]);
-                        self.hosts
-                            .lock()
-                            .await
-                            .insert(params[1].to_string(), vhost.unwrap());
-                        self.reply(client, "You have activated the requested vhost")
-                            .await;
-                    } else {
-                        self.reply(
-                            client,
-                            &format!("No pending vhost for nick {} found", params[1]),
-                        )
+                if let Some(host) = vhost {
+                    self.pending.lock().await.remove(params[1]);
+                    let vhost = VHost {
+                        host,
+                        set_by: client.nick.lock().await.to_string(),
+                        set_at: Utc::now().timestamp(),
+                        expires,
+                    };
+                    self.hosts.lock().await.insert(params[1].to_string(), vhost);
+                    self.save().await;
+                    self.reply(client, "You have activated the requested vhost")
                         .await;
-                    }
                 } else {
-                    self.reply(client, "You are not an IRC operator").await;
+                    self.reply(
+                        client,
+                        &format!("No pending vhost for account {} found", params[1]),
+                    )
+                    .await;
                 }
             }
-            "reject" => {
-                if params.len() < 2 {
-                    self.reply(client, "Not enough params").await;
-                } else if *client.operator.lock().await {
-                    if !self.pending.lock().await.contains_key(params[1]) {
-                        self.reply(
-                            client,
-                            &format!("No pending vhost for nick {} found", params[1]),
-                        )
-                        .await;
-                        return;
-                    }
-
-                    self.pending.lock().await.remove(params[1]);
+            "REJECT" => {
+                if !self.pending.lock().await.contains_key(params[1]) {
                     self.reply(
                         client,
-                        &format!(
-                            "You have rejected the requested vhost for nick {}",
-                            params[1]
-                        ),
+                        &format!("No pending vhost for account {} found", params[1]),
                     )
                     .await;
-                } else {
-                    self.reply(client, "You are not an IRC operator").await;
+                    return;
                 }
+
+                self.pending.lock().await.remove(params[1]);
+                self.save().await;
+                self.reply(
+                    client,
+                    &format!(
+                        "You have rejected the requested vhost for account {}",
+                        params[1]
+                    ),
+                )
+                .await;
             }
-            "waiting" => {
-                if *client.operator.lock().await {
-                    self.reply(client, "List of pending vhosts:").await;
-                    for (nick, vhost) in self.pending.lock().await.iter() {
-                        self.reply(client, &format!("{} - {}", nick, vhost)).await;
-                    }
-                } else {
-                    self.reply(client, "You are not an IRC operator").await;
+            "WAITING" => {
+                self.reply(client, "List of pending vhosts:").await;
+                for (account, vhost) in self.pending.lock().await.iter() {
+                    self.reply(client, &format!("{} - {}", account, vhost))
+                        .await;
                 }
             }
-            "del" => {
-                if params.len() < 2 {
-                    self.reply(client, "Not enough params").await;
-                } else if *client.operator.lock().await {
-                    if !self.hosts.lock().await.contains_key(params[1]) {
-                        self.reply(client, &format!("No vhost for nick {} found", params[1]))
-                            .await;
-                        return;
-                    }
+            "DEL" => {
+                if !self.hosts.lock().await.contains_key(params[1]) {
+                    self.reply(client, &format!("No vhost for account {} found", params[1]))
+                        .await;
+                    return;
+                }
 
-                    self.hosts.lock().await.remove(params[1]);
+                self.hosts.lock().await.remove(params[1]);
+                self.save().await;
+                self.reply(
+                    client,
+                    &format!("You have removed the vhost for account {}", params[1]),
+                )
+                .await;
+            }
+            "LIST" => {
+                let mask = params.get(1).unwrap_or(&"*").to_string();
+                let mut hosts = self
+                    .hosts
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(account, vhost)| !vhost.is_expired() && check_mask(&mask, account))
+                    .map(|(account, vhost)| (account.to_string(), vhost.clone()))
+                    .collect::<Vec<(String, VHost)>>();
+                hosts.sort_by(|a, b| a.0.cmp(&b.0));
+
+                self.reply(client, &format!("List of vhosts matching {}:", mask))
+                    .await;
+                for (account, vhost) in hosts.iter() {
                     self.reply(
                         client,
-                        &format!("You have removed the vhost for nick {}", params[1]),
+                        &format!(
+                            "{} - {} (set by {} at {}, expires {})",
+                            account,
+                            vhost.host,
+                            vhost.set_by,
+                            format_timestamp(vhost.set_at),
+                            format_timestamp(vhost.expires)
+                        ),
                     )
                     .await;
-                } else {
-                    self.reply(client, "You are not an IRC operator").await;
                 }
+                self.reply(client, &format!("{} vhost(s) found", hosts.len()))
+                    .await;
             }
-            "help" => {}
-            _ => {
-                self.reply(client, "Unknown command, try HELP").await;
-            }
+            _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_vhosts() {
+        assert!(is_vhost_valid("example.com"));
+        assert!(is_vhost_valid("users.my-network.org"));
+        assert!(is_vhost_valid("localhost"));
+        assert!(is_vhost_valid("ident@example.com"));
+        assert!(is_vhost_valid("1.2.3.example"));
+    }
+
+    #[test]
+    fn invalid_vhosts() {
+        assert!(!is_vhost_valid(""));
+        assert!(!is_vhost_valid("example..com"));
+        assert!(!is_vhost_valid(".example.com"));
+        assert!(!is_vhost_valid("example.com."));
+        assert!(!is_vhost_valid("-example.com"));
+        assert!(!is_vhost_valid("example-.com"));
+        assert!(!is_vhost_valid("exa mple.com"));
+        assert!(!is_vhost_valid("*.example.com"));
+        assert!(!is_vhost_valid(&format!("{}.com", "a".repeat(64))));
+        assert!(!is_vhost_valid(&format!("{}com", "a.".repeat(127))));
+    }
+
+    #[test]
+    fn ip_like_vhosts() {
+        assert!(!is_vhost_valid("127.0.0.1"));
+        assert!(!is_vhost_valid("example.123"));
+    }
+
+    #[test]
+    fn vhost_idents() {
+        assert!(!is_vhost_valid("@example.com"));
+        assert!(!is_vhost_valid("toolongident@example.com"));
+        assert!(!is_vhost_valid("bad!ident@example.com"));
+        assert!(!is_vhost_valid("ident@"));
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use chrono::{TimeZone, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::{Mutex, RwLock};
+
+use crate::client::Client;
+use crate::service::{Service, ServiceArgs, ServiceCommand, ServicePrivilege};
+use crate::storage;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Memo {
+    pub sender: String,
+    pub text: String,
+    pub sent_at: i64,
+    pub read: bool,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct MemoServData {
+    memos: HashMap<String, Vec<Memo>>,
+    limits: HashMap<String, usize>,
+}
+
+pub struct MemoServ {
+    pub limit: RwLock<usize>,
+    pub memos: Mutex<HashMap<String, Vec<Memo>>>,
+    pub limits: Mutex<HashMap<String, usize>>,
+    data_path: String,
+}
+
+impl MemoServ {
+    pub fn new(data_path: &str, limit: usize) -> MemoServ {
+        let data: MemoServData = storage::load(data_path, "memoserv");
+        MemoServ {
+            limit: RwLock::new(limit),
+            memos: Mutex::new(data.memos),
+            limits: Mutex::new(data.limits),
+            data_path: data_path.to_string(),
+        }
+    }
+
+    pub async fn save(&self) {
+        let data = MemoServData {
+            memos: self.memos.lock().await.clone(),
+            limits: self.limits.lock().await.clone(),
+        };
+        storage::save(&self.data_path, "memoserv", &data);
+    }
+
+    async fn get_limit(&self, account: &str) -> usize {
+        match self.limits.lock().await.get(account) {
+            Some(limit) => *limit,
+            None => *self.limit.read().await,
+        }
+    }
+
+    async fn notify_unread(&self, client: &Client, account: &str) {
+        let unread = match self.memos.lock().await.get(account) {
+            Some(memos) => memos.iter().filter(|memo| !memo.read).count(),
+            None => 0,
+        };
+
+        if unread > 0 {
+            self.reply(
+                client,
+                &format!(
+                    "You have {} unread memo(s), type /msg MemoServ LIST to view them",
+                    unread
+                ),
+            )
+            .await;
+        }
+    }
+
+    pub async fn clear(&self, account: &str) {
+        let removed = self.memos.lock().await.remove(account).is_some();
+        let removed_limit = self.limits.lock().await.remove(account).is_some();
+        if removed || removed_limit {
+            self.save().await;
+        }
+    }
+
+    async fn send_memo(&self, client: &Client, target: &str, text: String) {
+        let nick = client.nick.lock().await.to_string();
+        let account = match client.server.nickserv.get_account(target).await {
+            Some(account) => account,
+            None => {
+                self.reply(client, &format!("Nick {} is not registered", target))
+                    .await;
+                return;
+            }
+        };
+
+        let limit = self.get_limit(&account).await;
+        let count = {
+            let mut memos = self.memos.lock().await;
+            let entries = memos.entry(account.clone()).or_insert_with(Vec::new);
+            if entries.len() >= limit {
+                None
+            } else {
+                entries.push(Memo {
+                    sender: nick.clone(),
+                    text,
+                    sent_at: Utc::now().timestamp(),
+                    read: false,
+                });
+                Some(entries.len())
+            }
+        };
+
+        match count {
+            Some(count) => {
+                self.save().await;
+                self.reply(client, &format!("Your memo to {} has been sent", target))
+                    .await;
+
+                for recipient in client.server.get_clients().await {
+                    let logged_in = recipient.account.lock().await.clone();
+                    if logged_in.as_ref() == Some(&account) {
+                        self.reply(
+                            &recipient,
+                            &format!(
+                                "You have a new memo from {}, type /msg MemoServ READ {} to read it",
+                                nick, count
+                            ),
+                        )
+                        .await;
+                    }
+                }
+            }
+            None => {
+                self.reply(client, &format!("The memo box of {} is full", target))
+                    .await;
+            }
+        }
+    }
+
+    async fn list_memos(&self, client: &Client, account: &str) {
+        let memos = match self.memos.lock().await.get(account) {
+            Some(memos) => memos.clone(),
+            None => vec![],
+        };
+
+        if memos.is_empty() {
+            self.reply(client, "You have no memos").await;
+            return;
+        }
+
+        self.reply(
+            client,
+            &format!(
+                "You have {} memo(s) (limit {}):",
+                memos.len(),
+                self.get_limit(account).await
+            ),
+        )
+        .await;
+        for (index, memo) in memos.iter().enumerate() {
+            self.reply(
+                client,
+                &format!(
+                    "{}{} - from {} at {}",
+                    if memo.read { " " } else { "*" },
+                    index + 1,
+                    memo.sender,
+                    Utc.timestamp(memo.sent_at, 0).format("%Y-%m-%d %H:%M:%S")
+                ),
+            )
+            .await;
+        }
+    }
+
+    async fn read_memos(&self, client: &Client, account: &str, which: &str) {
+        let mut result = vec![];
+        {
+            let mut memos = self.memos.lock().await;
+            if let Some(entries) = memos.get_mut(account) {
+                if which.eq_ignore_ascii_case("new") {
+                    for (index, memo) in entries.iter_mut().enumerate() {
+                        if !memo.read {
+                            memo.read = true;
+                            result.push((index + 1, memo.clone()));
+                        }
+                    }
+                } else if let Ok(index) = which.parse::<usize>() {
+                    if index > 0 && index <= entries.len() {
+                        entries[index - 1].read = true;
+                        result.push((index, entries[index - 1].clone()));
+                    }
+                }
+            }
+        }
+
+        if result.is_empty() {
+            self.reply(client, "No such memo").await;
+            return;
+        }
+
+        self.save().await;
+        for (index, memo) in result {
+            self.reply(
+                client,
+                &format!(
+                    "Memo {} from {} at {}:",
+                    index,
+                    memo.sender,
+                    Utc.timestamp(memo.sent_at, 0).format("%Y-%m-%d %H:%M:%S")
+                ),
+            )
+            .await;
+            self.reply(client, &memo.text).await;
+        }
+    }
+
+    async fn delete_memos(&self, client: &Client, account: &str, which: &str) {
+        let deleted = {
+            let mut memos = self.memos.lock().await;
+            match memos.get_mut(account) {
+                Some(entries) => {
+                    if which.eq_ignore_ascii_case("all") {
+                        let count = entries.len();
+                        entries.clear();
+                        count
+                    } else if let Ok(index) = which.parse::<usize>() {
+                        if index > 0 && index <= entries.len() {
+                            entries.remove(index - 1);
+                            1
+                        } else {
+                            0
+                        }
+                    } else {
+                        0
+                    }
+                }
+                None => 0,
+            }
+        };
+
+        if deleted == 0 {
+            self.reply(client, "No such memo").await;
+        } else {
+            self.save().await;
+            self.reply(client, &format!("Deleted {} memo(s)", deleted))
+                .await;
+        }
+    }
+}
+
+static COMMANDS: &[ServiceCommand] = &[
+    ServiceCommand {
+        name: "SEND",
+        syntax: &["<nick> <text>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Identified,
+        help: "Sends a memo to the account of a registered nick",
+    },
+    ServiceCommand {
+        name: "LIST",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Identified,
+        help: "Lists your memos, unread ones are marked with an asterisk",
+    },
+    ServiceCommand {
+        name: "READ",
+        syntax: &["<number|NEW>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Identified,
+        help: "Reads a memo or all of your unread memos",
+    },
+    ServiceCommand {
+        name: "DEL",
+        syntax: &["<number|ALL>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Identified,
+        help: "Deletes a memo or all of your memos",
+    },
+    ServiceCommand {
+        name: "SET",
+        syntax: &["LIMIT <count>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Identified,
+        help: "Changes the maximum number of memos you can receive",
+    },
+];
+
+#[async_trait]
+impl Service for MemoServ {
+    fn get_name(&self) -> &'static str {
+        "MemoServ"
+    }
+
+    fn get_commands(&self) -> &'static [ServiceCommand] {
+        COMMANDS
+    }
+
+    async fn on_identify(&self, client: &Client, account: &str) {
+        self.notify_unread(client, account).await;
+    }
+
+    async fn on_command(&self, client: &Client, command: &str, args: &ServiceArgs<'_>) {
+        let params = args.params();
+        let account = match client.account.lock().await.clone() {
+            Some(account) => account,
+            None => return,
+        };
+
+        match command {
+            "SEND" => {
+                self.send_memo(client, params[1], args.rest(2).to_string())
+                    .await;
+            }
+            "LIST" => {
+                self.list_memos(client, &account).await;
+            }
+            "READ" => {
+                self.read_memos(client, &account, params[1]).await;
+            }
+            "DEL" => {
+                self.delete_memos(client, &account, params[1]).await;
+            }
+            "SET" => {
+                if !params[1].eq_ignore_ascii_case("limit") {
+                    self.reply_syntax(client, command).await;
+                    return;
+                }
+
+                let max_limit = *self.limit.read().await;
+                match params[2].parse::<usize>() {
+                    Ok(limit) if limit <= max_limit => {
+                        self.limits.lock().await.insert(account, limit);
+                        self.save().await;
+                        self.reply(client, &format!("Your memo limit is now {}", limit))
+                            .await;
+                    }
+                    _ => {
+                        self.reply(
+                            client,
+                            &format!("The memo limit must be between 0 and {}", max_limit),
+                        )
+                        .await;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,2 +1,65 @@
+use chrono::{TimeZone, Utc};
+
 pub mod hostserv;
+pub mod memoserv;
 pub mod nickserv;
+pub mod operserv;
+
+/* NOTE(diath): Durations are specified as +<number><unit> where unit is one of s, m, h, d or w, +0 means permanent. */
+pub fn parse_duration(value: &str) -> Option<i64> {
+    if !value.starts_with('+') || value.len() < 2 {
+        return None;
+    }
+
+    let value = &value[1..];
+    let (number, multiplier) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 3600),
+        Some('d') => (&value[..value.len() - 1], 86400),
+        Some('w') => (&value[..value.len() - 1], 604_800),
+        _ => (value, 86400),
+    };
+
+    match number.parse::<i64>() {
+        Ok(number) if number >= 0 => Some(number * multiplier),
+        _ => None,
+    }
+}
+
+pub fn format_timestamp(timestamp: i64) -> String {
+    if timestamp == 0 {
+        return "never".to_string();
+    }
+
+    Utc.timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("+0"), Some(0));
+        assert_eq!(parse_duration("+30s"), Some(30));
+        assert_eq!(parse_duration("+5m"), Some(300));
+        assert_eq!(parse_duration("+2h"), Some(7200));
+        assert_eq!(parse_duration("+1d"), Some(86400));
+        assert_eq!(parse_duration("+2w"), Some(1_209_600));
+        assert_eq!(parse_duration("+3"), Some(3 * 86400));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("+"), None);
+        assert_eq!(parse_duration("5m"), None);
+        assert_eq!(parse_duration("+m"), None);
+        assert_eq!(parse_duration("+-5m"), None);
+        assert_eq!(parse_duration("+5x"), None);
+        assert_eq!(parse_duration("+5.5h"), None);
+    }
+}
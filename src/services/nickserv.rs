@@ -1,122 +1,871 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 
+use chrono::Utc;
+
+use crypto::pbkdf2::{pbkdf2_check, pbkdf2_simple};
+
+use serde::{Deserialize, Serialize};
+
+use serde_json::{json, Map, Value};
+
 use tokio::sync::Mutex;
+use tokio::time::{delay_until, Duration, Instant};
 
 use crate::client::Client;
-use crate::service::Service;
+use crate::service::{Service, ServiceArgs, ServiceCommand, ServicePrivilege};
+use crate::services::format_timestamp;
+use crate::storage;
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Account {
+    pub name: String,
+    pub password_hash: String,
+    pub nicks: Vec<String>,
+    pub registered_at: i64,
+    pub last_seen: i64,
+    pub last_host: String,
+    pub hide: bool,
+    pub enforce: bool,
+    pub kill_protect: Option<u64>,
+}
+
+impl Default for Account {
+    fn default() -> Account {
+        Account {
+            name: String::new(),
+            password_hash: String::new(),
+            nicks: vec![],
+            registered_at: 0,
+            last_seen: 0,
+            last_host: String::new(),
+            hide: false,
+            enforce: true,
+            kill_protect: None,
+        }
+    }
+}
+
+/* NOTE(diath): Version 0 is a plain map of nicks to password hashes, version 1 introduced accounts with grouped nicks. */
+const DATA_VERSION: u64 = 1;
+const HASH_ROUNDS: u32 = 10_000;
+
+#[derive(Default, Deserialize, Serialize)]
+struct NickServData {
+    version: u64,
+    accounts: HashMap<String, Account>,
+}
+
+fn hash_password(password: &str) -> String {
+    pbkdf2_simple(password, HASH_ROUNDS).expect("Failed to generate a password salt")
+}
+
+fn migrate(version: u64, value: Value) -> Result<Value, String> {
+    match version {
+        0 => {
+            let nicks = value.as_object().ok_or("expected an object")?;
+            let mut accounts = Map::new();
+            for (nick, hash) in nicks.iter() {
+                let hash = hash.as_str().ok_or("expected a password hash string")?;
+                accounts.insert(
+                    nick.clone(),
+                    json!({ "name": nick, "password_hash": hash, "nicks": [nick] }),
+                );
+            }
+            Ok(json!({ "version": DATA_VERSION, "accounts": accounts }))
+        }
+        DATA_VERSION => Ok(value),
+        _ => Err(format!("unsupported version {}", version)),
+    }
+}
 
 pub struct NickServ {
+    pub enforce: bool,
+    pub enforce_delay: u64,
+    pub hold_duration: u64,
+    pub accounts: Mutex<HashMap<String, Account>>,
+    /* NOTE(diath): Maps every grouped nick to the name of the account that owns it. */
     pub nicks: Mutex<HashMap<String, String>>,
+    pub held: Mutex<HashMap<String, i64>>,
+    data_path: String,
 }
 
 impl NickServ {
-    pub fn new() -> NickServ {
+    pub fn new(data_path: &str, enforce: bool, enforce_delay: u64, hold_duration: u64) -> NickServ {
+        let data: NickServData = storage::load_versioned(data_path, "nickserv", migrate);
+
+        let mut nicks = HashMap::new();
+        for account in data.accounts.values() {
+            for nick in account.nicks.iter() {
+                nicks.insert(nick.to_string(), account.name.to_string());
+            }
+        }
+
         NickServ {
-            nicks: Mutex::new(HashMap::new()),
+            enforce,
+            enforce_delay,
+            hold_duration,
+            accounts: Mutex::new(data.accounts),
+            nicks: Mutex::new(nicks),
+            held: Mutex::new(HashMap::new()),
+            data_path: data_path.to_string(),
+        }
+    }
+
+    pub async fn is_registered(&self, nick: &str) -> bool {
+        self.nicks.lock().await.contains_key(nick)
+    }
+
+    pub async fn get_account(&self, nick: &str) -> Option<String> {
+        self.nicks.lock().await.get(nick).cloned()
+    }
+
+    /* NOTE(diath): A client is identified for a nick only if the nick is grouped to the account it is logged in to. */
+    pub async fn is_identified_for(&self, client: &Client, nick: &str) -> bool {
+        let account = client.account.lock().await.clone();
+        match account {
+            Some(account) => self.get_account(nick).await == Some(account),
+            None => false,
+        }
+    }
+
+    async fn verify_password(&self, nick: &str, password: &str) -> Option<bool> {
+        let account = self.get_account(nick).await?;
+        self.accounts
+            .lock()
+            .await
+            .get(&account)
+            .map(|entry| pbkdf2_check(password, &entry.password_hash).unwrap_or(false))
+    }
+
+    async fn login(&self, client: &Client, account: &str) {
+        (*client.account.lock().await) = Some(account.to_string());
+        self.update_last_seen(client).await;
+        for service in client.server.get_services() {
+            service.on_identify(client, account).await;
         }
     }
 
-    async fn reply(&self, client: &Client, message: &str) {
-        let nick = client.nick.lock().await;
-        client
-            .send_raw(format!(":NickServ@services NOTICE {} :{}", nick, message))
+    /* NOTE(diath): Called when a client logs in to or disconnects from an account. */
+    pub async fn update_last_seen(&self, client: &Client) {
+        let account = match client.account.lock().await.clone() {
+            Some(account) => account,
+            None => return,
+        };
+
+        let host = format!("{}@{}", client.get_user().await, client.get_host().await);
+        if let Some(entry) = self.accounts.lock().await.get_mut(&account) {
+            entry.last_seen = Utc::now().timestamp();
+            entry.last_host = host;
+        }
+        self.save().await;
+    }
+
+    async fn send_info(&self, client: &Client, nick: &str) {
+        let entry = match self.get_account(nick).await {
+            Some(account) => self.accounts.lock().await.get(&account).cloned(),
+            None => None,
+        };
+
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                self.reply(client, &format!("Nick {} is not registered", nick))
+                    .await;
+                return;
+            }
+        };
+
+        let mut online = false;
+        for target in client.server.get_clients().await {
+            if target.account.lock().await.as_ref() == Some(&entry.name) {
+                online = true;
+                break;
+            }
+        }
+
+        let owner = client.account.lock().await.as_ref() == Some(&entry.name);
+        let privileged = owner || *client.operator.lock().await;
+
+        self.reply(
+            client,
+            &format!("Information on {} (account {}):", nick, entry.name),
+        )
+        .await;
+        self.reply(
+            client,
+            &format!("Registered: {}", format_timestamp(entry.registered_at)),
+        )
+        .await;
+
+        if !entry.hide || privileged {
+            if online {
+                self.reply(client, "Last seen: now").await;
+            } else {
+                self.reply(
+                    client,
+                    &format!("Last seen: {}", format_timestamp(entry.last_seen)),
+                )
+                .await;
+            }
+
+            if !entry.last_host.is_empty() {
+                self.reply(client, &format!("Last seen host: {}", entry.last_host))
+                    .await;
+            }
+        }
+
+        if let Some(vhost) = client.server.hostserv.get_vhost(&entry.name).await {
+            self.reply(client, &format!("VHost: {}", vhost.host)).await;
+        }
+
+        if privileged {
+            self.reply(client, &format!("Nicks: {}", entry.nicks.join(", ")))
+                .await;
+
+            let mut options = vec![];
+            if entry.enforce {
+                options.push("ENFORCE".to_string());
+            }
+            if entry.hide {
+                options.push("HIDE".to_string());
+            }
+            if let Some(delay) = entry.kill_protect {
+                options.push(format!("KILLPROTECT {}", delay));
+            }
+
+            if options.is_empty() {
+                self.reply(client, "Options: none").await;
+            } else {
+                self.reply(client, &format!("Options: {}", options.join(", ")))
+                    .await;
+            }
+        }
+    }
+
+    async fn set_option(&self, client: &Client, args: &ServiceArgs<'_>) {
+        let account = match client.account.lock().await.clone() {
+            Some(account) => account,
+            None => return,
+        };
+
+        let params = args.params();
+        let option = params[0].to_ascii_lowercase();
+        let value = params[1];
+        let message = {
+            let mut accounts = self.accounts.lock().await;
+            let entry = match accounts.get_mut(&account) {
+                Some(entry) => entry,
+                None => return,
+            };
+
+            match option.as_str() {
+                "password" => {
+                    entry.password_hash = hash_password(args.rest(1));
+                    Some("Your password has been changed".to_string())
+                }
+                "hide" | "enforce" => match parse_toggle(value) {
+                    Some(enabled) => {
+                        if option == "hide" {
+                            entry.hide = enabled;
+                        } else {
+                            entry.enforce = enabled;
+                        }
+
+                        Some(format!(
+                            "{} is now {}",
+                            option.to_ascii_uppercase(),
+                            if enabled { "ON" } else { "OFF" }
+                        ))
+                    }
+                    None => None,
+                },
+                "killprotect" => {
+                    if value.eq_ignore_ascii_case("default") {
+                        entry.kill_protect = None;
+                        Some("KILLPROTECT delay has been reset to the default".to_string())
+                    } else {
+                        match value.parse::<u64>() {
+                            Ok(delay) if delay > 0 => {
+                                entry.kill_protect = Some(delay);
+                                Some(format!("KILLPROTECT delay is now {} seconds", delay))
+                            }
+                            _ => None,
+                        }
+                    }
+                }
+                _ => {
+                    self.reply(client, "Unknown option, try HELP SET").await;
+                    return;
+                }
+            }
+        };
+
+        match message {
+            Some(message) => {
+                self.save().await;
+                self.reply(client, &message).await;
+            }
+            None => {
+                self.reply(client, "Invalid value specified").await;
+            }
+        }
+    }
+
+    pub async fn is_held(&self, nick: &str) -> bool {
+        let mut held = self.held.lock().await;
+        match held.get(nick) {
+            Some(expires) if *expires > Utc::now().timestamp() => true,
+            Some(_) => {
+                held.remove(nick);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /* NOTE(diath): Called whenever a client starts using a nick, warns about registered nicks and schedules the enforcement. */
+    pub async fn check_nick(&self, client: &Client) {
+        if !self.enforce {
+            return;
+        }
+
+        let nick = client.nick.lock().await.to_string();
+        if self.is_identified_for(client, &nick).await {
+            return;
+        }
+
+        let entry = match self.get_account(&nick).await {
+            Some(account) => self.accounts.lock().await.get(&account).cloned(),
+            None => None,
+        };
+
+        let delay = match entry {
+            Some(entry) if entry.enforce => entry.kill_protect.unwrap_or(self.enforce_delay),
+            _ => return,
+        };
+
+        self.reply(
+            client,
+            "This nick is registered and protected. If it is your nick, type /msg NickServ IDENTIFY <nick> <password>. Otherwise, please choose a different nick.",
+        )
+        .await;
+        self.reply(
+            client,
+            &format!(
+                "If you do not change within {} seconds, I will change your nick.",
+                delay
+            ),
+        )
+        .await;
+
+        let target = match client.server.get_client(&nick).await {
+            Some(target) => target,
+            None => return,
+        };
+
+        tokio::spawn(async move {
+            delay_until(Instant::now() + Duration::from_secs(delay)).await;
+
+            /* NOTE(diath): Make sure the nick still belongs to the same connection. */
+            if let Some(client) = target.server.get_client(&nick).await {
+                if Arc::ptr_eq(&client, &target) {
+                    target.server.nickserv.enforce_nick(&target, &nick).await;
+                }
+            }
+        });
+    }
+
+    async fn enforce_nick(&self, client: &Client, nick: &str) {
+        if self.is_identified_for(client, nick).await {
+            return;
+        }
+
+        let mut index = Utc::now().timestamp_subsec_micros() % 100_000;
+        let mut guest = format!("Guest{}", index);
+        while client.server.is_nick_mapped(&guest).await {
+            index = (index + 1) % 100_000;
+            guest = format!("Guest{}", index);
+        }
+
+        self.reply(client, &format!("Your nick has been changed to {}", guest))
             .await;
+        client.server.change_nick(client, &guest).await;
+
+        self.held.lock().await.insert(
+            nick.to_string(),
+            Utc::now().timestamp() + self.hold_duration as i64,
+        );
+    }
+
+    pub async fn save(&self) {
+        let data = NickServData {
+            version: DATA_VERSION,
+            accounts: self.accounts.lock().await.clone(),
+        };
+        storage::save(&self.data_path, "nickserv", &data);
     }
 }
 
+fn parse_toggle(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("on") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("off") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+static COMMANDS: &[ServiceCommand] = &[
+    ServiceCommand {
+        name: "REGISTER",
+        syntax: &["<nick> <password>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Anyone,
+        help: "Registers your current nick and logs you in to the new account",
+    },
+    ServiceCommand {
+        name: "IDENTIFY",
+        syntax: &["<password>", "<nick> <password>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Anyone,
+        help: "Logs you in to the account of a nick, the nick defaults to your current one",
+    },
+    ServiceCommand {
+        name: "LOGOUT",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Identified,
+        help: "Logs you out of your account",
+    },
+    ServiceCommand {
+        name: "DROP",
+        syntax: &["<nick> <password>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Anyone,
+        help: "Drops the account of a nick along with all of its nicks and memos",
+    },
+    ServiceCommand {
+        name: "GROUP",
+        syntax: &["<nick> <password>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Anyone,
+        help: "Adds your current nick to the account of another nick",
+    },
+    ServiceCommand {
+        name: "UNGROUP",
+        syntax: &["[nick]"],
+        min_params: 0,
+        privilege: ServicePrivilege::Identified,
+        help: "Removes a nick from your account, the nick defaults to your current one",
+    },
+    ServiceCommand {
+        name: "GLIST",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Identified,
+        help: "Lists the nicks grouped to your account",
+    },
+    ServiceCommand {
+        name: "INFO",
+        syntax: &["<nick>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Anyone,
+        help: "Shows information about a registered nick",
+    },
+    ServiceCommand {
+        name: "SET",
+        syntax: &[
+            "PASSWORD <password>",
+            "HIDE <ON|OFF>",
+            "ENFORCE <ON|OFF>",
+            "KILLPROTECT <seconds|DEFAULT>",
+        ],
+        min_params: 2,
+        privilege: ServicePrivilege::Identified,
+        help: "Changes the settings of your account",
+    },
+    ServiceCommand {
+        name: "GHOST",
+        syntax: &["<nick> <password>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Anyone,
+        help: "Disconnects a client using one of your nicks",
+    },
+    ServiceCommand {
+        name: "REGAIN",
+        syntax: &["<nick> <password>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Anyone,
+        help: "Disconnects a client using one of your nicks and takes the nick over",
+    },
+    ServiceCommand {
+        name: "RELEASE",
+        syntax: &["<nick> <password>"],
+        min_params: 2,
+        privilege: ServicePrivilege::Anyone,
+        help: "Releases a nick held after enforcement",
+    },
+];
+
 #[async_trait]
 impl Service for NickServ {
-    async fn on_message(&self, client: &Client, params: Vec<&str>) {
-        if params.len() < 1 {
-            return;
-        }
+    fn get_name(&self) -> &'static str {
+        "NickServ"
+    }
+
+    fn get_commands(&self) -> &'static [ServiceCommand] {
+        COMMANDS
+    }
+
+    async fn on_register(&self, client: &Client) {
+        self.check_nick(client).await;
+    }
+
+    async fn on_nick_change(&self, client: &Client, _old_nick: &str) {
+        self.check_nick(client).await;
+    }
+
+    async fn on_quit(&self, client: &Client, _reason: &str) {
+        self.update_last_seen(client).await;
+    }
 
-        match params[0].to_ascii_lowercase().as_str() {
-            "register" => {
-                if params.len() < 3 {
-                    self.reply(client, "Not enough params").await;
+    async fn on_command(&self, client: &Client, command: &str, args: &ServiceArgs<'_>) {
+        let params = args.params();
+        match command {
+            "REGISTER" => {
+                if client.account.lock().await.is_some() {
+                    self.reply(
+                        client,
+                        "You are already logged in, use GROUP to add a nick to your account",
+                    )
+                    .await;
+                } else if self.is_registered(params[1]).await
+                    || self.accounts.lock().await.contains_key(params[1])
+                {
+                    self.reply(client, "Nick already taken").await;
                 } else {
-                    if self.nicks.lock().await.contains_key(params[1]) {
-                        self.reply(client, "Nick already taken").await;
+                    let nick = client.nick.lock().await.to_string();
+                    if nick == params[1] {
+                        self.accounts.lock().await.insert(
+                            nick.clone(),
+                            Account {
+                                name: nick.clone(),
+                                password_hash: hash_password(args.rest(2)),
+                                nicks: vec![nick.clone()],
+                                registered_at: Utc::now().timestamp(),
+                                ..Default::default()
+                            },
+                        );
+                        self.nicks.lock().await.insert(nick.clone(), nick.clone());
+                        self.save().await;
+                        self.reply(client, "Nick successfully registered").await;
+                        self.login(client, &nick).await;
                     } else {
-                        let nick = client.nick.lock().await.to_string();
-                        if nick == params[1] {
-                            self.nicks
-                                .lock()
-                                .await
-                                .insert(params[1].to_string(), params[2].to_string());
-                            self.reply(client, "Nick successfully registered").await;
-                        } else {
-                            self.reply(client, "You can only register your current nick")
-                                .await;
-                        }
+                        self.reply(client, "You can only register your current nick")
+                            .await;
                     }
                 }
             }
-            "identify" => {
-                if params.len() < 3 {
-                    self.reply(client, "Not enough params").await;
-                } else if *client.identified.lock().await {
+            "IDENTIFY" => {
+                /* NOTE(diath): A single word is the password for the current nick, anything longer has to name the nick first. */
+                let (nick, password) = if params.len() > 2 {
+                    (params[1].to_string(), args.rest(2))
+                } else {
+                    (client.nick.lock().await.to_string(), args.rest(1))
+                };
+
+                if client.account.lock().await.is_some() {
                     self.reply(client, "You are already identified").await;
-                } else if let Some(password) = self.nicks.lock().await.get(params[1]) {
-                    if password == params[2] {
-                        (*client.identified.lock().await) = true;
-                        self.reply(client, "You are now identified for this nick")
-                            .await;
-                    } else {
+                    return;
+                }
+
+                match self.verify_password(&nick, password).await {
+                    Some(true) => {
+                        let account = self.get_account(&nick).await.unwrap_or(nick);
+                        self.reply(
+                            client,
+                            &format!("You are now identified for account {}", account),
+                        )
+                        .await;
+                        self.login(client, &account).await;
+                    }
+                    Some(false) => {
                         self.reply(client, "Wrong password").await;
                     }
-                } else {
-                    self.reply(client, "Nick not registered").await;
+                    None => {
+                        self.reply(client, "Nick not registered").await;
+                    }
                 }
             }
-            "logout" => {
-                let identified = *client.identified.lock().await;
-                if identified {
-                    (*client.identified.lock().await) = false;
-                    self.reply(client, "You are no longer identified").await;
-                } else {
-                    self.reply(client, "You are not identified").await;
-                }
+            "LOGOUT" => {
+                client.account.lock().await.take();
+                self.reply(client, "You are no longer identified").await;
             }
-            "drop" => {
-                if params.len() < 3 {
-                    self.reply(client, "Not enough params").await;
-                } else if *client.identified.lock().await {
+            "DROP" => {
+                if client.account.lock().await.is_some() {
                     self.reply(client, "You must logout before dropping a nick")
                         .await;
-                } else {
-                    let mut password = None;
-                    if let Some(_password) = self.nicks.lock().await.get(params[1]) {
-                        password = Some(_password.clone());
+                    return;
+                }
+
+                match self.verify_password(params[1], args.rest(2)).await {
+                    Some(true) => {}
+                    Some(false) => {
+                        self.reply(client, "Wrong password").await;
+                        return;
                     }
+                    None => {
+                        self.reply(client, "Nick not registered").await;
+                        return;
+                    }
+                }
 
-                    if let Some(password) = password {
-                        if password == params[2] {
-                            self.nicks.lock().await.remove(params[1]);
-                            self.reply(client, "The nick registration has been released")
-                                .await;
-                        } else {
-                            self.reply(client, "Wrong password").await;
-                        }
-                    } else {
+                let account = match self.get_account(params[1]).await {
+                    Some(account) => account,
+                    None => return,
+                };
+
+                if let Some(entry) = self.accounts.lock().await.remove(&account) {
+                    let mut nicks = self.nicks.lock().await;
+                    for nick in entry.nicks.iter() {
+                        nicks.remove(nick);
+                    }
+                }
+                self.save().await;
+                client.server.memoserv.clear(&account).await;
+
+                for target in client.server.get_clients().await {
+                    let mut logged_in = target.account.lock().await;
+                    if logged_in.as_ref() == Some(&account) {
+                        (*logged_in) = None;
+                    }
+                }
+
+                self.reply(
+                    client,
+                    &format!(
+                        "The account {} and all of its nicks have been released",
+                        account
+                    ),
+                )
+                .await;
+            }
+            "GROUP" => {
+                let nick = client.nick.lock().await.to_string();
+                if self.is_registered(&nick).await {
+                    self.reply(client, "Your current nick is already registered")
+                        .await;
+                    return;
+                }
+
+                match self.verify_password(params[1], args.rest(2)).await {
+                    Some(true) => {}
+                    Some(false) => {
+                        self.reply(client, "Wrong password").await;
+                        return;
+                    }
+                    None => {
                         self.reply(client, "Nick not registered").await;
+                        return;
+                    }
+                }
+
+                let account = match self.get_account(params[1]).await {
+                    Some(account) => account,
+                    None => return,
+                };
+
+                if let Some(entry) = self.accounts.lock().await.get_mut(&account) {
+                    entry.nicks.push(nick.clone());
+                }
+                self.nicks
+                    .lock()
+                    .await
+                    .insert(nick.clone(), account.clone());
+                self.save().await;
+
+                self.reply(
+                    client,
+                    &format!("Your nick {} is now grouped to account {}", nick, account),
+                )
+                .await;
+
+                let logged_in = client.account.lock().await.clone();
+                if logged_in.as_ref() != Some(&account) {
+                    self.login(client, &account).await;
+                }
+            }
+            "UNGROUP" => {
+                let account = match client.account.lock().await.clone() {
+                    Some(account) => account,
+                    None => return,
+                };
+
+                let nick = if params.len() > 1 {
+                    params[1].to_string()
+                } else {
+                    client.nick.lock().await.to_string()
+                };
+
+                if self.get_account(&nick).await != Some(account.clone()) {
+                    self.reply(
+                        client,
+                        &format!("Nick {} is not grouped to your account", nick),
+                    )
+                    .await;
+                    return;
+                }
+
+                let removed = match self.accounts.lock().await.get_mut(&account) {
+                    Some(entry) if entry.nicks.len() > 1 => {
+                        entry.nicks.retain(|entry| *entry != nick);
+                        true
                     }
+                    _ => false,
+                };
+
+                if removed {
+                    self.nicks.lock().await.remove(&nick);
+                    self.save().await;
+                    self.reply(
+                        client,
+                        &format!("Nick {} has been removed from your account", nick),
+                    )
+                    .await;
+                } else {
+                    self.reply(
+                        client,
+                        "You cannot ungroup the last nick of your account, use DROP instead",
+                    )
+                    .await;
                 }
             }
-            "help" => {
-                self.reply(client, "NickServ commands:").await;
-                self.reply(client, "REGISTER <nick> <password>").await;
-                self.reply(client, "IDENTIFY <nick> <password>").await;
-                self.reply(client, "LOGOUT").await;
-                self.reply(client, "DROP <nick> <password>").await;
-                self.reply(client, "HELP").await;
+            "GLIST" => {
+                let account = match client.account.lock().await.clone() {
+                    Some(account) => account,
+                    None => return,
+                };
+
+                let nicks = match self.accounts.lock().await.get(&account) {
+                    Some(entry) => entry.nicks.clone(),
+                    None => vec![],
+                };
+
+                self.reply(client, &format!("Nicks grouped to account {}:", account))
+                    .await;
+                for nick in nicks {
+                    self.reply(client, &nick).await;
+                }
             }
-            _ => {
-                self.reply(client, "Unknown command, try HELP").await;
+            "INFO" => {
+                self.send_info(client, params[1]).await;
             }
+            "SET" => {
+                self.set_option(client, &args.shift(1)).await;
+            }
+            "GHOST" | "REGAIN" => {
+                let nick = client.nick.lock().await.to_string();
+                if nick == params[1] {
+                    self.reply(client, "You are already using that nick").await;
+                    return;
+                }
+
+                match self.verify_password(params[1], args.rest(2)).await {
+                    Some(true) => {}
+                    Some(false) => {
+                        self.reply(client, "Wrong password").await;
+                        return;
+                    }
+                    None => {
+                        self.reply(client, "Nick not registered").await;
+                        return;
+                    }
+                }
+
+                if let Some(target) = client.server.get_client(params[1]).await {
+                    target
+                        .kill(&format!("{} command used by {}", command, nick))
+                        .await;
+                    self.reply(client, &format!("{} has been ghosted", params[1]))
+                        .await;
+                } else if command == "GHOST" {
+                    self.reply(client, &format!("{} is not online", params[1]))
+                        .await;
+                }
+
+                if command == "REGAIN" {
+                    self.held.lock().await.remove(params[1]);
+                    client.server.change_nick(client, params[1]).await;
+                    self.reply(client, "You have regained your nick and are now identified")
+                        .await;
+
+                    if let Some(account) = self.get_account(params[1]).await {
+                        let logged_in = client.account.lock().await.clone();
+                        if logged_in.as_ref() != Some(&account) {
+                            self.login(client, &account).await;
+                        }
+                    }
+                }
+            }
+            "RELEASE" => match self.verify_password(params[1], args.rest(2)).await {
+                Some(true) => {
+                    if self.held.lock().await.remove(params[1]).is_some() {
+                        self.reply(client, &format!("{} has been released", params[1]))
+                            .await;
+                    } else {
+                        self.reply(client, &format!("{} is not being held", params[1]))
+                            .await;
+                    }
+                }
+                Some(false) => {
+                    self.reply(client, "Wrong password").await;
+                }
+                None => {
+                    self.reply(client, "Nick not registered").await;
+                }
+            },
+            _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_plain_map() {
+        let hash = hash_password("secret pass");
+        let value = migrate(0, json!({ "diath": hash })).unwrap();
+        let data: NickServData = serde_json::from_value(value).unwrap();
+        assert_eq!(data.version, DATA_VERSION);
+
+        let account = &data.accounts["diath"];
+        assert_eq!(account.name, "diath");
+        assert_eq!(account.nicks, vec!["diath".to_string()]);
+        assert_eq!(account.password_hash, hash);
+        assert_eq!(
+            pbkdf2_check("secret pass", &account.password_hash),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn migrate_rejects_garbage() {
+        assert!(migrate(0, json!({ "diath": 5 })).is_err());
+        assert!(migrate(0, json!([])).is_err());
+        assert!(migrate(DATA_VERSION + 1, json!({})).is_err());
+    }
+
+    #[test]
+    fn migrate_current() {
+        let hash = hash_password("secret");
+        let value = json!({ "version": DATA_VERSION, "accounts": { "diath": { "name": "diath", "password_hash": hash, "nicks": ["diath"] } } });
+        assert_eq!(migrate(DATA_VERSION, value.clone()), Ok(value));
+    }
+}
@@ -0,0 +1,687 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use chrono::Utc;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::Mutex;
+
+use crate::client::Client;
+use crate::mask::check_mask;
+use crate::service::{Service, ServiceArgs, ServiceCommand, ServicePrivilege};
+use crate::services::{format_timestamp, parse_duration};
+use crate::storage;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Akill {
+    pub mask: String,
+    pub reason: String,
+    pub set_by: String,
+    pub set_at: i64,
+    pub expires: i64,
+}
+
+impl Akill {
+    fn is_expired(&self) -> bool {
+        self.expires != 0 && self.expires <= Utc::now().timestamp()
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct OperServData {
+    akills: Vec<Akill>,
+    jupes: HashMap<String, String>,
+    session_limit: Option<usize>,
+    session_exceptions: HashMap<String, usize>,
+}
+
+pub struct OperServ {
+    pub akills: Mutex<Vec<Akill>>,
+    pub jupes: Mutex<HashMap<String, String>>,
+    pub session_limit: Mutex<usize>,
+    pub session_exceptions: Mutex<HashMap<String, usize>>,
+    custom_session_limit: Mutex<bool>,
+    data_path: String,
+}
+
+impl OperServ {
+    pub fn new(data_path: &str, session_limit: usize) -> OperServ {
+        let data: OperServData = storage::load(data_path, "operserv");
+        OperServ {
+            akills: Mutex::new(data.akills),
+            jupes: Mutex::new(data.jupes),
+            session_limit: Mutex::new(data.session_limit.unwrap_or(session_limit)),
+            session_exceptions: Mutex::new(data.session_exceptions),
+            custom_session_limit: Mutex::new(data.session_limit.is_some()),
+            data_path: data_path.to_string(),
+        }
+    }
+
+    pub async fn save(&self) {
+        let session_limit = if *self.custom_session_limit.lock().await {
+            Some(*self.session_limit.lock().await)
+        } else {
+            None
+        };
+
+        let data = OperServData {
+            akills: self.akills.lock().await.clone(),
+            jupes: self.jupes.lock().await.clone(),
+            session_limit,
+            session_exceptions: self.session_exceptions.lock().await.clone(),
+        };
+        storage::save(&self.data_path, "operserv", &data);
+    }
+
+    async fn log(&self, client: &Client, message: String) {
+        let nick = client.nick.lock().await.to_string();
+        client
+            .server
+            .broadcast_oper_notice(format!("OperServ: {} {}", nick, message))
+            .await;
+    }
+
+    pub async fn find_akill(&self, user: &str, hosts: &[String]) -> Option<Akill> {
+        let mut akills = self.akills.lock().await;
+        let count = akills.len();
+        akills.retain(|akill| !akill.is_expired());
+        let expired = count != akills.len();
+
+        let result = akills
+            .iter()
+            .find(|akill| {
+                hosts
+                    .iter()
+                    .any(|host| check_mask(&akill.mask, &format!("{}@{}", user, host)))
+            })
+            .cloned();
+        drop(akills);
+
+        if expired {
+            self.save().await;
+        }
+
+        result
+    }
+
+    pub async fn is_juped(&self, name: &str) -> Option<String> {
+        self.jupes
+            .lock()
+            .await
+            .get(&name.to_lowercase())
+            .map(|reason| reason.to_string())
+    }
+
+    pub async fn get_session_limit(&self, ip: &str) -> usize {
+        for (mask, limit) in &*self.session_exceptions.lock().await {
+            if check_mask(mask, ip) {
+                return *limit;
+            }
+        }
+
+        *self.session_limit.lock().await
+    }
+
+    /* NOTE(diath): Applies the configured default, a limit set with SESSION LIMIT takes precedence over the config. */
+    pub async fn set_default_session_limit(&self, limit: usize) -> bool {
+        if *self.custom_session_limit.lock().await {
+            return false;
+        }
+
+        let mut session_limit = self.session_limit.lock().await;
+        let changed = *session_limit != limit;
+        *session_limit = limit;
+        changed
+    }
+
+    async fn on_akill(&self, client: &Client, args: &ServiceArgs<'_>) {
+        let params = args.params();
+        match params[0].to_ascii_lowercase().as_str() {
+            "add" => {
+                let mut args = args.shift(1);
+                let mut expires = 0;
+                if let Some(param) = args.get(0) {
+                    if param.starts_with('+') {
+                        match parse_duration(param) {
+                            Some(0) => {}
+                            Some(duration) => expires = Utc::now().timestamp() + duration,
+                            None => {
+                                self.reply(client, "Invalid expiry time specified").await;
+                                return;
+                            }
+                        }
+                        args = args.shift(1);
+                    }
+                }
+
+                let params = args.params();
+                if params.len() < 2 {
+                    self.reply_syntax(client, "AKILL").await;
+                    return;
+                }
+
+                let mask = if params[0].contains('@') {
+                    params[0].to_string()
+                } else {
+                    format!("*@{}", params[0])
+                };
+
+                if mask == "*@*" {
+                    self.reply(client, "That mask is too wide").await;
+                    return;
+                }
+
+                let reason = args.rest(1).to_string();
+                let nick = client.nick.lock().await.to_string();
+                {
+                    let mut akills = self.akills.lock().await;
+                    akills.retain(|akill| akill.mask != mask);
+                    akills.push(Akill {
+                        mask: mask.clone(),
+                        reason: reason.clone(),
+                        set_by: nick,
+                        set_at: Utc::now().timestamp(),
+                        expires,
+                    });
+                }
+                self.save().await;
+
+                self.reply(client, &format!("Added AKILL on {}", mask))
+                    .await;
+                self.log(
+                    client,
+                    format!(
+                        "added an AKILL on {} (expires: {}): {}",
+                        mask,
+                        format_timestamp(expires),
+                        reason
+                    ),
+                )
+                .await;
+
+                for target in client.server.get_clients().await {
+                    let user = target.user.lock().await.to_string();
+                    let hosts = target.get_ban_hosts().await;
+                    if hosts
+                        .iter()
+                        .any(|host| check_mask(&mask, &format!("{}@{}", user, host)))
+                    {
+                        target.kill(&format!("AKILL: {}", reason)).await;
+                    }
+                }
+            }
+            "del" => {
+                if params.len() < 2 {
+                    self.reply_syntax(client, "AKILL").await;
+                    return;
+                }
+
+                let removed = {
+                    let mut akills = self.akills.lock().await;
+                    let count = akills.len();
+                    akills.retain(|akill| akill.mask != params[1]);
+                    count != akills.len()
+                };
+
+                if removed {
+                    self.save().await;
+                    self.reply(client, &format!("Removed AKILL on {}", params[1]))
+                        .await;
+                    self.log(client, format!("removed the AKILL on {}", params[1]))
+                        .await;
+                } else {
+                    self.reply(client, &format!("No AKILL on {} found", params[1]))
+                        .await;
+                }
+            }
+            "list" => {
+                let akills = self.akills.lock().await.clone();
+                self.reply(client, "List of AKILLs:").await;
+                for akill in akills.iter().filter(|akill| !akill.is_expired()) {
+                    self.reply(
+                        client,
+                        &format!(
+                            "{} - set by {} at {}, expires {} ({})",
+                            akill.mask,
+                            akill.set_by,
+                            format_timestamp(akill.set_at),
+                            format_timestamp(akill.expires),
+                            akill.reason
+                        ),
+                    )
+                    .await;
+                }
+            }
+            _ => {
+                self.reply(client, "Unknown AKILL command, try HELP AKILL")
+                    .await;
+            }
+        }
+    }
+
+    async fn on_session(&self, client: &Client, params: &[&str]) {
+        match params[0].to_ascii_lowercase().as_str() {
+            "list" => {
+                let threshold = match params.get(1) {
+                    Some(value) => value.parse::<usize>().unwrap_or(2),
+                    None => 2,
+                };
+
+                let mut sessions: HashMap<String, usize> = HashMap::new();
+                for target in client.server.get_clients().await {
+                    *sessions.entry(target.address.ip().to_string()).or_insert(0) += 1;
+                }
+
+                self.reply(
+                    client,
+                    &format!("Hosts with at least {} sessions:", threshold),
+                )
+                .await;
+                for (ip, count) in sessions {
+                    if count >= threshold {
+                        self.reply(client, &format!("{} - {}", ip, count)).await;
+                    }
+                }
+            }
+            "limit" => {
+                if params.len() < 2 {
+                    self.reply(
+                        client,
+                        &format!("The session limit is {}", *self.session_limit.lock().await),
+                    )
+                    .await;
+                    return;
+                }
+
+                match params[1].parse::<usize>() {
+                    Ok(limit) => {
+                        (*self.session_limit.lock().await) = limit;
+                        (*self.custom_session_limit.lock().await) = true;
+                        self.save().await;
+
+                        self.reply(client, &format!("The session limit is now {}", limit))
+                            .await;
+                        self.log(client, format!("set the session limit to {}", limit))
+                            .await;
+                    }
+                    Err(_) => {
+                        self.reply(client, "Invalid session limit specified").await;
+                    }
+                }
+            }
+            "exception" => match params.get(1).map(|param| param.to_ascii_lowercase()) {
+                Some(command) if command == "add" => {
+                    if params.len() < 4 {
+                        self.reply_syntax(client, "SESSION").await;
+                        return;
+                    }
+
+                    match params[3].parse::<usize>() {
+                        Ok(limit) => {
+                            self.session_exceptions
+                                .lock()
+                                .await
+                                .insert(params[2].to_string(), limit);
+                            self.save().await;
+
+                            self.reply(
+                                client,
+                                &format!("Session limit for {} is now {}", params[2], limit),
+                            )
+                            .await;
+                            self.log(
+                                client,
+                                format!("added a session exception for {} ({})", params[2], limit),
+                            )
+                            .await;
+                        }
+                        Err(_) => {
+                            self.reply(client, "Invalid session limit specified").await;
+                        }
+                    }
+                }
+                Some(command) if command == "del" => {
+                    if params.len() < 3 {
+                        self.reply_syntax(client, "SESSION").await;
+                        return;
+                    }
+
+                    let removed = self
+                        .session_exceptions
+                        .lock()
+                        .await
+                        .remove(params[2])
+                        .is_some();
+                    if removed {
+                        self.save().await;
+                        self.reply(
+                            client,
+                            &format!("Removed the session exception for {}", params[2]),
+                        )
+                        .await;
+                        self.log(
+                            client,
+                            format!("removed the session exception for {}", params[2]),
+                        )
+                        .await;
+                    } else {
+                        self.reply(
+                            client,
+                            &format!("No session exception for {} found", params[2]),
+                        )
+                        .await;
+                    }
+                }
+                Some(command) if command == "list" => {
+                    let exceptions = self.session_exceptions.lock().await.clone();
+                    self.reply(client, "List of session exceptions:").await;
+                    for (mask, limit) in exceptions {
+                        self.reply(client, &format!("{} - {}", mask, limit)).await;
+                    }
+                }
+                _ => {
+                    self.reply(
+                        client,
+                        "Unknown SESSION EXCEPTION command, try HELP SESSION",
+                    )
+                    .await;
+                }
+            },
+            _ => {
+                self.reply(client, "Unknown SESSION command, try HELP SESSION")
+                    .await;
+            }
+        }
+    }
+
+    async fn on_jupe(&self, client: &Client, args: &ServiceArgs<'_>) {
+        let params = args.params();
+        match params[0].to_ascii_lowercase().as_str() {
+            "add" => {
+                if params.len() < 3 {
+                    self.reply_syntax(client, "JUPE").await;
+                    return;
+                }
+
+                let name = params[1].to_lowercase();
+                let reason = args.rest(2).to_string();
+                self.jupes.lock().await.insert(name, reason.clone());
+                self.save().await;
+
+                self.reply(client, &format!("{} has been juped", params[1]))
+                    .await;
+                self.log(client, format!("juped {}: {}", params[1], reason))
+                    .await;
+            }
+            "del" => {
+                if params.len() < 2 {
+                    self.reply_syntax(client, "JUPE").await;
+                    return;
+                }
+
+                let removed = self
+                    .jupes
+                    .lock()
+                    .await
+                    .remove(&params[1].to_lowercase())
+                    .is_some();
+                if removed {
+                    self.save().await;
+                    self.reply(client, &format!("{} is no longer juped", params[1]))
+                        .await;
+                    self.log(client, format!("removed the jupe on {}", params[1]))
+                        .await;
+                } else {
+                    self.reply(client, &format!("{} is not juped", params[1]))
+                        .await;
+                }
+            }
+            "list" => {
+                let jupes = self.jupes.lock().await.clone();
+                self.reply(client, "List of jupes:").await;
+                for (name, reason) in jupes {
+                    self.reply(client, &format!("{} - {}", name, reason)).await;
+                }
+            }
+            _ => {
+                self.reply(client, "Unknown JUPE command, try HELP JUPE")
+                    .await;
+            }
+        }
+    }
+
+    async fn on_global(&self, client: &Client, message: String) {
+        let prefix = client.server.get_service_prefix("OperServ");
+        for target in client.server.get_clients().await {
+            let nick = target.nick.lock().await.to_string();
+            target
+                .send_raw(format!(
+                    ":{} NOTICE {} :[Global Notice] {}",
+                    prefix, nick, message
+                ))
+                .await;
+        }
+
+        self.log(client, format!("sent a global notice: {}", message))
+            .await;
+    }
+
+    async fn on_mode(&self, client: &Client, params: &[&str]) {
+        let channel_name = params[0];
+        let modes = params[1..]
+            .iter()
+            .map(|param| param.to_string())
+            .collect::<Vec<String>>();
+
+        match client
+            .server
+            .override_channel_mode(client, channel_name, modes)
+            .await
+        {
+            Some(changes) => {
+                if changes.is_empty() {
+                    self.reply(client, "No modes were changed").await;
+                } else {
+                    self.log(client, format!("used MODE {} {}", channel_name, changes))
+                        .await;
+                }
+            }
+            None => {
+                self.reply(client, &format!("Channel {} does not exist", channel_name))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_kick(&self, client: &Client, args: &ServiceArgs<'_>) {
+        let channel_name = args.params()[0];
+        let nick = args.params()[1];
+        let reason = if args.len() > 2 {
+            args.rest(2).to_string()
+        } else {
+            "Kicked".to_string()
+        };
+
+        if !client
+            .server
+            .has_channel_participant(channel_name, nick)
+            .await
+        {
+            self.reply(
+                client,
+                &format!("{} is not on channel {}", nick, channel_name),
+            )
+            .await;
+            return;
+        }
+
+        if client
+            .server
+            .kick_channel(client, channel_name, nick, reason.clone())
+            .await
+        {
+            if let Some(target) = client.server.get_client(nick).await {
+                let name = channel_name.to_lowercase();
+                target
+                    .channels
+                    .lock()
+                    .await
+                    .retain(|channel| channel.to_lowercase() != name);
+            }
+
+            self.log(
+                client,
+                format!("used KICK {} {} ({})", channel_name, nick, reason),
+            )
+            .await;
+        }
+    }
+
+    async fn on_stats(&self, client: &Client) {
+        let server = &client.server;
+        let uptime = server.uptime().await;
+        let stats = server.get_stats().await;
+
+        self.reply(client, "Network statistics:").await;
+        self.reply(
+            client,
+            &format!(
+                "Uptime: {} days, {:02}:{:02}:{:02}",
+                uptime / 86400,
+                (uptime / 3600) % 24,
+                (uptime / 60) % 60,
+                uptime % 60
+            ),
+        )
+        .await;
+        self.reply(
+            client,
+            &format!(
+                "Users: {} ({} unregistered), operators: {}, channels: {}",
+                stats.clients, stats.pending, stats.operators, stats.channels
+            ),
+        )
+        .await;
+        self.reply(
+            client,
+            &format!(
+                "AKILLs: {}, jupes: {}, session limit: {}, session exceptions: {}",
+                self.akills.lock().await.len(),
+                self.jupes.lock().await.len(),
+                *self.session_limit.lock().await,
+                self.session_exceptions.lock().await.len()
+            ),
+        )
+        .await;
+        self.reply(
+            client,
+            &format!(
+                "Traffic: received {} packets ({} bytes), sent {} packets ({} bytes)",
+                *server.recv_packets.read().await,
+                *server.recv_bytes.read().await,
+                *server.sent_packets.read().await,
+                *server.sent_bytes.read().await
+            ),
+        )
+        .await;
+    }
+}
+
+static COMMANDS: &[ServiceCommand] = &[
+    ServiceCommand {
+        name: "AKILL",
+        syntax: &["ADD [+expiry] <mask> <reason>", "DEL <mask>", "LIST"],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Manages the network bans applied on connect",
+    },
+    ServiceCommand {
+        name: "SESSION",
+        syntax: &[
+            "LIST [threshold]",
+            "LIMIT [count]",
+            "EXCEPTION ADD <ip mask> <limit>",
+            "EXCEPTION DEL <ip mask>",
+            "EXCEPTION LIST",
+        ],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Manages the limit of connections per IP address",
+    },
+    ServiceCommand {
+        name: "JUPE",
+        syntax: &["ADD <nick|channel> <reason>", "DEL <nick|channel>", "LIST"],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Manages the nicks and channels that cannot be used",
+    },
+    ServiceCommand {
+        name: "GLOBAL",
+        syntax: &["<message>"],
+        min_params: 1,
+        privilege: ServicePrivilege::Operator,
+        help: "Sends a notice to every user on the network",
+    },
+    ServiceCommand {
+        name: "MODE",
+        syntax: &["<channel> <modes> [params]"],
+        min_params: 2,
+        privilege: ServicePrivilege::Operator,
+        help: "Changes the modes of a channel",
+    },
+    ServiceCommand {
+        name: "KICK",
+        syntax: &["<channel> <nick> [reason]"],
+        min_params: 2,
+        privilege: ServicePrivilege::Operator,
+        help: "Kicks a user from a channel",
+    },
+    ServiceCommand {
+        name: "STATS",
+        syntax: &[""],
+        min_params: 0,
+        privilege: ServicePrivilege::Operator,
+        help: "Shows network statistics",
+    },
+];
+
+#[async_trait]
+impl Service for OperServ {
+    fn get_name(&self) -> &'static str {
+        "OperServ"
+    }
+
+    fn get_commands(&self) -> &'static [ServiceCommand] {
+        COMMANDS
+    }
+
+    async fn on_command(&self, client: &Client, command: &str, args: &ServiceArgs<'_>) {
+        let params = args.params();
+        match command {
+            "AKILL" => {
+                self.on_akill(client, &args.shift(1)).await;
+            }
+            "SESSION" => {
+                self.on_session(client, &params[1..]).await;
+            }
+            "JUPE" => {
+                self.on_jupe(client, &args.shift(1)).await;
+            }
+            "GLOBAL" => {
+                self.on_global(client, args.rest(1).to_string()).await;
+            }
+            "MODE" => {
+                self.on_mode(client, &params[1..]).await;
+            }
+            "KICK" => {
+                self.on_kick(client, &args.shift(1)).await;
+            }
+            "STATS" => {
+                self.on_stats(client).await;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read_to_string, remove_file, rename, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::Utc;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use serde_json::Value;
+
+pub fn load<T: DeserializeOwned + Default>(path: &str, name: &str) -> T {
+    load_versioned(path, name, |_, value| Ok(value))
+}
+
+/* NOTE(diath): The migration receives the stored "version" field (0 if the file has none) and returns the data in the current format. */
+pub fn load_versioned<T, F>(path: &str, name: &str, migrate: F) -> T
+where
+    T: DeserializeOwned + Default,
+    F: Fn(u64, Value) -> Result<Value, String>,
+{
+    let filename = Path::new(path).join(format!("{}.json", name));
+    let contents = match read_to_string(&filename) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Default::default(),
+        Err(error) => {
+            log::error!("Failed to read {}: {}", filename.display(), error);
+            set_aside(&filename);
+            return Default::default();
+        }
+    };
+
+    let result = serde_json::from_str::<Value>(&contents)
+        .map_err(|error| error.to_string())
+        .and_then(|value| {
+            let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+            migrate(version, value)
+        })
+        .and_then(|value| serde_json::from_value(value).map_err(|error| error.to_string()));
+
+    match result {
+        Ok(data) => data,
+        Err(error) => {
+            log::error!("Storage parse error ({}): {}", filename.display(), error);
+            set_aside(&filename);
+            Default::default()
+        }
+    }
+}
+
+/* NOTE(diath): The files that could neither be loaded nor moved aside, they are never saved over until the server is restarted. */
+fn get_locked_files() -> &'static Mutex<HashSet<PathBuf>> {
+    static LOCKED: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+    LOCKED.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+/* NOTE(diath): Move an unreadable file out of the way so the next save does not overwrite the data in it. */
+fn set_aside(filename: &Path) {
+    let target = filename.with_extension(format!("json.broken-{}", Utc::now().timestamp()));
+    match rename(filename, &target) {
+        Ok(_) => log::error!(
+            "Moved {} to {}, it has to be repaired by hand",
+            filename.display(),
+            target.display()
+        ),
+        Err(error) => {
+            log::error!(
+                "Failed to move {} aside: {}, it will not be saved until it is repaired by hand and the server is restarted",
+                filename.display(),
+                error
+            );
+            get_locked_files()
+                .lock()
+                .unwrap()
+                .insert(filename.to_path_buf());
+        }
+    }
+}
+
+pub fn save<T: Serialize>(path: &str, name: &str, data: &T) {
+    if let Err(error) = create_dir_all(path) {
+        log::warn!("Failed to create data directory ({}): {}", path, error);
+        return;
+    }
+
+    let contents = match serde_json::to_string_pretty(data) {
+        Ok(contents) => contents,
+        Err(error) => {
+            log::warn!("Storage serialize error ({}): {}", name, error);
+            return;
+        }
+    };
+
+    let filename = Path::new(path).join(format!("{}.json", name));
+    if get_locked_files().lock().unwrap().contains(&filename) {
+        log::warn!("Not saving {}, it could not be loaded.", filename.display());
+        return;
+    }
+
+    /* NOTE(diath): Write to a temporary file first so a crash mid-write does not leave us with a truncated file, the file is only readable by the owner since it holds account data. */
+    let temporary = Path::new(path).join(format!("{}.json.tmp", name));
+    let _ = remove_file(&temporary);
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temporary)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|_| rename(&temporary, &filename));
+    if let Err(error) = result {
+        log::warn!("Failed to save {}: {}", filename.display(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{create_dir, read_dir, remove_dir_all, write};
+
+    fn get_directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("ayame-storage-{}-{}", std::process::id(), name));
+        let _ = remove_dir_all(&directory);
+        create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn broken_file_is_moved_aside() {
+        let directory = get_directory("aside");
+        let path = directory.to_str().unwrap();
+        write(directory.join("data.json"), "{ broken").unwrap();
+
+        let data: HashMap<String, String> = load(path, "data");
+        assert!(data.is_empty());
+        assert!(!directory.join("data.json").exists());
+
+        let names = read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].starts_with("data.json.broken-"));
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn broken_file_that_can_not_be_moved_is_kept() {
+        let directory = get_directory("locked");
+        let path = directory.to_str().unwrap();
+        write(directory.join("data.json"), "{ broken").unwrap();
+
+        /* NOTE(diath): A directory in place of the target makes the rename fail even when running as root. */
+        let now = Utc::now().timestamp();
+        for timestamp in now..now + 3 {
+            let target = directory.join(format!("data.json.broken-{}", timestamp));
+            create_dir(&target).unwrap();
+            write(target.join("file"), "").unwrap();
+        }
+
+        let data: HashMap<String, String> = load(path, "data");
+        assert!(data.is_empty());
+
+        save(path, "data", &data);
+        assert_eq!(
+            read_to_string(directory.join("data.json")).unwrap(),
+            "{ broken"
+        );
+
+        remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use chrono::Utc;
+
+/* NOTE(diath): IRCv3 message tags, a line may start with `@key=value;+client/key ` which the parser does not understand, so the tags are split off before parsing. */
+
+/* NOTE(diath): Longer labels are ignored and the command gets no labeled response. */
+pub const MAX_LABEL_LENGTH: usize = 64;
+
+/* NOTE(diath): The limit on the client-only tags a client may send, excluding the leading @ and the trailing space. */
+pub const MAX_CLIENT_TAGS_LENGTH: usize = 4094;
+
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub key: String,
+    /* NOTE(diath): A missing value and an empty value mean the same thing. */
+    pub value: String,
+}
+
+impl Tag {
+    pub fn is_client_only(&self) -> bool {
+        self.key.starts_with('+')
+    }
+}
+
+/* NOTE(diath): Returns the raw tag section without the @ and the rest of the line, the section is None for a line without tags. */
+pub fn split_tags(line: &str) -> (Option<&str>, &str) {
+    if !line.starts_with('@') {
+        return (None, line);
+    }
+
+    match line.find(' ') {
+        Some(index) => (Some(&line[1..index]), line[index..].trim_start_matches(' ')),
+        None => (Some(&line[1..]), ""),
+    }
+}
+
+/* NOTE(diath): Tags with an invalid key are dropped, a key given more than once keeps the last value. */
+pub fn parse_tags(section: &str) -> Vec<Tag> {
+    let mut tags: Vec<Tag> = vec![];
+    for tag in section.split(';').filter(|tag| !tag.is_empty()) {
+        let (key, value) = match tag.find('=') {
+            Some(index) => (&tag[..index], unescape_value(&tag[index + 1..])),
+            None => (tag, String::new()),
+        };
+
+        if !is_valid_key(key) {
+            log::debug!("Dropping invalid tag key {}.", key);
+            continue;
+        }
+
+        tags.retain(|tag| tag.key != key);
+        tags.push(Tag {
+            key: key.to_string(),
+            value,
+        });
+    }
+
+    tags
+}
+
+/* NOTE(diath): <key> ::= [ '+' ] [ <vendor> '/' ] <key_name>, the vendor is a host name and the name is letters, digits and hyphens. */
+pub fn is_valid_key(key: &str) -> bool {
+    let key = key.strip_prefix('+').unwrap_or(key);
+    let (vendor, name) = match key.rfind('/') {
+        Some(index) => (Some(&key[..index]), &key[index + 1..]),
+        None => (None, key),
+    };
+
+    if let Some(vendor) = vendor {
+        if vendor.is_empty()
+            || !vendor
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '.')
+        {
+            return false;
+        }
+    }
+
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+}
+
+pub fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+/* NOTE(diath): An unknown escape drops the backslash and a lone backslash at the end is dropped entirely. */
+pub fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(ch) => unescaped.push(ch),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+pub fn format_tags(tags: &[Tag]) -> String {
+    tags.iter()
+        .map(|tag| {
+            if tag.value.is_empty() {
+                tag.key.to_string()
+            } else {
+                format!("{}={}", tag.key, escape_value(&tag.value))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/* NOTE(diath): The tags of an outgoing message, built once per broadcast and then put in front of the line for every recipient according to what it negotiated. The time and msgid of a message relayed by a linked server are kept so they are the same on the whole network. */
+pub struct MessageTags {
+    time: String,
+    tags: String,
+}
+
+impl MessageTags {
+    pub fn new(tags: &[Tag]) -> MessageTags {
+        let time = match tags.iter().find(|tag| tag.key == "time") {
+            Some(tag) => tag.value.to_string(),
+            None => Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        };
+
+        let msgid = match tags.iter().find(|tag| tag.key == "msgid") {
+            Some(tag) => tag.value.to_string(),
+            None => generate_id(),
+        };
+
+        let mut message_tags = vec![Tag {
+            key: "msgid".to_string(),
+            value: msgid,
+        }];
+        message_tags.extend(tags.iter().filter(|tag| tag.is_client_only()).cloned());
+
+        MessageTags {
+            time: escape_value(&time),
+            tags: format_tags(&message_tags),
+        }
+    }
+
+    /* NOTE(diath): The time tag belongs to server-time, everything else to message-tags. */
+    pub fn apply_for(&self, line: String, server_time: bool, message_tags: bool) -> String {
+        match (server_time, message_tags) {
+            (true, true) => format!("@time={};{} {}", self.time, self.tags, line),
+            (true, false) => format!("@time={} {}", self.time, line),
+            (false, true) => format!("@{} {}", self.tags, line),
+            (false, false) => line,
+        }
+    }
+
+    /* NOTE(diath): Links always get every tag. */
+    pub fn apply(&self, line: String) -> String {
+        self.apply_for(line, true, true)
+    }
+}
+
+/* NOTE(diath): A counter with a random starting point, unique within the server and unlikely to collide with the ids of other servers, used for message ids and batch references. */
+pub fn generate_id() -> String {
+    static COUNTER: OnceLock<AtomicU64> = OnceLock::new();
+    let counter =
+        COUNTER.get_or_init(|| AtomicU64::new(RandomState::new().build_hasher().finish()));
+    let id = counter.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}{:08x}", id, Utc::now().timestamp() as u32)
+}
+
+/* NOTE(diath): Adds a tag in front of a line that may or may not have tags already. */
+pub fn prepend_tag(line: &str, key: &str, value: &str) -> String {
+    let tag = format_tags(&[Tag {
+        key: key.to_string(),
+        value: value.to_string(),
+    }]);
+
+    match line.strip_prefix('@') {
+        Some(line) => format!("@{};{}", tag, line),
+        None => format!("@{} {}", tag, line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(tags: &[Tag]) -> Vec<(&str, &str)> {
+        tags.iter()
+            .map(|tag| (tag.key.as_str(), tag.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_tags("PING :x"), (None, "PING :x"));
+        assert_eq!(split_tags("@a=1;b PING :x"), (Some("a=1;b"), "PING :x"));
+        assert_eq!(split_tags("@a=1   PING"), (Some("a=1"), "PING"));
+        assert_eq!(split_tags("@a=1"), (Some("a=1"), ""));
+        assert_eq!(split_tags("@ PING"), (Some(""), "PING"));
+    }
+
+    #[test]
+    fn parse() {
+        let tags = parse_tags("a=1;+b;c=;;d=x\\sy");
+        assert_eq!(
+            pairs(&tags),
+            vec![("a", "1"), ("+b", ""), ("c", ""), ("d", "x y")]
+        );
+        assert!(tags[1].is_client_only());
+        assert!(!tags[0].is_client_only());
+
+        assert!(parse_tags("").is_empty());
+        assert!(parse_tags(";;").is_empty());
+    }
+
+    #[test]
+    fn parse_duplicate_keys() {
+        let tags = parse_tags("a=1;b=2;a=3;+a=4");
+        assert_eq!(pairs(&tags), vec![("b", "2"), ("a", "3"), ("+a", "4")]);
+    }
+
+    #[test]
+    fn parse_drops_invalid_keys() {
+        let tags = parse_tags("=1;a b=2;/x=3;vendor.example/;ok=4;bad_key=5");
+        assert_eq!(pairs(&tags), vec![("ok", "4")]);
+    }
+
+    #[test]
+    fn parse_vendor_keys() {
+        let tags = parse_tags("+example.com/typing=active;draft/label=x");
+        assert_eq!(
+            pairs(&tags),
+            vec![("+example.com/typing", "active"), ("draft/label", "x")]
+        );
+    }
+
+    #[test]
+    fn valid_keys() {
+        assert!(is_valid_key("msgid"));
+        assert!(is_valid_key("+typing"));
+        assert!(is_valid_key("example.com/key-name"));
+        assert!(is_valid_key("+my-vendor.example/k2"));
+
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("+"));
+        assert!(!is_valid_key("/key"));
+        assert!(!is_valid_key("example.com/"));
+        assert!(!is_valid_key("under_score"));
+        assert!(!is_valid_key("ex ample.com/key"));
+        assert!(!is_valid_key("++key"));
+    }
+
+    #[test]
+    fn unescape() {
+        assert_eq!(unescape_value(""), "");
+        assert_eq!(unescape_value("a\\:b\\sc\\\\d\\r\\n"), "a;b c\\d\r\n");
+        assert_eq!(unescape_value("\\x"), "x");
+        assert_eq!(unescape_value("trailing\\"), "trailing");
+        assert_eq!(unescape_value("\\\\\\"), "\\");
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let value = "a;b c\\d\r\n";
+        assert_eq!(escape_value(value), "a\\:b\\sc\\\\d\\r\\n");
+        assert_eq!(unescape_value(&escape_value(value)), value);
+    }
+
+    #[test]
+    fn format() {
+        let tags = parse_tags("a=1;+b;c=x\\sy");
+        assert_eq!(format_tags(&tags), "a=1;+b;c=x\\sy");
+        assert_eq!(format_tags(&[]), "");
+    }
+
+    #[test]
+    fn prepend() {
+        assert_eq!(prepend_tag("PING", "label", "a b"), "@label=a\\sb PING");
+        assert_eq!(
+            prepend_tag("@time=x PING", "label", "1"),
+            "@label=1;time=x PING"
+        );
+    }
+}
@@ -0,0 +1,416 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/* NOTE(diath): A server process running from its own config and data directory, killed when dropped. */
+struct Instance {
+    port: u16,
+    directory: PathBuf,
+    child: Child,
+}
+
+impl Instance {
+    /* NOTE(diath): The dns lines go into the [dns] table, the rest is appended to the config. */
+    fn start(name: &str, port: u16, dns: &str, rest: &str) -> Instance {
+        let directory = env::temp_dir().join(format!("ayame-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let config = format!(
+            r#"[server]
+name = "{name}"
+host = "127.0.0.1"
+port = {port}
+data_path = "{data}"
+
+[dns]
+{dns}
+[[oper]]
+name = "admin"
+password = "secret"
+
+{rest}"#,
+            name = name,
+            port = port,
+            data = directory.join("data").display(),
+            dns = dns,
+            rest = rest,
+        );
+        fs::write(directory.join("config.toml"), config).unwrap();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_ayame"))
+            .current_dir(&directory)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let instance = Instance {
+            port,
+            directory,
+            child,
+        };
+
+        let deadline = Instant::now() + TIMEOUT;
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(Instant::now() < deadline, "{} did not start", name);
+            sleep(Duration::from_millis(50));
+        }
+
+        instance
+    }
+
+    fn start_linked(name: &str, port: u16, peer: &str, peer_port: u16) -> Instance {
+        let link = format!(
+            r#"[[link]]
+name = "{peer}"
+host = "127.0.0.1"
+port = {peer_port}
+password = "linkpass"
+"#,
+            peer = peer,
+            peer_port = peer_port,
+        );
+        Instance::start(name, port, "lookups = false\n", &link)
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+struct User {
+    nick: String,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl User {
+    fn connect(instance: &Instance, nick: &str) -> User {
+        let mut user = User::start_registration(instance, nick);
+        user.expect(|line| has_numeric(line, "001"));
+        user
+    }
+
+    fn start_registration(instance: &Instance, nick: &str) -> User {
+        let mut user = User::open(instance, nick);
+        user.send(&format!("NICK {}", nick));
+        user.send(&format!("USER {} 0 * :{}", nick, nick));
+        user
+    }
+
+    fn open(instance: &Instance, nick: &str) -> User {
+        let stream = TcpStream::connect(("127.0.0.1", instance.port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        User {
+            nick: nick.to_string(),
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .unwrap();
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                let line = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+                if line.starts_with("PING ") {
+                    self.send(&line.replacen("PING", "PONG", 1));
+                }
+                Some(line)
+            }
+            Err(error)
+                if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut =>
+            {
+                Some(String::new())
+            }
+            Err(error) => panic!("{}: read error: {}", self.nick, error),
+        }
+    }
+
+    /* NOTE(diath): Returns the first line that matches, panics if none arrives in time or the connection closes. */
+    fn expect<F: Fn(&str) -> bool>(&mut self, predicate: F) -> String {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            match self.read_line() {
+                Some(line) if !line.is_empty() && predicate(&line) => return line,
+                Some(_) => {}
+                None => panic!("{}: connection closed", self.nick),
+            }
+        }
+        panic!("{}: timed out waiting for a line", self.nick);
+    }
+
+    /* NOTE(diath): Returns every line until the connection closes. */
+    fn expect_closed(&mut self) -> Vec<String> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut lines = vec![];
+        while Instant::now() < deadline {
+            match self.read_line() {
+                Some(line) if !line.is_empty() => lines.push(line),
+                Some(_) => {}
+                None => return lines,
+            }
+        }
+        panic!(
+            "{}: timed out waiting for the connection to close",
+            self.nick
+        );
+    }
+}
+
+fn has_numeric(line: &str, numeric: &str) -> bool {
+    line.split(' ').nth(1) == Some(numeric)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn start_network() -> (Instance, Instance) {
+    let (port_a, port_b) = (free_port(), free_port());
+    let a = Instance::start_linked("a.test", port_a, "b.test", port_b);
+    let b = Instance::start_linked("b.test", port_b, "a.test", port_a);
+    (a, b)
+}
+
+fn connect_servers(oper: &mut User) {
+    oper.send("OPER admin secret");
+    oper.expect(|line| has_numeric(line, "381"));
+    oper.send("CONNECT b.test");
+    oper.expect(|line| line.contains("Link with b.test finished bursting"));
+}
+
+#[test]
+fn burst_and_collisions() {
+    let (a, b) = start_network();
+
+    let mut alice = User::connect(&a, "alice");
+    alice.send("JOIN #burst");
+    alice.expect(|line| has_numeric(line, "366"));
+    alice.send("TOPIC #burst :linked topic");
+    alice.expect(|line| line.contains("TOPIC #burst"));
+    alice.send("JOIN #older");
+    alice.expect(|line| has_numeric(line, "366"));
+    let mut older_dup = User::connect(&a, "dup");
+
+    /* NOTE(diath): Nick and channel timestamps have a resolution of one second. */
+    sleep(Duration::from_millis(1100));
+
+    let mut newer_dup = User::connect(&b, "dup");
+    let mut bob = User::connect(&b, "bob");
+    bob.send("JOIN #older");
+    bob.expect(|line| has_numeric(line, "366"));
+
+    connect_servers(&mut alice);
+
+    /* NOTE(diath): The newer user loses the nick on both sides, the older one keeps it. */
+    let lines = newer_dup.expect_closed();
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.starts_with("ERROR") && line.contains("Nick collision")),
+        "{:?}",
+        lines
+    );
+
+    bob.send("WHOIS dup");
+    let line = bob.expect(|line| has_numeric(line, "312") || has_numeric(line, "401"));
+    assert!(
+        has_numeric(&line, "312") && line.contains("a.test"),
+        "{}",
+        line
+    );
+
+    older_dup.send("PING :alive");
+    older_dup.expect(|line| line.contains("PONG") && line.contains("alive"));
+
+    /* NOTE(diath): The burst carries the topic and members of channels that only exist on one side. */
+    bob.send("JOIN #burst");
+    let line = bob.expect(|line| has_numeric(line, "332"));
+    assert!(line.ends_with(":linked topic"), "{}", line);
+    let line = bob.expect(|line| has_numeric(line, "353"));
+    assert!(line.contains("@alice"), "{}", line);
+
+    /* NOTE(diath): The older copy of #older wins, so bob loses the status it had on the newer copy. */
+    bob.send("NAMES #older");
+    let line = bob.expect(|line| has_numeric(line, "353"));
+    let names = line
+        .rsplit(':')
+        .next()
+        .unwrap()
+        .split(' ')
+        .collect::<Vec<&str>>();
+    assert!(names.contains(&"@alice"), "{}", line);
+    assert!(names.contains(&"bob"), "{}", line);
+}
+
+#[test]
+fn split_removes_remote_users() {
+    let (a, b) = start_network();
+
+    let mut alice = User::connect(&a, "alice");
+    alice.send("JOIN #split");
+    alice.expect(|line| has_numeric(line, "366"));
+    let mut bob = User::connect(&b, "bob");
+
+    connect_servers(&mut alice);
+
+    bob.send("JOIN #split");
+    alice.expect(|line| line.starts_with(":bob!") && line.contains("JOIN"));
+
+    alice.send("SQUIT b.test :test");
+    let line = alice.expect(|line| line.starts_with(":bob!") && line.contains("QUIT"));
+    assert!(line.contains("a.test b.test"), "{}", line);
+
+    alice.send("WHOIS bob");
+    alice.expect(|line| has_numeric(line, "401"));
+    alice.send("NAMES #split");
+    let line = alice.expect(|line| has_numeric(line, "353"));
+    assert!(!line.contains("bob"), "{}", line);
+
+    /* NOTE(diath): The other side drops our users as well and keeps running on its own. */
+    bob.send("WHOIS alice");
+    bob.expect(|line| has_numeric(line, "401"));
+}
+
+/* NOTE(diath): Answers every query with 127.0.0.2 while listed is set and with NXDOMAIN otherwise. */
+fn start_blocklist(listed: Arc<AtomicBool>) -> String {
+    let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    spawn(move || {
+        let mut buffer = [0; 512];
+        while let Ok((size, peer)) = socket.recv_from(&mut buffer) {
+            let mut packet = buffer[..size].to_vec();
+            packet[2] = 0x81;
+            if listed.load(Ordering::SeqCst) {
+                packet[3] = 0x80;
+                packet[7] = 1;
+                packet.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+                packet.extend_from_slice(&[0x00, 0x00, 0x0e, 0x10, 0x00, 0x04]);
+                packet.extend_from_slice(&[127, 0, 0, 2]);
+            } else {
+                packet[3] = 0x83;
+            }
+            let _ = socket.send_to(&packet, peer);
+        }
+    });
+    address
+}
+
+#[test]
+fn dnsbl_requires_login() {
+    let listed = Arc::new(AtomicBool::new(false));
+    let nameserver = start_blocklist(listed.clone());
+    let dns = format!(
+        "lookups = false\nnameserver = \"{}\"\ndnsbl_cache_duration = 0\n",
+        nameserver
+    );
+    let dnsbl = "[[dnsbl]]\nzone = \"dnsbl.test\"\naction = \"require-sasl\"\n";
+    let server = Instance::start("dnsbl.test", free_port(), &dns, dnsbl);
+
+    let mut alice = User::connect(&server, "alice");
+    alice.send("NS REGISTER alice secret");
+    alice.expect(|line| line.contains("Nick successfully registered"));
+
+    listed.store(true, Ordering::SeqCst);
+    let mut held = User::start_registration(&server, "held");
+    held.expect(|line| line.contains("NickServ IDENTIFY"));
+
+    /* NOTE(diath): Until the client logs in it can only identify, any other command or NickServ request is refused. */
+    held.send("JOIN #held");
+    held.expect(|line| has_numeric(line, "451"));
+    held.send("NS REGISTER other secret");
+    held.expect(|line| line.contains("You have to log in to an account first"));
+    held.send("PRIVMSG NickServ :IDENTIFY alice wrong");
+    held.expect(|line| line.contains("Wrong password"));
+
+    held.send("NS IDENTIFY alice secret");
+    let line = held.expect(|line| has_numeric(line, "001") || line.starts_with("ERROR"));
+    assert!(has_numeric(&line, "001"), "{}", line);
+}
+
+#[test]
+fn labeled_response() {
+    let server = Instance::start("label.test", free_port(), "lookups = false\n", "");
+
+    /* NOTE(diath): Without batch there would be no way to label a reply of several lines. */
+    let mut alice = User::open(&server, "alice");
+    alice.send("CAP REQ :labeled-response");
+    let line = alice.expect(|line| line.contains(" CAP "));
+    assert!(line.contains(" NAK :labeled-response"), "{}", line);
+    alice.send("CAP REQ :batch labeled-response");
+    let line = alice.expect(|line| line.contains(" CAP "));
+    assert!(line.contains(" ACK :batch labeled-response"), "{}", line);
+    alice.send("CAP REQ :-batch");
+    let line = alice.expect(|line| line.contains(" CAP "));
+    assert!(line.contains(" NAK :-batch"), "{}", line);
+
+    alice.send("NICK alice");
+    alice.send("USER alice 0 * :alice");
+    alice.send("CAP END");
+    alice.expect(|line| has_numeric(line, "001"));
+    alice.send("JOIN #labels");
+    alice.expect(|line| has_numeric(line, "366"));
+
+    alice.send("@label=none PONG :test");
+    let line = alice.expect(|line| line.starts_with("@label=none "));
+    assert!(line.ends_with(" ACK"), "{}", line);
+
+    alice.send("@label=single PING :test");
+    let line = alice.expect(|line| line.starts_with("@label=single "));
+    assert!(
+        line.contains(" PONG ") && line.ends_with(":test"),
+        "{}",
+        line
+    );
+
+    alice.send("@label=several NAMES #labels");
+    let line = alice.expect(|line| line.starts_with("@label=several "));
+    let id = line
+        .split(" BATCH +")
+        .nth(1)
+        .and_then(|rest| rest.strip_suffix(" labeled-response"))
+        .unwrap_or_else(|| panic!("{}", line))
+        .to_string();
+    let tag = format!("@batch={} ", id);
+    let mut numerics = vec![];
+    loop {
+        let line = alice.expect(|_| true);
+        match line.strip_prefix(&tag) {
+            Some(line) => numerics.push(line.split(' ').nth(1).unwrap_or("").to_string()),
+            None => {
+                assert!(line.ends_with(&format!(" BATCH -{}", id)), "{}", line);
+                break;
+            }
+        }
+    }
+    assert_eq!(numerics, vec!["353", "366"]);
+}